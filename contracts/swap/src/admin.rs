@@ -1,37 +1,77 @@
 use crate::{
+    buffer::{verify_route_buffers, MAX_DENOM_DECIMALS},
+    fees::{BPS_DENOMINATOR, MAX_PROTOCOL_FEE_BPS},
+    helpers::sum_coins_by_denom,
     msg::FeeRecipient,
-    state::{remove_swap_route, store_swap_route, CONFIG},
-    types::{Config, SwapRoute},
+    state::{
+        get_buffer_ledger, get_buffer_value, read_swap_route, remove_swap_route, store_swap_route, ACCRUED_PROTOCOL_FEES, BUFFER_CAPS, BUFFER_DENOMS,
+        BUFFER_KEEPERS, BUFFER_PNL_BASELINE, BUFFER_PRICE_SOURCES, CONFIG, EMERGENCY_MODE, EXEMPT_BUFFER_BUDGET, FEE_EXEMPT_ADDRESSES, FEE_TIERS,
+        SWAP_ROUTES, WITHDRAWAL_RESERVES,
+    },
+    types::{BufferCap, BufferDenom, BufferPnlBaseline, BufferPriceSource, BufferPricing, Config, FPCoin, FeeTier, FeeTierSchedule, SwapRoute},
     ContractError,
     ContractError::CustomError,
 };
-use cosmwasm_std::{ensure, ensure_eq, Addr, Attribute, BankMsg, Coin, Deps, DepsMut, Env, Event, Response, StdResult};
-use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQuerier, InjectiveQueryWrapper, MarketId};
+use cosmwasm_std::{ensure, ensure_eq, Addr, Attribute, BankMsg, Coin, Deps, DepsMut, Empty, Env, Event, Order, Response, StdResult, Storage};
+use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQuerier, InjectiveQueryWrapper, MarketId, SpotMarket};
+use injective_math::FPDecimal;
 use std::collections::HashSet;
 
 pub fn save_config(deps: DepsMut<InjectiveQueryWrapper>, env: Env, admin: Addr, fee_recipient: FeeRecipient) -> StdResult<()> {
-    let fee_recipient = match fee_recipient {
-        FeeRecipient::Address(addr) => addr,
-        FeeRecipient::SwapContract => env.contract.address,
+    let config = Config {
+        fee_recipient: resolve_contract_fee_recipient(fee_recipient, &env),
+        admin,
+        protocol_fee_bps: 0,
+        treasury: None,
+        max_integrator_fee_bps: 0,
+        burn_share_bps: 0,
+        depositor_fee_share_bps: 0,
+        depositor_rebate_share_bps: 0,
+        max_buffer_withdrawal_bps: 0,
+        accrue_protocol_fees: false,
     };
-    let config = Config { fee_recipient, admin };
     config.to_owned().validate()?;
 
     CONFIG.save(deps.storage, &config)
 }
 
+// fee recipients are stored with the contract as its address
+fn resolve_contract_fee_recipient(fee_recipient: FeeRecipient, env: &Env) -> FeeRecipient {
+    match fee_recipient {
+        FeeRecipient::SwapContract => FeeRecipient::Address(env.contract.address.to_owned()),
+        fee_recipient => fee_recipient,
+    }
+}
+
+fn fee_recipient_attr(fee_recipient: &FeeRecipient) -> String {
+    match fee_recipient {
+        FeeRecipient::Address(addr) => addr.to_string(),
+        FeeRecipient::SwapContract => "swap_contract".to_string(),
+        FeeRecipient::Sender => "sender".to_string(),
+    }
+}
+
 pub fn verify_sender_is_admin(deps: Deps<InjectiveQueryWrapper>, sender: &Addr) -> Result<(), ContractError> {
     let config = CONFIG.load(deps.storage)?;
     ensure_eq!(&config.admin, sender, ContractError::Unauthorized {});
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_config(
     deps: DepsMut<InjectiveQueryWrapper>,
     env: Env,
     sender: Addr,
     admin: Option<Addr>,
     fee_recipient: Option<FeeRecipient>,
+    protocol_fee_bps: Option<u16>,
+    treasury: Option<Addr>,
+    max_integrator_fee_bps: Option<u16>,
+    burn_share_bps: Option<u16>,
+    depositor_fee_share_bps: Option<u16>,
+    depositor_rebate_share_bps: Option<u16>,
+    max_buffer_withdrawal_bps: Option<u16>,
+    accrue_protocol_fees: Option<bool>,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     verify_sender_is_admin(deps.as_ref(), &sender)?;
     let mut config = CONFIG.load(deps.storage)?;
@@ -41,12 +81,42 @@ pub fn update_config(
         updated_config_event_attrs.push(Attribute::new("admin", admin.to_string()));
     }
     if let Some(fee_recipient) = fee_recipient {
-        config.fee_recipient = match fee_recipient {
-            FeeRecipient::Address(addr) => addr,
-            FeeRecipient::SwapContract => env.contract.address,
-        };
-        updated_config_event_attrs.push(Attribute::new("fee_recipient", config.fee_recipient.to_string()));
+        config.fee_recipient = resolve_contract_fee_recipient(fee_recipient, &env);
+        updated_config_event_attrs.push(Attribute::new("fee_recipient", fee_recipient_attr(&config.fee_recipient)));
+    }
+    if let Some(protocol_fee_bps) = protocol_fee_bps {
+        config.protocol_fee_bps = protocol_fee_bps;
+        updated_config_event_attrs.push(Attribute::new("protocol_fee_bps", protocol_fee_bps.to_string()));
+    }
+    if let Some(treasury) = treasury {
+        config.treasury = Some(treasury.clone());
+        updated_config_event_attrs.push(Attribute::new("treasury", treasury.to_string()));
     }
+    if let Some(max_integrator_fee_bps) = max_integrator_fee_bps {
+        config.max_integrator_fee_bps = max_integrator_fee_bps;
+        updated_config_event_attrs.push(Attribute::new("max_integrator_fee_bps", max_integrator_fee_bps.to_string()));
+    }
+    if let Some(burn_share_bps) = burn_share_bps {
+        config.burn_share_bps = burn_share_bps;
+        updated_config_event_attrs.push(Attribute::new("burn_share_bps", burn_share_bps.to_string()));
+    }
+    if let Some(depositor_fee_share_bps) = depositor_fee_share_bps {
+        config.depositor_fee_share_bps = depositor_fee_share_bps;
+        updated_config_event_attrs.push(Attribute::new("depositor_fee_share_bps", depositor_fee_share_bps.to_string()));
+    }
+    if let Some(depositor_rebate_share_bps) = depositor_rebate_share_bps {
+        config.depositor_rebate_share_bps = depositor_rebate_share_bps;
+        updated_config_event_attrs.push(Attribute::new("depositor_rebate_share_bps", depositor_rebate_share_bps.to_string()));
+    }
+    if let Some(max_buffer_withdrawal_bps) = max_buffer_withdrawal_bps {
+        config.max_buffer_withdrawal_bps = max_buffer_withdrawal_bps;
+        updated_config_event_attrs.push(Attribute::new("max_buffer_withdrawal_bps", max_buffer_withdrawal_bps.to_string()));
+    }
+    if let Some(accrue_protocol_fees) = accrue_protocol_fees {
+        config.accrue_protocol_fees = accrue_protocol_fees;
+        updated_config_event_attrs.push(Attribute::new("accrue_protocol_fees", accrue_protocol_fees.to_string()));
+    }
+    config.to_owned().validate()?;
     CONFIG.save(deps.storage, &config)?;
 
     Ok(Response::new()
@@ -54,24 +124,408 @@ pub fn update_config(
         .add_event(Event::new("config_updated").add_attributes(updated_config_event_attrs)))
 }
 
+// Replaces the protocol fee discount schedule, an empty list of tiers disables discounts
+pub fn set_fee_tiers(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    reference_denom: String,
+    tiers: Vec<FeeTier>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    if tiers.is_empty() {
+        FEE_TIERS.remove(deps.storage);
+        return Ok(Response::new().add_attribute("method", "set_fee_tiers"));
+    }
+
+    let schedule = FeeTierSchedule { reference_denom, tiers };
+    schedule.validate()?;
+    FEE_TIERS.save(deps.storage, &schedule)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "set_fee_tiers")
+        .add_attribute("reference_denom", schedule.reference_denom)
+        .add_attribute("tiers_count", schedule.tiers.len().to_string()))
+}
+
+pub fn update_fee_exempt_addresses(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    add: Vec<Addr>,
+    remove: Vec<Addr>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    for address in add.iter() {
+        FEE_EXEMPT_ADDRESSES.save(deps.storage, address, &Empty {})?;
+    }
+    for address in remove.iter() {
+        FEE_EXEMPT_ADDRESSES.remove(deps.storage, address);
+    }
+
+    let join = |addresses: &[Addr]| addresses.iter().map(Addr::as_str).collect::<Vec<_>>().join(",");
+
+    Ok(Response::new()
+        .add_attribute("method", "update_fee_exempt_addresses")
+        .add_attribute("added", join(&add))
+        .add_attribute("removed", join(&remove)))
+}
+
+pub fn update_buffer_keepers(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    add: Vec<Addr>,
+    remove: Vec<Addr>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    for address in add.iter() {
+        BUFFER_KEEPERS.save(deps.storage, address, &Empty {})?;
+    }
+    for address in remove.iter() {
+        BUFFER_KEEPERS.remove(deps.storage, address);
+    }
+
+    let join = |addresses: &[Addr]| addresses.iter().map(Addr::as_str).collect::<Vec<_>>().join(",");
+
+    Ok(Response::new()
+        .add_attribute("method", "update_buffer_keepers")
+        .add_attribute("added", join(&add))
+        .add_attribute("removed", join(&remove)))
+}
+
+// Replaces what is left of the buffer budget of fee-exempt addresses, their swaps draw it down until it is set again.
+// Denoms that are not listed cannot be drawn from the buffer.
+pub fn set_exempt_buffer_budget(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    budget: Vec<FPCoin>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    ensure!(
+        budget.iter().all(|coin| !coin.amount.is_negative()),
+        CustomError {
+            val: "Buffer budget cannot be negative".to_string()
+        }
+    );
+
+    let previous_denoms = EXEMPT_BUFFER_BUDGET
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<String>>>()?;
+    for denom in previous_denoms {
+        EXEMPT_BUFFER_BUDGET.remove(deps.storage, denom);
+    }
+    for coin in budget.iter() {
+        EXEMPT_BUFFER_BUDGET.save(deps.storage, coin.denom.to_owned(), &coin.amount)?;
+    }
+
+    Ok(Response::new().add_attribute("method", "set_exempt_buffer_budget"))
+}
+
+// Replaces the quote denoms the contract keeps buffers of
+pub fn set_buffer_denoms(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    buffers: Vec<BufferDenom>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    ensure!(
+        buffers.iter().all(|buffer| [
+            Some(buffer.min_balance),
+            buffer.target_balance,
+            buffer.low_balance,
+            buffer.critical_balance
+        ]
+        .into_iter()
+        .flatten()
+        .all(|balance| !balance.is_negative())),
+        CustomError {
+            val: "Buffer balances cannot be negative".to_string()
+        }
+    );
+    ensure!(
+        buffers.iter().all(|buffer| buffer.critical_max_use_bps <= BPS_DENOMINATOR),
+        CustomError {
+            val: format!("Critical buffer use cannot be higher than {BPS_DENOMINATOR} bps")
+        }
+    );
+
+    BUFFER_DENOMS.clear(deps.storage);
+    for buffer in buffers.iter() {
+        BUFFER_DENOMS.save(deps.storage, buffer.denom.to_owned(), buffer)?;
+    }
+
+    // stored routes keep working only while every quote denom they trade through still has a buffer
+    let routes = SWAP_ROUTES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, route)| route))
+        .collect::<StdResult<Vec<_>>>()?;
+    for route in routes.iter() {
+        verify_route_buffers(deps.as_ref(), route).map_err(|err| match err {
+            ContractError::BufferNotConfigured { denom } => CustomError {
+                val: format!(
+                    "Route {} -> {} still needs a buffer of {denom}, delete it or keep the buffer",
+                    route.source_denom, route.target_denom
+                ),
+            },
+            err => err,
+        })?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "set_buffer_denoms")
+        .add_attribute("denoms", buffers.iter().map(|buffer| buffer.denom.as_str()).collect::<Vec<_>>().join(",")))
+}
+
+// Replaces the caps of buffer use by swaps, denoms that are not listed are not capped
+pub fn set_buffer_caps(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    caps: Vec<BufferCap>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    ensure!(
+        caps.iter()
+            .flat_map(|cap| [cap.per_swap, cap.per_block])
+            .flatten()
+            .all(|limit| !limit.is_negative()),
+        CustomError {
+            val: "Buffer caps cannot be negative".to_string()
+        }
+    );
+
+    BUFFER_CAPS.clear(deps.storage);
+    for cap in caps.iter() {
+        BUFFER_CAPS.save(deps.storage, cap.denom.to_owned(), cap)?;
+    }
+
+    Ok(Response::new().add_attribute("method", "set_buffer_caps"))
+}
+
+// Replaces the price sources buffer profit and loss is valued with, denoms that are not listed are reported without value
+pub fn set_buffer_price_sources(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    sources: Vec<BufferPricing>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    ensure!(
+        sources.iter().all(|pricing| match &pricing.source {
+            BufferPriceSource::Oracle { .. } => true,
+            BufferPriceSource::Route { quantity, .. } => *quantity > FPDecimal::ZERO,
+        }),
+        CustomError {
+            val: "Quantity priced through a route must be positive".to_string()
+        }
+    );
+    ensure!(
+        sources.iter().all(|pricing| pricing.decimals <= MAX_DENOM_DECIMALS
+            && match &pricing.source {
+                BufferPriceSource::Oracle { .. } => true,
+                BufferPriceSource::Route { target_decimals, .. } => *target_decimals <= MAX_DENOM_DECIMALS,
+            }),
+        CustomError {
+            val: format!("Denom decimals cannot be above {MAX_DENOM_DECIMALS}")
+        }
+    );
+
+    BUFFER_PRICE_SOURCES.clear(deps.storage);
+    for pricing in sources.iter() {
+        BUFFER_PRICE_SOURCES.save(deps.storage, pricing.denom.to_owned(), pricing)?;
+    }
+
+    Ok(Response::new().add_attribute("method", "set_buffer_price_sources"))
+}
+
+// Starts counting buffer profit and loss from the current ledger
+pub fn reset_buffer_pnl_baseline(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: &Addr,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    let baseline = BufferPnlBaseline {
+        time: env.block.time,
+        ledgers: get_buffer_ledger(deps.storage)?,
+    };
+    BUFFER_PNL_BASELINE.save(deps.storage, &baseline)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "reset_buffer_pnl_baseline")
+        .add_attribute("time", env.block.time.seconds().to_string()))
+}
+
+// Replaces the balances withdrawals of the admin cannot go below, denoms that are not listed have no reserve
+pub fn set_withdrawal_reserves(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    reserves: Vec<FPCoin>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    ensure!(
+        reserves.iter().all(|coin| !coin.amount.is_negative()),
+        CustomError {
+            val: "Withdrawal reserve cannot be negative".to_string()
+        }
+    );
+
+    WITHDRAWAL_RESERVES.clear(deps.storage);
+    for coin in reserves.iter() {
+        WITHDRAWAL_RESERVES.save(deps.storage, coin.denom.to_owned(), &coin.amount)?;
+    }
+
+    Ok(Response::new().add_attribute("method", "set_withdrawal_reserves"))
+}
+
+pub fn set_emergency_mode(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    sender: &Addr,
+    enabled: bool,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    EMERGENCY_MODE.save(deps.storage, &enabled)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "set_emergency_mode")
+        .add_event(Event::new("emergency_mode_updated").add_attribute("enabled", enabled.to_string())))
+}
+
+// Balance the reserve of a denom leaves for withdrawals, all of it in emergency mode
+fn get_withdrawable_balance(deps: Deps<InjectiveQueryWrapper>, denom: &str, balance: FPDecimal) -> StdResult<FPDecimal> {
+    if EMERGENCY_MODE.may_load(deps.storage)?.unwrap_or_default() {
+        return Ok(balance);
+    }
+
+    let reserve = WITHDRAWAL_RESERVES.may_load(deps.storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO);
+    Ok((balance - reserve).max(FPDecimal::ZERO))
+}
+
+// Funds taken out of the contract by the admin, including fee conversions, cannot leave a balance below the reserve
+pub fn verify_withdrawal_reserve(deps: Deps<InjectiveQueryWrapper>, denom: &str, balance: FPDecimal, amount: FPDecimal) -> Result<(), ContractError> {
+    if amount > get_withdrawable_balance(deps, denom, balance)? {
+        return Err(ContractError::WithdrawalBelowReserve {
+            denom: denom.to_string(),
+            balance: balance - amount,
+            reserve: WITHDRAWAL_RESERVES.may_load(deps.storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO),
+        });
+    }
+
+    Ok(())
+}
+
+// Balance of the contract not owed to buffer depositors or waiting as accrued protocol fees, the only part the admin may take
+fn get_unaccounted_balance(storage: &dyn Storage, denom: &str, balance: FPDecimal) -> StdResult<FPDecimal> {
+    let accounted = get_buffer_value(storage, denom)? + ACCRUED_PROTOCOL_FEES.may_load(storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO);
+
+    Ok((balance - accounted).max(FPDecimal::ZERO))
+}
+
+pub fn withdrawal_event(coin: &Coin, balance_before: FPDecimal, target_address: &Addr) -> Event {
+    Event::new("withdrawal")
+        .add_attribute("denom", coin.denom.to_owned())
+        .add_attribute("amount", coin.amount.to_string())
+        .add_attribute("balance_before", balance_before.to_string())
+        .add_attribute("balance_after", (balance_before - FPDecimal::from(coin.amount)).to_string())
+        .add_attribute("target_address", target_address.to_string())
+}
+
 pub fn withdraw_support_funds(
     deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
     sender: Addr,
     coins: Vec<Coin>,
     target_address: Addr,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     verify_sender_is_admin(deps.as_ref(), &sender)?;
+
+    // amounts of a denom requested more than once are checked together
+    let coins = sum_coins_by_denom(&coins);
+    let mut events = Vec::with_capacity(coins.len());
+    for coin in coins.iter() {
+        let balance: FPDecimal = deps.querier.query_balance(&env.contract.address, &coin.denom)?.amount.into();
+        let amount: FPDecimal = coin.amount.into();
+        // buffer funds of the admin are withdrawn through their shares like those of any other depositor
+        let unaccounted = get_unaccounted_balance(deps.storage, &coin.denom, balance)?;
+        ensure!(
+            amount <= unaccounted,
+            CustomError {
+                val: format!(
+                    "Cannot withdraw {coin}, only {unaccounted}{} is not owed to buffer depositors or accrued protocol fees",
+                    coin.denom
+                )
+            }
+        );
+
+        verify_withdrawal_reserve(deps.as_ref(), &coin.denom, balance, amount)?;
+
+        events.push(withdrawal_event(coin, balance, &target_address));
+    }
     let send_message = BankMsg::Send {
         to_address: target_address.to_string(),
         amount: coins,
     };
     let response = Response::new()
         .add_message(send_message)
+        .add_events(events)
         .add_attribute("method", "withdraw_support_funds")
         .add_attribute("target_address", target_address.to_string());
     Ok(response)
 }
 
+// Withdraws the funds the contract holds beyond what the buffer ledger and accrued protocol fees account for, so neither
+// the buffer of depositors nor the treasury share is touched
+pub fn withdraw_all_excess_funds(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: Addr,
+    target_address: Addr,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), &sender)?;
+
+    let mut coins = vec![];
+    let mut events = vec![];
+    for balance in deps.querier.query_all_balances(env.contract.address)? {
+        let balance_before: FPDecimal = balance.amount.into();
+        let excess = get_unaccounted_balance(deps.storage, &balance.denom, balance_before)?
+            .min(get_withdrawable_balance(deps.as_ref(), &balance.denom, balance_before)?)
+            .int();
+        if excess <= FPDecimal::ZERO {
+            continue;
+        }
+
+        let coin: Coin = FPCoin {
+            amount: excess,
+            denom: balance.denom,
+        }
+        .into();
+        events.push(withdrawal_event(&coin, balance_before, &target_address));
+        coins.push(coin);
+    }
+
+    ensure!(
+        !coins.is_empty(),
+        CustomError {
+            val: "No excess funds to withdraw".to_string()
+        }
+    );
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: target_address.to_string(),
+            amount: coins,
+        })
+        .add_events(events)
+        .add_attribute("method", "withdraw_all_excess_funds")
+        .add_attribute("target_address", target_address.to_string()))
+}
+
 pub fn set_route(
     deps: DepsMut<InjectiveQueryWrapper>,
     sender: &Addr,
@@ -81,6 +535,84 @@ pub fn set_route(
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     verify_sender_is_admin(deps.as_ref(), sender)?;
 
+    let mut route = validate_route(deps.as_ref(), source_denom, target_denom, route)?;
+    verify_route_buffers(deps.as_ref(), &route)?;
+
+    // changing the steps keeps fee overrides of the route
+    if let Ok(existing_route) = read_swap_route(deps.storage, &route.source_denom, &route.target_denom) {
+        route.fee_recipient = existing_route.fee_recipient;
+        route.protocol_fee_bps = existing_route.protocol_fee_bps;
+    }
+    store_swap_route(deps.storage, &route)?;
+
+    Ok(Response::new().add_attribute("method", "set_route"))
+}
+
+// Replaces fee overrides of a stored route, None falls back to the config
+pub fn set_route_fee_overrides(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: &Addr,
+    source_denom: String,
+    target_denom: String,
+    fee_recipient: Option<FeeRecipient>,
+    protocol_fee_bps: Option<u16>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    verify_sender_is_admin(deps.as_ref(), sender)?;
+
+    let mut route = read_swap_route(deps.storage, &source_denom, &target_denom)?;
+
+    if let Some(protocol_fee_bps) = protocol_fee_bps {
+        ensure!(
+            protocol_fee_bps <= MAX_PROTOCOL_FEE_BPS,
+            CustomError {
+                val: format!("Protocol fee cannot be higher than {MAX_PROTOCOL_FEE_BPS} bps")
+            }
+        );
+        ensure!(
+            protocol_fee_bps == 0 || CONFIG.load(deps.storage)?.treasury.is_some(),
+            CustomError {
+                val: "Treasury must be set to charge protocol fee".to_string()
+            }
+        );
+    }
+
+    // contract address is resolved now, like in the config
+    route.fee_recipient = fee_recipient.map(|fee_recipient| resolve_contract_fee_recipient(fee_recipient, &env));
+    route.protocol_fee_bps = protocol_fee_bps;
+    store_swap_route(deps.storage, &route)?;
+
+    let mut response = Response::new().add_attribute("method", "set_route_fee_overrides");
+    if let Some(fee_recipient) = &route.fee_recipient {
+        response = response.add_attribute("fee_recipient", fee_recipient_attr(fee_recipient));
+    }
+    if let Some(protocol_fee_bps) = route.protocol_fee_bps {
+        response = response.add_attribute("protocol_fee_bps", protocol_fee_bps.to_string());
+    }
+
+    Ok(response)
+}
+
+pub fn validate_route(
+    deps: Deps<InjectiveQueryWrapper>,
+    source_denom: String,
+    target_denom: String,
+    route: Vec<MarketId>,
+) -> Result<SwapRoute, ContractError> {
+    let querier = InjectiveQuerier::new(&deps.querier);
+
+    validate_route_with_markets(source_denom, target_denom, route, |market_id| {
+        Ok(querier.query_spot_market(market_id)?.market)
+    })
+}
+
+// Applies the same validation as validate_route, but reads markets using the passed function instead of the live exchange
+pub fn validate_route_with_markets(
+    source_denom: String,
+    target_denom: String,
+    route: Vec<MarketId>,
+    find_market: impl Fn(&MarketId) -> StdResult<Option<SpotMarket>>,
+) -> Result<SwapRoute, ContractError> {
     if source_denom == target_denom {
         return Err(ContractError::CustomError {
             val: "Cannot set a route with the same denom being source and target".to_string(),
@@ -103,23 +635,23 @@ pub fn set_route(
         steps: route,
         source_denom,
         target_denom,
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
-    verify_route_exists(deps.as_ref(), &route)?;
-    store_swap_route(deps.storage, &route)?;
+    verify_route_exists(&route, find_market)?;
 
-    Ok(Response::new().add_attribute("method", "set_route"))
+    Ok(route)
 }
 
-fn verify_route_exists(deps: Deps<InjectiveQueryWrapper>, route: &SwapRoute) -> Result<(), ContractError> {
+fn verify_route_exists(route: &SwapRoute, find_market: impl Fn(&MarketId) -> StdResult<Option<SpotMarket>>) -> Result<(), ContractError> {
     struct MarketDenom {
         quote_denom: String,
         base_denom: String,
     }
     let mut denoms: Vec<MarketDenom> = Vec::new();
-    let querier = InjectiveQuerier::new(&deps.querier);
 
     for market_id in route.steps.iter() {
-        let market = querier.query_spot_market(market_id)?.market.ok_or(CustomError {
+        let market = find_market(market_id)?.ok_or(CustomError {
             val: format!("Market {} not found", market_id.as_str()).to_string(),
         })?;
 
@@ -0,0 +1,510 @@
+use crate::{
+    admin::withdrawal_event,
+    fees::bps_to_dec,
+    helpers::Scaled,
+    queries::{estimate_swap_result, SwapQuantity},
+    state::{
+        add_buffer_shares, get_accrued_protocol_fees, get_buffer_ledger, get_buffer_value, record_buffer_deposit, record_buffer_fees,
+        record_buffer_withdrawal, remove_buffer_shares, take_accrued_protocol_fee, BUFFER_DENOMS, BUFFER_PNL_BASELINE, BUFFER_PRICE_SOURCES,
+        BUFFER_SHARES, BUFFER_WITHDRAWALS, CONFIG, TOTAL_BUFFER_SHARES,
+    },
+    types::{BufferPnl, BufferPnlResponse, BufferPriceSource, BufferPricing, BufferStatus, Config, FPCoin, OutputFeeRates, SwapRoute},
+    ContractError,
+    ContractError::CustomError,
+};
+use cosmwasm_std::{ensure, Addr, BankMsg, Coin, Deps, DepsMut, Env, Event, Order, Response, StdError, StdResult, Storage};
+use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQuerier, InjectiveQueryWrapper, MarketId, SpotMarket};
+use injective_math::FPDecimal;
+
+// Voids the shares of a buffer that has lost all its value, so the next deposit starts it afresh. A deficit in the ledger
+// was covered by funds outside of it and is counted as principal of the operators.
+fn reset_worthless_buffer(storage: &mut dyn Storage, denom: &str, value: FPDecimal) -> StdResult<()> {
+    let holders = BUFFER_SHARES
+        .prefix(denom.to_string())
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for holder in holders {
+        BUFFER_SHARES.remove(storage, (denom.to_string(), &holder));
+    }
+    TOTAL_BUFFER_SHARES.remove(storage, denom.to_string());
+
+    if value.is_negative() {
+        record_buffer_deposit(storage, denom, -value)?;
+    }
+
+    Ok(())
+}
+
+// Mints shares of the buffer of each denom sent along, priced by the value of the buffer before the deposit
+pub fn deposit_buffer(deps: DepsMut<InjectiveQueryWrapper>, sender: &Addr, funds: Vec<Coin>) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    ensure!(
+        !funds.is_empty() && funds.iter().all(|coin| !coin.amount.is_zero()),
+        CustomError {
+            val: "No funds to deposit".to_string()
+        }
+    );
+
+    let mut minted_shares = Vec::with_capacity(funds.len());
+
+    for coin in funds.iter() {
+        ensure!(
+            BUFFER_DENOMS.has(deps.storage, coin.denom.to_owned()),
+            ContractError::BufferNotConfigured {
+                denom: coin.denom.to_owned()
+            }
+        );
+
+        let amount: FPDecimal = coin.amount.into();
+        let mut value = get_buffer_value(deps.storage, &coin.denom)?;
+        let mut total_shares = TOTAL_BUFFER_SHARES
+            .may_load(deps.storage, coin.denom.to_owned())?
+            .unwrap_or(FPDecimal::ZERO);
+
+        if value.is_negative() || (value.is_zero() && !total_shares.is_zero()) {
+            reset_worthless_buffer(deps.storage, &coin.denom, value)?;
+            value = FPDecimal::ZERO;
+            total_shares = FPDecimal::ZERO;
+        }
+
+        let shares = if total_shares.is_zero() { amount } else { amount * total_shares / value };
+        add_buffer_shares(deps.storage, sender, &coin.denom, shares)?;
+        record_buffer_deposit(deps.storage, &coin.denom, amount)?;
+
+        minted_shares.push(format!("{}{}", shares, coin.denom));
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "deposit_buffer")
+        .add_attribute("depositor", sender.to_string())
+        .add_attribute("shares", minted_shares.join(",")))
+}
+
+// Withdrawals of depositors in one block cannot take more than the configured share of the buffer it started with
+fn check_buffer_withdrawal_limit(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    denom: &str,
+    value: FPDecimal,
+    amount: FPDecimal,
+) -> StdResult<()> {
+    if config.max_buffer_withdrawal_bps == 0 {
+        return Ok(());
+    }
+
+    let withdrawn_in_block = match BUFFER_WITHDRAWALS.may_load(storage, denom.to_string())? {
+        Some((height, withdrawn)) if height == env.block.height => withdrawn,
+        _ => FPDecimal::ZERO,
+    };
+    let limit = ((value + withdrawn_in_block) * bps_to_dec(config.max_buffer_withdrawal_bps)).int();
+
+    if withdrawn_in_block + amount > limit {
+        return Err(StdError::generic_err(format!(
+            "Withdrawals of {denom} buffer are limited to {limit} per block, {withdrawn_in_block} already withdrawn"
+        )));
+    }
+
+    BUFFER_WITHDRAWALS.save(storage, denom.to_string(), &(env.block.height, withdrawn_in_block + amount))
+}
+
+pub fn withdraw_buffer(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: &Addr,
+    denom: String,
+    shares: FPDecimal,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    ensure!(
+        shares > FPDecimal::ZERO,
+        CustomError {
+            val: "Shares to withdraw must be positive".to_string()
+        }
+    );
+
+    let owned_shares = BUFFER_SHARES
+        .may_load(deps.storage, (denom.to_owned(), sender))?
+        .unwrap_or(FPDecimal::ZERO);
+    ensure!(
+        shares <= owned_shares,
+        CustomError {
+            val: format!("Cannot withdraw {shares} shares of {denom} buffer, only {owned_shares} owned")
+        }
+    );
+
+    let total_shares = TOTAL_BUFFER_SHARES.load(deps.storage, denom.to_owned())?;
+    let value = get_buffer_value(deps.storage, &denom)?;
+    let amount = (shares * value / total_shares).int();
+    ensure!(
+        amount > FPDecimal::ZERO,
+        CustomError {
+            val: format!("Shares of {denom} buffer are not worth anything to withdraw")
+        }
+    );
+
+    let config = CONFIG.load(deps.storage)?;
+    check_buffer_withdrawal_limit(deps.storage, &env, &config, &denom, value, amount)?;
+
+    remove_buffer_shares(deps.storage, sender, &denom, shares)?;
+    record_buffer_withdrawal(deps.storage, &denom, amount)?;
+
+    let withdrawn = Coin::new(amount, denom.to_owned());
+    let balance_before: FPDecimal = deps.querier.query_balance(&env.contract.address, &denom)?.amount.into();
+    let send_message = BankMsg::Send {
+        to_address: sender.to_string(),
+        amount: vec![withdrawn.to_owned()],
+    };
+
+    Ok(Response::new()
+        .add_message(send_message)
+        .add_event(withdrawal_event(&withdrawn, balance_before, sender))
+        .add_attribute("method", "withdraw_buffer")
+        .add_attribute("depositor", sender.to_string())
+        .add_attribute("shares", shares.to_string())
+        .add_attribute("amount", format!("{amount}{denom}")))
+}
+
+// Keeps as much of the protocol fee as the buffer of its denom lacks to reach its target balance, returns the amount kept
+pub fn top_up_buffer(storage: &mut dyn Storage, denom: &str, fee: FPDecimal) -> StdResult<FPDecimal> {
+    let Some(target_balance) = BUFFER_DENOMS
+        .may_load(storage, denom.to_string())?
+        .and_then(|buffer| buffer.target_balance)
+    else {
+        return Ok(FPDecimal::ZERO);
+    };
+
+    // buffer is topped up by whole units, so that what is left of whole fees can be sent on without dropping a fraction
+    let missing = (target_balance - get_buffer_value(storage, denom)?).max(FPDecimal::ZERO);
+    let missing_units = if missing == missing.int() {
+        missing
+    } else {
+        missing.int() + FPDecimal::ONE
+    };
+    let top_up = missing_units.min(fee);
+    record_buffer_fees(storage, denom, top_up)?;
+
+    Ok(top_up)
+}
+
+// Sweeps accrued protocol fees into buffers up to their targets, what is left goes to the treasury
+pub fn top_up_buffers(deps: DepsMut<InjectiveQueryWrapper>, sender: &Addr) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let accrued_fees = get_accrued_protocol_fees(deps.storage)?;
+    ensure!(
+        !accrued_fees.is_empty(),
+        CustomError {
+            val: "No accrued protocol fees to top up buffers with".to_string()
+        }
+    );
+
+    let mut top_ups = Vec::with_capacity(accrued_fees.len());
+    let mut treasury_coins = Vec::with_capacity(accrued_fees.len());
+
+    for fee in accrued_fees {
+        take_accrued_protocol_fee(deps.storage, &fee.denom, fee.amount)?;
+        let top_up = top_up_buffer(deps.storage, &fee.denom, fee.amount)?;
+        if top_up > FPDecimal::ZERO {
+            top_ups.push(format!("{}{}", top_up, fee.denom));
+        }
+
+        let excess = fee.amount - top_up;
+        if excess > FPDecimal::ZERO {
+            treasury_coins.push(
+                FPCoin {
+                    amount: excess,
+                    denom: fee.denom,
+                }
+                .into(),
+            );
+        }
+    }
+
+    let mut response = Response::new()
+        .add_attribute("method", "top_up_buffers")
+        .add_attribute("keeper", sender.to_string())
+        .add_attribute("top_ups", top_ups.join(","));
+
+    if !treasury_coins.is_empty() {
+        let treasury = CONFIG
+            .load(deps.storage)?
+            .treasury
+            .ok_or_else(|| StdError::generic_err("Treasury must be set to receive protocol fees above buffer targets"))?;
+        response = response.add_message(BankMsg::Send {
+            to_address: treasury.to_string(),
+            amount: treasury_coins,
+        });
+    }
+
+    Ok(response)
+}
+
+fn query_route_markets(deps: Deps<InjectiveQueryWrapper>, steps: &[MarketId]) -> StdResult<Vec<SpotMarket>> {
+    let querier = InjectiveQuerier::new(&deps.querier);
+
+    steps
+        .iter()
+        .map(|market_id| {
+            querier
+                .query_spot_market(market_id)?
+                .market
+                .ok_or_else(|| StdError::generic_err(format!("Market {} not found", market_id.as_str())))
+        })
+        .collect()
+}
+
+// Once buffers are configured, every quote denom of a route needs one
+pub fn verify_route_buffers(deps: Deps<InjectiveQueryWrapper>, route: &SwapRoute) -> Result<(), ContractError> {
+    if BUFFER_DENOMS.is_empty(deps.storage) {
+        return Ok(());
+    }
+
+    for market in query_route_markets(deps, &route.steps)? {
+        ensure!(
+            BUFFER_DENOMS.has(deps.storage, market.quote_denom.to_owned()),
+            ContractError::BufferNotConfigured { denom: market.quote_denom }
+        );
+    }
+
+    Ok(())
+}
+
+// Buys lock funds of their quote denom, so a swap fails before placing any order if one of these buffers is below its
+// minimum. Funds of the swapper are not counted.
+pub fn verify_swap_buffers(deps: Deps<InjectiveQueryWrapper>, env: &Env, steps: &[MarketId], input_funds: &Coin) -> Result<(), ContractError> {
+    if BUFFER_DENOMS.is_empty(deps.storage) {
+        return Ok(());
+    }
+
+    let mut current_denom = input_funds.denom.to_owned();
+    for market in query_route_markets(deps, steps)? {
+        if market.quote_denom != current_denom {
+            current_denom = market.quote_denom;
+            continue;
+        }
+
+        let buffer = BUFFER_DENOMS
+            .may_load(deps.storage, market.quote_denom.to_owned())?
+            .ok_or_else(|| ContractError::BufferNotConfigured {
+                denom: market.quote_denom.to_owned(),
+            })?;
+
+        let balance = query_buffer_balance(deps, env, &buffer.denom, &[input_funds.to_owned().into()])?;
+
+        ensure!(
+            balance >= buffer.min_balance,
+            ContractError::BufferUnderfunded {
+                denom: buffer.denom,
+                balance,
+                min_balance: buffer.min_balance,
+            }
+        );
+
+        current_denom = market.base_denom;
+    }
+
+    Ok(())
+}
+
+// A rebalance swaps a coin of the buffer itself instead of funds a swapper brought in, so the buffer left without it must
+// still keep its min balance, whether the route sells or buys with the coin
+pub fn verify_rebalance_source_buffer(deps: Deps<InjectiveQueryWrapper>, env: &Env, coin: &Coin) -> Result<(), ContractError> {
+    let buffer = BUFFER_DENOMS
+        .may_load(deps.storage, coin.denom.to_owned())?
+        .ok_or_else(|| ContractError::BufferNotConfigured {
+            denom: coin.denom.to_owned(),
+        })?;
+    let balance = query_buffer_balance(deps, env, &buffer.denom, &[coin.to_owned().into()])?;
+
+    ensure!(
+        balance >= buffer.min_balance,
+        ContractError::BufferUnderfunded {
+            denom: buffer.denom,
+            balance,
+            min_balance: buffer.min_balance,
+        }
+    );
+
+    Ok(())
+}
+
+// Balance of the buffer of a denom, without the funds of the swap in progress
+fn query_buffer_balance(deps: Deps<InjectiveQueryWrapper>, env: &Env, denom: &str, swap_funds: &[FPCoin]) -> StdResult<FPDecimal> {
+    let balance: FPDecimal = deps.querier.query_balance(&env.contract.address, denom)?.amount.into();
+    let swap_amount = swap_funds
+        .iter()
+        .filter(|coin| coin.denom == denom)
+        .fold(FPDecimal::ZERO, |total, coin| total + coin.amount);
+
+    Ok(balance - swap_amount)
+}
+
+// Balances of the buffers of quote denoms of a route before a swap of input_funds over it
+pub fn get_route_buffer_balances(deps: Deps<InjectiveQueryWrapper>, env: &Env, steps: &[MarketId], input_funds: &Coin) -> StdResult<Vec<FPCoin>> {
+    if BUFFER_DENOMS.is_empty(deps.storage) {
+        return Ok(vec![]);
+    }
+
+    let mut quote_denoms: Vec<String> = query_route_markets(deps, steps)?.into_iter().map(|market| market.quote_denom).collect();
+    quote_denoms.sort();
+    quote_denoms.dedup();
+
+    let mut balances = vec![];
+    for denom in quote_denoms {
+        if BUFFER_DENOMS.has(deps.storage, denom.to_owned()) {
+            balances.push(FPCoin {
+                amount: query_buffer_balance(deps, env, &denom, &[input_funds.to_owned().into()])?,
+                denom,
+            });
+        }
+    }
+
+    Ok(balances)
+}
+
+// buffer_low events for the buffers a swap took from at or above their low balance to below it, so a buffer staying low
+// is reported once instead of after every swap
+pub fn get_buffer_low_events(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    balances_before: &[FPCoin],
+    swap_funds: &[FPCoin],
+) -> StdResult<Vec<Event>> {
+    let mut events = vec![];
+    for balance_before in balances_before {
+        let Some(buffer) = BUFFER_DENOMS.may_load(deps.storage, balance_before.denom.to_owned())? else {
+            continue;
+        };
+
+        let balance = query_buffer_balance(deps, env, &buffer.denom, swap_funds)?;
+        if !buffer.is_low(balance_before.amount) && buffer.is_low(balance) {
+            events.push(
+                Event::new("buffer_low")
+                    .add_attribute("denom", buffer.denom.to_owned())
+                    .add_attribute("balance_before", balance_before.amount.to_string())
+                    .add_attribute("balance", balance.to_string())
+                    .add_attribute("low_balance", buffer.low_balance.unwrap_or(FPDecimal::ZERO).to_string())
+                    .add_attribute("is_critical", buffer.is_critical(balance).to_string()),
+            );
+        }
+    }
+
+    Ok(events)
+}
+
+pub fn get_buffer_statuses(deps: Deps<InjectiveQueryWrapper>, env: &Env) -> StdResult<Vec<BufferStatus>> {
+    BUFFER_DENOMS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (_, buffer) = item?;
+            let balance: FPDecimal = deps.querier.query_balance(&env.contract.address, &buffer.denom)?.amount.into();
+
+            Ok(BufferStatus {
+                ledger_balance: get_buffer_value(deps.storage, &buffer.denom)?,
+                is_underfunded: balance < buffer.min_balance,
+                is_low: buffer.is_low(balance),
+                is_critical: buffer.is_critical(balance),
+                denom: buffer.denom,
+                min_balance: buffer.min_balance,
+                balance,
+            })
+        })
+        .collect()
+}
+
+// most decimals of a denom that buffer prices can be scaled with
+pub const MAX_DENOM_DECIMALS: u8 = 18;
+
+// Chain units in a whole unit of a denom with the decimals
+pub fn decimals_factor(decimals: u8) -> FPDecimal {
+    FPDecimal::ONE.scaled(i32::from(decimals))
+}
+
+fn query_buffer_price(deps: Deps<InjectiveQueryWrapper>, env: &Env, pricing: &BufferPricing) -> StdResult<FPDecimal> {
+    let denom = &pricing.denom;
+    match &pricing.source {
+        BufferPriceSource::Oracle { oracle_type, base, quote } => InjectiveQuerier::new(&deps.querier)
+            .query_oracle_price(oracle_type, base, quote, None)?
+            .price_pair_state
+            .map(|state| state.pair_price)
+            .ok_or_else(|| StdError::generic_err(format!("No oracle price of {base}/{quote} for {denom} buffer"))),
+        BufferPriceSource::Route {
+            target_denom,
+            quantity,
+            target_decimals,
+        } => {
+            let estimate = estimate_swap_result(
+                deps,
+                env,
+                denom.to_string(),
+                target_denom.to_owned(),
+                SwapQuantity::InputQuantity(*quantity),
+                &OutputFeeRates::default(),
+            )?;
+
+            Ok(estimate.result_quantity / *quantity * decimals_factor(pricing.decimals) / decimals_factor(*target_decimals))
+        }
+    }
+}
+
+fn load_buffer_pricing(deps: Deps<InjectiveQueryWrapper>, denom: &str) -> StdResult<BufferPricing> {
+    BUFFER_PRICE_SOURCES
+        .may_load(deps.storage, denom.to_string())?
+        .ok_or_else(|| StdError::generic_err(format!("No price source for {denom} buffer")))
+}
+
+// Chain units of target denom worth the coin by the buffer price sources and decimals of both denoms
+pub fn get_equivalent_buffer_amount(deps: Deps<InjectiveQueryWrapper>, env: &Env, coin: &FPCoin, target_denom: &str) -> StdResult<FPDecimal> {
+    let source = load_buffer_pricing(deps, &coin.denom)?;
+    let target = load_buffer_pricing(deps, target_denom)?;
+
+    let value = coin.amount / decimals_factor(source.decimals) * query_buffer_price(deps, env, &source)?;
+    Ok(value / query_buffer_price(deps, env, &target)? * decimals_factor(target.decimals))
+}
+
+// Changes of the buffer ledger since the baseline, valued with the price source of each denom. A denom whose price
+// source fails is reported without value instead of failing the query.
+pub fn get_buffer_pnl(deps: Deps<InjectiveQueryWrapper>, env: &Env) -> StdResult<BufferPnlResponse> {
+    let baseline = BUFFER_PNL_BASELINE.may_load(deps.storage)?;
+    let baseline_ledgers = baseline.as_ref().map(|baseline| baseline.ledgers.as_slice()).unwrap_or_default();
+
+    let mut total_net_value = FPDecimal::ZERO;
+    let mut denoms = vec![];
+
+    for entry in get_buffer_ledger(deps.storage)? {
+        let start = baseline_ledgers
+            .iter()
+            .find(|baseline_entry| baseline_entry.denom == entry.denom)
+            .map(|baseline_entry| baseline_entry.ledger.to_owned())
+            .unwrap_or_default();
+
+        let fees = entry.ledger.fees - start.fees;
+        let rounding_gains = entry.ledger.rounding_gains - start.rounding_gains;
+        let rounding_losses = entry.ledger.rounding_losses - start.rounding_losses;
+        let rebalanced = entry.ledger.rebalanced_in - start.rebalanced_in - (entry.ledger.rebalanced_out - start.rebalanced_out);
+        let net = fees + rounding_gains - rounding_losses + rebalanced;
+        let rebates_passed_on = entry.ledger.rebates_passed_on - start.rebates_passed_on;
+
+        let pricing = BUFFER_PRICE_SOURCES.may_load(deps.storage, entry.denom.to_owned())?;
+        let price = pricing.as_ref().and_then(|pricing| query_buffer_price(deps, env, pricing).ok());
+        let net_value = pricing.zip(price).map(|(pricing, price)| net / decimals_factor(pricing.decimals) * price);
+        if let Some(net_value) = net_value {
+            total_net_value += net_value;
+        }
+
+        denoms.push(BufferPnl {
+            denom: entry.denom,
+            fees,
+            rounding_gains,
+            rounding_losses,
+            rebalanced,
+            net,
+            rebates_passed_on,
+            price,
+            net_value,
+        });
+    }
+
+    Ok(BufferPnlResponse {
+        baseline_time: baseline.map(|baseline| baseline.time),
+        denoms,
+        total_net_value,
+    })
+}
@@ -1,10 +1,24 @@
 use crate::{
-    admin::{delete_route, save_config, set_route, update_config, withdraw_support_funds},
+    admin::{
+        delete_route, reset_buffer_pnl_baseline, save_config, set_buffer_caps, set_buffer_denoms, set_buffer_price_sources, set_emergency_mode,
+        set_exempt_buffer_budget, set_fee_tiers, set_route, set_route_fee_overrides, set_withdrawal_reserves, update_buffer_keepers, update_config,
+        update_fee_exempt_addresses, withdraw_all_excess_funds, withdraw_support_funds,
+    },
+    buffer::{deposit_buffer, get_buffer_pnl, get_buffer_statuses, top_up_buffers, withdraw_buffer},
     error::ContractError,
+    fees::get_output_fee_rates,
+    market_data::{LiveMarketData, TraderMarketData},
     msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg},
-    queries::{estimate_swap_result, SwapQuantity},
-    state::{get_all_swap_routes, get_config, read_swap_route},
-    swap::{handle_atomic_order_reply, start_swap_flow},
+    queries::{
+        estimate_swap_result_for_route, get_hypothetical_swap_result, get_max_swap_size, get_price_ladder, get_trader_volume_response,
+        resolve_swap_route, SwapQuantity,
+    },
+    state::{
+        get_accrued_protocol_fees, get_accrued_rebates, get_all_swap_routes, get_buffer_caps, get_buffer_keepers, get_buffer_ledger,
+        get_buffer_positions, get_burn_auction_contributions, get_config, get_exempt_buffer_budget, get_fee_exempt_addresses,
+        get_withdrawal_reserves, is_fee_exempt, read_swap_route, FEE_TIERS,
+    },
+    swap::{convert_fees, handle_atomic_order_reply, handle_fee_conversion_reply, rebalance_buffer, start_swap_flow},
     types::{ConfigResponse, SwapQuantityMode},
 };
 
@@ -17,6 +31,7 @@ pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const ATOMIC_ORDER_REPLY_ID: u64 = 1u64;
 pub const DEPOSIT_REPLY_ID: u64 = 2u64;
+pub const FEE_CONVERSION_REPLY_ID: u64 = 3u64;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -42,16 +57,30 @@ pub fn execute(
         ExecuteMsg::SwapMinOutput {
             target_denom,
             min_output_quantity,
-        } => start_swap_flow(deps, env, info, target_denom, SwapQuantityMode::MinOutputQuantity(min_output_quantity)),
+            integrator,
+            tip_dust,
+        } => start_swap_flow(
+            deps,
+            env,
+            info,
+            target_denom,
+            SwapQuantityMode::MinOutputQuantity(min_output_quantity),
+            integrator,
+            tip_dust,
+        ),
         ExecuteMsg::SwapExactOutput {
             target_denom,
             target_output_quantity,
+            integrator,
+            tip_dust,
         } => start_swap_flow(
             deps,
             env,
             info,
             target_denom,
             SwapQuantityMode::ExactOutputQuantity(target_output_quantity),
+            integrator,
+            tip_dust,
         ),
         // Admin functions:
         ExecuteMsg::SetRoute {
@@ -60,8 +89,63 @@ pub fn execute(
             route,
         } => set_route(deps, &info.sender, source_denom, target_denom, route),
         ExecuteMsg::DeleteRoute { source_denom, target_denom } => delete_route(deps, &info.sender, source_denom, target_denom),
-        ExecuteMsg::UpdateConfig { admin, fee_recipient } => update_config(deps, env, info.sender, admin, fee_recipient),
-        ExecuteMsg::WithdrawSupportFunds { coins, target_address } => withdraw_support_funds(deps, info.sender, coins, target_address),
+        ExecuteMsg::SetRouteFeeOverrides {
+            source_denom,
+            target_denom,
+            fee_recipient,
+            protocol_fee_bps,
+        } => set_route_fee_overrides(deps, env, &info.sender, source_denom, target_denom, fee_recipient, protocol_fee_bps),
+        ExecuteMsg::UpdateConfig {
+            admin,
+            fee_recipient,
+            protocol_fee_bps,
+            treasury,
+            max_integrator_fee_bps,
+            burn_share_bps,
+            depositor_fee_share_bps,
+            depositor_rebate_share_bps,
+            max_buffer_withdrawal_bps,
+            accrue_protocol_fees,
+        } => update_config(
+            deps,
+            env,
+            info.sender,
+            admin,
+            fee_recipient,
+            protocol_fee_bps,
+            treasury,
+            max_integrator_fee_bps,
+            burn_share_bps,
+            depositor_fee_share_bps,
+            depositor_rebate_share_bps,
+            max_buffer_withdrawal_bps,
+            accrue_protocol_fees,
+        ),
+        ExecuteMsg::WithdrawSupportFunds { coins, target_address } => withdraw_support_funds(deps, env, info.sender, coins, target_address),
+        ExecuteMsg::WithdrawAllExcessFunds { target_address } => withdraw_all_excess_funds(deps, env, info.sender, target_address),
+        ExecuteMsg::SetWithdrawalReserves { reserves } => set_withdrawal_reserves(deps, &info.sender, reserves),
+        ExecuteMsg::SetEmergencyMode { enabled } => set_emergency_mode(deps, &info.sender, enabled),
+        ExecuteMsg::DepositBuffer {} => deposit_buffer(deps, &info.sender, info.funds),
+        ExecuteMsg::WithdrawBuffer { denom, shares } => withdraw_buffer(deps, env, &info.sender, denom, shares),
+        ExecuteMsg::SetFeeTiers { reference_denom, tiers } => set_fee_tiers(deps, &info.sender, reference_denom, tiers),
+        ExecuteMsg::UpdateFeeExemptAddresses { add, remove } => update_fee_exempt_addresses(deps, &info.sender, add, remove),
+        ExecuteMsg::UpdateBufferKeepers { add, remove } => update_buffer_keepers(deps, &info.sender, add, remove),
+        ExecuteMsg::RebalanceBuffer {
+            coin,
+            target_denom,
+            max_slippage_bps,
+        } => rebalance_buffer(deps, env, &info.sender, coin, target_denom, max_slippage_bps),
+        ExecuteMsg::SetExemptBufferBudget { budget } => set_exempt_buffer_budget(deps, &info.sender, budget),
+        ExecuteMsg::SetBufferDenoms { buffers } => set_buffer_denoms(deps, &info.sender, buffers),
+        ExecuteMsg::SetBufferCaps { caps } => set_buffer_caps(deps, &info.sender, caps),
+        ExecuteMsg::TopUpBuffers {} => top_up_buffers(deps, &info.sender),
+        ExecuteMsg::SetBufferPriceSources { sources } => set_buffer_price_sources(deps, &info.sender, sources),
+        ExecuteMsg::ResetBufferPnlBaseline {} => reset_buffer_pnl_baseline(deps, env, &info.sender),
+        ExecuteMsg::ConvertFees {
+            coins,
+            target_denom,
+            max_slippage_bps,
+        } => convert_fees(deps, env, &info.sender, coins, target_denom, max_slippage_bps),
     }
 }
 
@@ -69,6 +153,7 @@ pub fn execute(
 pub fn reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env, msg: Reply) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     match msg.id {
         ATOMIC_ORDER_REPLY_ID => handle_atomic_order_reply(deps, env, msg),
+        FEE_CONVERSION_REPLY_ID => handle_fee_conversion_reply(deps, env),
         _ => Err(ContractError::UnrecognizedReply(msg.id)),
     }
 }
@@ -81,27 +166,120 @@ pub fn query(deps: Deps<InjectiveQueryWrapper>, env: Env, msg: QueryMsg) -> Resu
             from_quantity,
             source_denom,
             target_denom,
-        } => to_json_binary(&estimate_swap_result(
+            route,
+            trader,
+            integrator,
+        } => {
+            let route = resolve_swap_route(deps, &source_denom, &target_denom, route)?;
+            to_json_binary(&estimate_swap_result_for_route(
+                deps,
+                &env,
+                &TraderMarketData::new(deps, &LiveMarketData::new(deps, &env), trader.as_ref())?,
+                &route,
+                source_denom,
+                target_denom,
+                SwapQuantity::InputQuantity(from_quantity),
+                &get_output_fee_rates(deps, &env, &route, trader.as_ref(), integrator.as_ref())?,
+            )?)
+        }
+
+        QueryMsg::GetInputQuantity {
+            to_quantity,
+            source_denom,
+            target_denom,
+            route,
+            trader,
+            integrator,
+        } => {
+            let route = resolve_swap_route(deps, &source_denom, &target_denom, route)?;
+            to_json_binary(&estimate_swap_result_for_route(
+                deps,
+                &env,
+                &TraderMarketData::new(deps, &LiveMarketData::new(deps, &env), trader.as_ref())?,
+                &route,
+                source_denom,
+                target_denom,
+                SwapQuantity::OutputQuantity(to_quantity),
+                &get_output_fee_rates(deps, &env, &route, trader.as_ref(), integrator.as_ref())?,
+            )?)
+        }
+
+        QueryMsg::GetAllRoutes { start_after, limit } => to_json_binary(&get_all_swap_routes(deps.storage, start_after, limit)?),
+
+        QueryMsg::GetMaxSwapSize {
+            source_denom,
+            target_denom,
+            route,
+            trader,
+            integrator,
+        } => to_json_binary(&get_max_swap_size(
             deps,
             &env,
             source_denom,
             target_denom,
-            SwapQuantity::InputQuantity(from_quantity),
+            route,
+            trader.as_ref(),
+            integrator.as_ref(),
         )?),
 
-        QueryMsg::GetInputQuantity {
-            to_quantity,
+        QueryMsg::GetPriceLadder {
             source_denom,
             target_denom,
-        } => to_json_binary(&estimate_swap_result(
+            sizes,
+            route,
+            trader,
+            integrator,
+        } => to_json_binary(&get_price_ladder(
             deps,
             &env,
             source_denom,
             target_denom,
-            SwapQuantity::OutputQuantity(to_quantity),
+            sizes,
+            route,
+            trader.as_ref(),
+            integrator.as_ref(),
         )?),
 
-        QueryMsg::GetAllRoutes { start_after, limit } => to_json_binary(&get_all_swap_routes(deps.storage, start_after, limit)?),
+        QueryMsg::GetHypotheticalSwapResult {
+            source_denom,
+            target_denom,
+            swap_quantity,
+            markets,
+            buffer,
+            trader,
+            integrator,
+        } => to_json_binary(&get_hypothetical_swap_result(
+            deps,
+            &env,
+            source_denom,
+            target_denom,
+            swap_quantity,
+            markets,
+            buffer,
+            trader.as_ref(),
+            integrator.as_ref(),
+        )?),
+
+        QueryMsg::GetFeeTiers {} => to_json_binary(&FEE_TIERS.may_load(deps.storage)?),
+
+        QueryMsg::GetFeeExemptAddresses { start_after, limit } => to_json_binary(&get_fee_exempt_addresses(deps.storage, start_after, limit)?),
+
+        QueryMsg::IsFeeExempt { address } => to_json_binary(&is_fee_exempt(deps.storage, &address)),
+        QueryMsg::GetBufferKeepers { start_after, limit } => to_json_binary(&get_buffer_keepers(deps.storage, start_after, limit)?),
+
+        QueryMsg::GetExemptBufferBudget {} => to_json_binary(&get_exempt_buffer_budget(deps.storage)?),
+
+        QueryMsg::GetTraderVolume { trader } => to_json_binary(&get_trader_volume_response(deps, &env, &trader)?),
+
+        QueryMsg::GetAccruedRebates {} => to_json_binary(&get_accrued_rebates(deps.storage)?),
+        QueryMsg::GetBurnAuctionContributions {} => to_json_binary(&get_burn_auction_contributions(deps.storage)?),
+        QueryMsg::GetBufferLedger {} => to_json_binary(&get_buffer_ledger(deps.storage)?),
+        QueryMsg::GetBufferCaps {} => to_json_binary(&get_buffer_caps(deps.storage)?),
+        QueryMsg::GetAccruedProtocolFees {} => to_json_binary(&get_accrued_protocol_fees(deps.storage)?),
+        QueryMsg::GetWithdrawalReserves {} => to_json_binary(&get_withdrawal_reserves(deps.storage)?),
+        QueryMsg::GetBufferPnl {} => to_json_binary(&get_buffer_pnl(deps, &env)?),
+        QueryMsg::GetBuffers {} => to_json_binary(&get_buffer_statuses(deps, &env)?),
+        QueryMsg::GetBufferPositions { depositor } => to_json_binary(&get_buffer_positions(deps.storage, &depositor)?),
 
         QueryMsg::GetConfig {} => {
             let config = get_config(deps.storage)?;
@@ -28,6 +28,78 @@ pub enum ContractError {
     #[error("Provided amount of {0} is below required amount of {1}")]
     InsufficientFundsProvided(FPDecimal, FPDecimal),
 
+    #[error("Not enough liquidity to fulfill order")]
+    InsufficientLiquidity {},
+
+    #[error("Swap amount too high, required {denom} funds: {required}, available funds: {available}")]
+    SwapAmountTooHigh {
+        denom: String,
+        required: FPDecimal,
+        available: FPDecimal,
+    },
+
+    #[error("Swap would use {required} of {denom} buffer, only {budget} is left in the buffer budget of fee-exempt addresses")]
+    ExemptBufferBudgetExceeded { denom: String, required: FPDecimal, budget: FPDecimal },
+
+    #[error("Swap would use {required} of {denom} buffer, only {available} is left under its caps")]
+    BufferCapExceeded {
+        denom: String,
+        required: FPDecimal,
+        available: FPDecimal,
+    },
+
+    #[error("Buffer of {denom} is below its critical balance, swap would use {required} of it but at most {available} is allowed")]
+    BufferCritical {
+        denom: String,
+        required: FPDecimal,
+        available: FPDecimal,
+    },
+
+    #[error("Rebalance of {denom} buffer would receive {output}, below {min_output} allowed by its max slippage")]
+    RebalanceSlippageExceeded {
+        denom: String,
+        output: FPDecimal,
+        min_output: FPDecimal,
+    },
+
+    #[error("Withdrawal would leave {balance} of {denom}, below its reserve of {reserve}")]
+    WithdrawalBelowReserve { denom: String, balance: FPDecimal, reserve: FPDecimal },
+
+    #[error("No buffer is configured for quote denom {denom}")]
+    BufferNotConfigured { denom: String },
+
+    #[error("Buffer of {denom} is underfunded, balance of {balance} is below the minimum of {min_balance}")]
+    BufferUnderfunded {
+        denom: String,
+        balance: FPDecimal,
+        min_balance: FPDecimal,
+    },
+
     #[error("Contract can't be migrated")]
     MigrationError {},
 }
+
+impl ContractError {
+    // Errors of swaps too large for the orderbooks or the buffer, a smaller swap over the same route may still succeed
+    pub fn is_size_limit(&self) -> bool {
+        matches!(
+            self,
+            ContractError::InsufficientLiquidity {}
+                | ContractError::SwapAmountTooHigh { .. }
+                | ContractError::ExemptBufferBudgetExceeded { .. }
+                | ContractError::BufferCapExceeded { .. }
+                | ContractError::BufferCritical { .. }
+                | ContractError::BufferUnderfunded { .. }
+        )
+    }
+}
+
+// Queries return StdError, errors of the contract keep their message
+impl From<ContractError> for StdError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            ContractError::Std(err) => err,
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use cosmwasm_std::{Addr, Deps, Env, StdError, StdResult};
+use injective_cosmwasm::InjectiveQueryWrapper;
+use injective_math::FPDecimal;
+
+use crate::{
+    state::{get_trader_volume, is_fee_exempt, CONFIG, FEE_TIERS},
+    types::{Config, FeeTierSchedule, Integrator, OutputFeeRates, SwapRoute},
+};
+
+pub const BPS_DENOMINATOR: u16 = 10_000;
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 1_000;
+pub const MAX_INTEGRATOR_FEE_BPS: u16 = 1_000;
+// subaccount of the exchange module whose deposits are auctioned for INJ that is burned
+pub const BURN_AUCTION_SUBACCOUNT_ID: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+
+// Fees skimmed from the final swap output before it is sent to the swapper
+pub struct OutputFees {
+    pub protocol_fee: FPDecimal,
+    pub integrator_fee: FPDecimal,
+}
+
+impl OutputFees {
+    pub fn total(&self) -> FPDecimal {
+        self.protocol_fee + self.integrator_fee
+    }
+}
+
+pub fn bps_to_dec(bps: u16) -> FPDecimal {
+    FPDecimal::from(bps as u128) / FPDecimal::from(BPS_DENOMINATOR as u128)
+}
+
+pub fn validate_integrator(config: &Config, integrator: Option<&Integrator>) -> StdResult<()> {
+    match integrator {
+        Some(integrator) if integrator.fee_bps > config.max_integrator_fee_bps => Err(StdError::generic_err(format!(
+            "Integrator fee cannot be higher than {} bps",
+            config.max_integrator_fee_bps
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Fee rates charged on output of a swap over the route made by the trader, through the integrator if there is one
+pub fn get_output_fee_rates(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    route: &SwapRoute,
+    trader: Option<&Addr>,
+    integrator: Option<&Integrator>,
+) -> StdResult<OutputFeeRates> {
+    let config = CONFIG.load(deps.storage)?;
+    validate_integrator(&config, integrator)?;
+
+    if trader.is_some_and(|trader| is_fee_exempt(deps.storage, trader)) {
+        return Ok(OutputFeeRates::default());
+    }
+
+    let discount_bps = match (trader, FEE_TIERS.may_load(deps.storage)?) {
+        (Some(trader), Some(schedule)) => get_tier_discount_bps(&schedule, get_trader_volume(deps.storage, trader, env.block.time)?),
+        _ => 0,
+    };
+
+    Ok(OutputFeeRates {
+        protocol_fee_bps: get_discounted_fee_bps(route.effective_protocol_fee_bps(&config), discount_bps),
+        integrator_fee_bps: integrator.map_or(0, |integrator| integrator.fee_bps),
+    })
+}
+
+// Discount of the reached tier with the highest minimum volume, picked by volume so it does not rely on the stored tier order
+pub fn get_tier_discount_bps(schedule: &FeeTierSchedule, volume: FPDecimal) -> u16 {
+    schedule
+        .tiers
+        .iter()
+        .filter(|tier| tier.min_volume <= volume)
+        .max_by_key(|tier| tier.min_volume)
+        .map_or(0, |tier| tier.discount_bps)
+}
+
+// Discount is a share of the fee, result is rounded down
+fn get_discounted_fee_bps(fee_bps: u16, discount_bps: u16) -> u16 {
+    (fee_bps as u32 * (BPS_DENOMINATOR - discount_bps) as u32 / BPS_DENOMINATOR as u32) as u16
+}
+
+// Fee taken from the swap output, rounded down to a whole amount so that it can be sent
+pub fn get_output_fee(output_quantity: FPDecimal, fee_bps: u16) -> FPDecimal {
+    (output_quantity * bps_to_dec(fee_bps)).int()
+}
+
+pub fn get_output_fees(output_quantity: FPDecimal, fee_rates: &OutputFeeRates) -> OutputFees {
+    OutputFees {
+        protocol_fee: get_output_fee(output_quantity, fee_rates.protocol_fee_bps),
+        integrator_fee: get_output_fee(output_quantity, fee_rates.integrator_fee_bps),
+    }
+}
+
+// Output the swap has to produce so that at least target quantity is left after all output fees are skimmed
+pub fn get_output_before_fees(target_quantity: FPDecimal, fee_rates: &OutputFeeRates) -> FPDecimal {
+    let total_fee_bps = fee_rates.protocol_fee_bps + fee_rates.integrator_fee_bps;
+
+    if total_fee_bps == 0 {
+        return target_quantity;
+    }
+
+    target_quantity / (FPDecimal::ONE - bps_to_dec(total_fee_bps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{msg::FeeRecipient, testing::test_utils::test_config, types::FeeTier};
+
+    fn config_with_max_integrator_fee(max_integrator_fee_bps: u16) -> Config {
+        Config {
+            fee_recipient: FeeRecipient::Address(Addr::unchecked("fee_recipient")),
+            admin: Addr::unchecked("admin"),
+            protocol_fee_bps: 30,
+            treasury: Some(Addr::unchecked("treasury")),
+            max_integrator_fee_bps,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn test_get_output_fee_rounds_down() {
+        assert_eq!(get_output_fee(FPDecimal::from(1000u128), 30), FPDecimal::from(3u128));
+        assert_eq!(get_output_fee(FPDecimal::from(999u128), 30), FPDecimal::TWO);
+        assert_eq!(get_output_fee(FPDecimal::from(1000u128), 0), FPDecimal::ZERO);
+    }
+
+    #[test]
+    fn test_output_before_fees_covers_target() {
+        let fee_rates = OutputFeeRates {
+            protocol_fee_bps: 30,
+            integrator_fee_bps: 20,
+        };
+        let target = FPDecimal::from(2888u128);
+        let output = get_output_before_fees(target, &fee_rates);
+
+        assert!(output > target);
+        assert!(output - get_output_fees(output, &fee_rates).total() >= target);
+        assert_eq!(get_output_before_fees(target, &OutputFeeRates::default()), target);
+    }
+
+    #[test]
+    fn test_validate_integrator_respects_max_fee() {
+        let config = config_with_max_integrator_fee(50);
+        let mut integrator = Integrator {
+            address: Addr::unchecked("integrator"),
+            fee_bps: 50,
+        };
+
+        assert!(validate_integrator(&config, Some(&integrator)).is_ok());
+        assert!(validate_integrator(&config, None).is_ok());
+
+        integrator.fee_bps = 51;
+        assert!(validate_integrator(&config, Some(&integrator)).is_err());
+    }
+
+    #[test]
+    fn test_tier_discount_uses_highest_reached_tier() {
+        let schedule = FeeTierSchedule {
+            reference_denom: "usdt".to_string(),
+            tiers: vec![
+                FeeTier {
+                    min_volume: FPDecimal::from(1_000u128),
+                    discount_bps: 1_000,
+                },
+                FeeTier {
+                    min_volume: FPDecimal::from(10_000u128),
+                    discount_bps: 5_000,
+                },
+            ],
+        };
+
+        assert_eq!(get_tier_discount_bps(&schedule, FPDecimal::from(999u128)), 0);
+        assert_eq!(get_tier_discount_bps(&schedule, FPDecimal::from(1_000u128)), 1_000);
+        assert_eq!(get_tier_discount_bps(&schedule, FPDecimal::from(50_000u128)), 5_000);
+
+        let mut reversed = schedule.clone();
+        reversed.tiers.reverse();
+        assert_eq!(get_tier_discount_bps(&reversed, FPDecimal::from(1_000u128)), 1_000);
+        assert_eq!(get_tier_discount_bps(&reversed, FPDecimal::from(50_000u128)), 5_000);
+
+        assert_eq!(get_discounted_fee_bps(30, 5_000), 15);
+        assert_eq!(get_discounted_fee_bps(30, 1_000), 27);
+        assert_eq!(get_discounted_fee_bps(30, BPS_DENOMINATOR), 0);
+    }
+}
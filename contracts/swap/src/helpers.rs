@@ -1,4 +1,4 @@
-use cosmwasm_std::{CosmosMsg, DepsMut, Response, SubMsg};
+use cosmwasm_std::{Coin, CosmosMsg, DepsMut, Response, SubMsg};
 
 use cw_storage_plus::Item;
 use injective_cosmwasm::{InjectiveMsgWrapper, InjectiveQueryWrapper};
@@ -46,6 +46,18 @@ pub fn dec_scale_factor() -> FPDecimal {
     FPDecimal::ONE.scaled(18)
 }
 
+// Sums amounts of the same denom, keeping the order in which denoms first appear
+pub fn sum_coins_by_denom(coins: &[Coin]) -> Vec<Coin> {
+    let mut totals: Vec<Coin> = Vec::with_capacity(coins.len());
+    for coin in coins {
+        match totals.iter_mut().find(|total| total.denom == coin.denom) {
+            Some(total) => total.amount += coin.amount,
+            None => totals.push(coin.to_owned()),
+        }
+    }
+    totals
+}
+
 type V100Config = Config;
 const V100CONFIG: Item<V100Config> = Item::new("config");
 
@@ -55,6 +67,14 @@ pub fn handle_config_migration(deps: DepsMut<InjectiveQueryWrapper>) -> Result<R
     let config = Config {
         fee_recipient: v100_config.fee_recipient,
         admin: v100_config.admin,
+        protocol_fee_bps: v100_config.protocol_fee_bps,
+        treasury: v100_config.treasury,
+        max_integrator_fee_bps: v100_config.max_integrator_fee_bps,
+        burn_share_bps: v100_config.burn_share_bps,
+        depositor_fee_share_bps: v100_config.depositor_fee_share_bps,
+        depositor_rebate_share_bps: v100_config.depositor_rebate_share_bps,
+        max_buffer_withdrawal_bps: v100_config.max_buffer_withdrawal_bps,
+        accrue_protocol_fees: v100_config.accrue_protocol_fees,
     };
 
     CONFIG.save(deps.storage, &config)?;
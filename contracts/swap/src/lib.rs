@@ -1,7 +1,10 @@
 pub mod admin;
+pub mod buffer;
 pub mod contract;
 mod error;
+pub mod fees;
 pub mod helpers;
+pub mod market_data;
 pub mod msg;
 pub mod queries;
 pub mod state;
@@ -0,0 +1,355 @@
+use cosmwasm_std::{ensure, Addr, Deps, Env, StdError, StdResult};
+use injective_cosmwasm::{InjectiveQuerier, InjectiveQueryWrapper, MarketId, OrderSide, PriceLevel, SpotMarket};
+use injective_math::FPDecimal;
+use std::{cell::RefCell, cmp::Reverse, collections::HashMap};
+
+use crate::state::{get_buffer_cap_left, get_exempt_buffer_budget, is_fee_exempt, BUFFER_DENOMS};
+use crate::types::{BufferDenom, FPCoin, HypotheticalMarket};
+use crate::{ContractError, ContractError::CustomError};
+
+// Everything the swap estimation needs to know about markets and the buffer, so that the same estimation code
+// can run against the live exchange and against caller-supplied orderbooks
+pub trait MarketDataSource {
+    fn spot_market(&self, market_id: &MarketId) -> StdResult<Option<SpotMarket>>;
+
+    fn atomic_execution_fee_multiplier(&self, market_id: &MarketId) -> StdResult<FPDecimal>;
+
+    // buys are returned from the highest price, sells from the lowest one
+    fn orderbook(
+        &self,
+        market_id: &MarketId,
+        order_side: OrderSide,
+        limit_cumulative_quantity: Option<FPDecimal>,
+        limit_cumulative_notional: Option<FPDecimal>,
+    ) -> StdResult<Vec<PriceLevel>>;
+
+    fn buffer_balance(&self, denom: &str) -> StdResult<FPDecimal>;
+
+    // most of the buffer a single swap may draw on top of its own funds, None if it is not limited
+    fn buffer_budget(&self, _denom: &str) -> StdResult<Option<FPDecimal>> {
+        Ok(None)
+    }
+
+    // most of the buffer a swap may use under the caps of the denom in the current block, None if it is not capped
+    fn buffer_cap(&self, _denom: &str) -> StdResult<Option<FPDecimal>> {
+        Ok(None)
+    }
+
+    // buffer configured for the denom with its balance marks, None if it has none
+    fn buffer_denom(&self, _denom: &str) -> StdResult<Option<BufferDenom>> {
+        Ok(None)
+    }
+}
+
+pub struct LiveMarketData<'a> {
+    deps: Deps<'a, InjectiveQueryWrapper>,
+    contract_address: Addr,
+    block_height: u64,
+}
+
+impl<'a> LiveMarketData<'a> {
+    pub fn new(deps: Deps<'a, InjectiveQueryWrapper>, env: &Env) -> Self {
+        LiveMarketData {
+            deps,
+            contract_address: env.contract.address.to_owned(),
+            block_height: env.block.height,
+        }
+    }
+}
+
+impl MarketDataSource for LiveMarketData<'_> {
+    fn spot_market(&self, market_id: &MarketId) -> StdResult<Option<SpotMarket>> {
+        let querier = InjectiveQuerier::new(&self.deps.querier);
+        Ok(querier.query_spot_market(market_id)?.market)
+    }
+
+    fn atomic_execution_fee_multiplier(&self, market_id: &MarketId) -> StdResult<FPDecimal> {
+        let querier = InjectiveQuerier::new(&self.deps.querier);
+        Ok(querier.query_market_atomic_execution_fee_multiplier(market_id)?.multiplier)
+    }
+
+    fn orderbook(
+        &self,
+        market_id: &MarketId,
+        order_side: OrderSide,
+        limit_cumulative_quantity: Option<FPDecimal>,
+        limit_cumulative_notional: Option<FPDecimal>,
+    ) -> StdResult<Vec<PriceLevel>> {
+        let querier = InjectiveQuerier::new(&self.deps.querier);
+        let orders = querier.query_spot_market_orderbook(market_id, order_side.to_owned(), limit_cumulative_quantity, limit_cumulative_notional)?;
+
+        Ok(match order_side {
+            OrderSide::Buy => orders.buys_price_level,
+            _ => orders.sells_price_level,
+        })
+    }
+
+    fn buffer_balance(&self, denom: &str) -> StdResult<FPDecimal> {
+        Ok(self.deps.querier.query_balance(&self.contract_address, denom)?.amount.into())
+    }
+
+    fn buffer_cap(&self, denom: &str) -> StdResult<Option<FPDecimal>> {
+        get_buffer_cap_left(self.deps.storage, denom, self.block_height)
+    }
+
+    fn buffer_denom(&self, denom: &str) -> StdResult<Option<BufferDenom>> {
+        BUFFER_DENOMS.may_load(self.deps.storage, denom.to_string())
+    }
+}
+
+// Markets and orderbooks supplied by the caller. If no buffer is supplied, the contract's live balances are used. None of
+// the buffer caps and critical balances apply to the simulation, only the buffer budget of a fee-exempt trader does.
+pub struct HypotheticalMarketData<'a> {
+    markets: HashMap<MarketId, HypotheticalMarket>,
+    buffer: Option<Vec<FPCoin>>,
+    live: LiveMarketData<'a>,
+}
+
+impl<'a> HypotheticalMarketData<'a> {
+    pub fn new(
+        deps: Deps<'a, InjectiveQueryWrapper>,
+        env: &Env,
+        markets: Vec<HypotheticalMarket>,
+        buffer: Option<Vec<FPCoin>>,
+    ) -> Result<Self, ContractError> {
+        let mut markets_by_id = HashMap::new();
+
+        for mut market in markets {
+            validate_hypothetical_market(&market)?;
+
+            market.buys.sort_by_key(|level| Reverse(level.p));
+            market.sells.sort_by_key(|level| level.p);
+
+            if markets_by_id.insert(market.market.market_id.to_owned(), market).is_some() {
+                return Err(StdError::generic_err("Hypothetical markets cannot be duplicated").into());
+            }
+        }
+
+        Ok(HypotheticalMarketData {
+            markets: markets_by_id,
+            buffer,
+            live: LiveMarketData::new(deps, env),
+        })
+    }
+
+    fn market(&self, market_id: &MarketId) -> StdResult<&HypotheticalMarket> {
+        self.markets
+            .get(market_id)
+            .ok_or_else(|| StdError::generic_err(format!("Market {} not provided", market_id.as_str())))
+    }
+}
+
+// Estimation divides by tick sizes, by one less the fee and by the value of price levels, so values the exchange would
+// never have are rejected before they can make it fail on them. Maker fee is not used by estimation and may be negative
+// like on the exchange.
+fn validate_hypothetical_market(market: &HypotheticalMarket) -> Result<(), ContractError> {
+    let market_id = market.market.market_id.as_str();
+    let is_rate = |rate: FPDecimal| !rate.is_negative() && rate < FPDecimal::ONE;
+
+    ensure!(
+        market.market.min_price_tick_size > FPDecimal::ZERO && market.market.min_quantity_tick_size > FPDecimal::ZERO,
+        CustomError {
+            val: format!("Tick sizes of market {market_id} must be positive")
+        }
+    );
+
+    ensure!(
+        is_rate(market.market.taker_fee_rate) && is_rate(market.market.relayer_fee_share_rate),
+        CustomError {
+            val: format!("Taker fee rate and relayer fee share of market {market_id} must be at least 0 and below 1")
+        }
+    );
+
+    ensure!(
+        market.atomic_execution_fee_multiplier > FPDecimal::ZERO && is_rate(market.market.taker_fee_rate * market.atomic_execution_fee_multiplier),
+        CustomError {
+            val: format!("Atomic execution fee multiplier of market {market_id} must be positive and keep the taker fee below 1")
+        }
+    );
+
+    // price times quantity of tiny levels can round to zero, such levels have no value to fill orders with
+    let has_invalid_level = market
+        .buys
+        .iter()
+        .chain(market.sells.iter())
+        .any(|level| level.p <= FPDecimal::ZERO || level.q <= FPDecimal::ZERO || (level.p * level.q).is_zero());
+    ensure!(
+        !has_invalid_level,
+        CustomError {
+            val: format!("Price levels of market {market_id} must have positive price, quantity and value")
+        }
+    );
+
+    Ok(())
+}
+
+impl MarketDataSource for HypotheticalMarketData<'_> {
+    fn spot_market(&self, market_id: &MarketId) -> StdResult<Option<SpotMarket>> {
+        Ok(self.markets.get(market_id).map(|market| market.market.to_owned()))
+    }
+
+    fn atomic_execution_fee_multiplier(&self, market_id: &MarketId) -> StdResult<FPDecimal> {
+        Ok(self.market(market_id)?.atomic_execution_fee_multiplier)
+    }
+
+    // whole side of the book is returned, estimation only takes as many levels as it needs
+    fn orderbook(&self, market_id: &MarketId, order_side: OrderSide, _: Option<FPDecimal>, _: Option<FPDecimal>) -> StdResult<Vec<PriceLevel>> {
+        let market = self.market(market_id)?;
+
+        Ok(match order_side {
+            OrderSide::Buy => market.buys.to_owned(),
+            _ => market.sells.to_owned(),
+        })
+    }
+
+    fn buffer_balance(&self, denom: &str) -> StdResult<FPDecimal> {
+        match &self.buffer {
+            Some(buffer) => Ok(buffer
+                .iter()
+                .filter(|coin| coin.denom == denom)
+                .fold(FPDecimal::ZERO, |acc, coin| acc + coin.amount)),
+            None => self.live.buffer_balance(denom),
+        }
+    }
+}
+
+// Remembers every market, multiplier and full orderbook side it was asked for, so that many estimations over the same
+// route query each of them only once. Limits are ignored, the whole side of the book is fetched and kept.
+pub struct CachedMarketData<'a> {
+    inner: &'a dyn MarketDataSource,
+    markets: RefCell<HashMap<MarketId, Option<SpotMarket>>>,
+    multipliers: RefCell<HashMap<MarketId, FPDecimal>>,
+    orderbooks: RefCell<HashMap<(MarketId, bool), Vec<PriceLevel>>>,
+    buffer_balances: RefCell<HashMap<String, FPDecimal>>,
+}
+
+impl<'a> CachedMarketData<'a> {
+    pub fn new(inner: &'a dyn MarketDataSource) -> Self {
+        CachedMarketData {
+            inner,
+            markets: RefCell::new(HashMap::new()),
+            multipliers: RefCell::new(HashMap::new()),
+            orderbooks: RefCell::new(HashMap::new()),
+            buffer_balances: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl MarketDataSource for CachedMarketData<'_> {
+    fn spot_market(&self, market_id: &MarketId) -> StdResult<Option<SpotMarket>> {
+        if let Some(market) = self.markets.borrow().get(market_id) {
+            return Ok(market.to_owned());
+        }
+
+        let market = self.inner.spot_market(market_id)?;
+        self.markets.borrow_mut().insert(market_id.to_owned(), market.to_owned());
+        Ok(market)
+    }
+
+    fn atomic_execution_fee_multiplier(&self, market_id: &MarketId) -> StdResult<FPDecimal> {
+        if let Some(multiplier) = self.multipliers.borrow().get(market_id) {
+            return Ok(*multiplier);
+        }
+
+        let multiplier = self.inner.atomic_execution_fee_multiplier(market_id)?;
+        self.multipliers.borrow_mut().insert(market_id.to_owned(), multiplier);
+        Ok(multiplier)
+    }
+
+    fn orderbook(&self, market_id: &MarketId, order_side: OrderSide, _: Option<FPDecimal>, _: Option<FPDecimal>) -> StdResult<Vec<PriceLevel>> {
+        let key = (market_id.to_owned(), order_side == OrderSide::Buy);
+        if let Some(orders) = self.orderbooks.borrow().get(&key) {
+            return Ok(orders.to_owned());
+        }
+
+        let orders = self.inner.orderbook(market_id, order_side, None, None)?;
+        self.orderbooks.borrow_mut().insert(key, orders.to_owned());
+        Ok(orders)
+    }
+
+    fn buffer_balance(&self, denom: &str) -> StdResult<FPDecimal> {
+        if let Some(balance) = self.buffer_balances.borrow().get(denom) {
+            return Ok(*balance);
+        }
+
+        let balance = self.inner.buffer_balance(denom)?;
+        self.buffer_balances.borrow_mut().insert(denom.to_string(), balance);
+        Ok(balance)
+    }
+
+    fn buffer_budget(&self, denom: &str) -> StdResult<Option<FPDecimal>> {
+        self.inner.buffer_budget(denom)
+    }
+
+    fn buffer_cap(&self, denom: &str) -> StdResult<Option<FPDecimal>> {
+        self.inner.buffer_cap(denom)
+    }
+
+    fn buffer_denom(&self, denom: &str) -> StdResult<Option<BufferDenom>> {
+        self.inner.buffer_denom(denom)
+    }
+}
+
+// Limits the buffer available to fee-exempt traders to what is left of their separate budget, so that internal operations
+// do not compete with users for it. They are not held to the shared buffer caps, which are left to other traders, and
+// denoms without a budget cannot be drawn from the buffer at all. Other traders are not limited.
+pub struct TraderMarketData<'a> {
+    inner: &'a dyn MarketDataSource,
+    buffer_budget: Option<Vec<FPCoin>>,
+}
+
+impl<'a> TraderMarketData<'a> {
+    pub fn new(deps: Deps<InjectiveQueryWrapper>, inner: &'a dyn MarketDataSource, trader: Option<&Addr>) -> StdResult<Self> {
+        let buffer_budget = match trader {
+            Some(trader) if is_fee_exempt(deps.storage, trader) => Some(get_exempt_buffer_budget(deps.storage)?),
+            _ => None,
+        };
+
+        Ok(TraderMarketData { inner, buffer_budget })
+    }
+}
+
+impl MarketDataSource for TraderMarketData<'_> {
+    fn spot_market(&self, market_id: &MarketId) -> StdResult<Option<SpotMarket>> {
+        self.inner.spot_market(market_id)
+    }
+
+    fn atomic_execution_fee_multiplier(&self, market_id: &MarketId) -> StdResult<FPDecimal> {
+        self.inner.atomic_execution_fee_multiplier(market_id)
+    }
+
+    fn orderbook(
+        &self,
+        market_id: &MarketId,
+        order_side: OrderSide,
+        limit_cumulative_quantity: Option<FPDecimal>,
+        limit_cumulative_notional: Option<FPDecimal>,
+    ) -> StdResult<Vec<PriceLevel>> {
+        self.inner
+            .orderbook(market_id, order_side, limit_cumulative_quantity, limit_cumulative_notional)
+    }
+
+    fn buffer_balance(&self, denom: &str) -> StdResult<FPDecimal> {
+        self.inner.buffer_balance(denom)
+    }
+
+    fn buffer_budget(&self, denom: &str) -> StdResult<Option<FPDecimal>> {
+        match &self.buffer_budget {
+            Some(budget) => Ok(Some(
+                budget.iter().find(|coin| coin.denom == denom).map_or(FPDecimal::ZERO, |coin| coin.amount),
+            )),
+            None => self.inner.buffer_budget(denom),
+        }
+    }
+
+    fn buffer_cap(&self, denom: &str) -> StdResult<Option<FPDecimal>> {
+        match &self.buffer_budget {
+            Some(_) => Ok(None),
+            None => self.inner.buffer_cap(denom),
+        }
+    }
+
+    fn buffer_denom(&self, denom: &str) -> StdResult<Option<BufferDenom>> {
+        self.inner.buffer_denom(denom)
+    }
+}
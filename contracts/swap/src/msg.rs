@@ -3,10 +3,26 @@ use cosmwasm_std::{Addr, Coin};
 use injective_cosmwasm::MarketId;
 use injective_math::FPDecimal;
 
+use crate::queries::SwapQuantity;
+use crate::types::{BufferCap, BufferDenom, BufferPricing, FPCoin, FeeTier, HypotheticalMarket, Integrator};
+
 #[cw_serde]
 pub enum FeeRecipient {
     Address(Addr),
     SwapContract,
+    // orders of each swap set the swapper as fee recipient, so the exchange pays the relayer rebate directly to them
+    Sender,
+}
+
+impl FeeRecipient {
+    // address set as fee recipient of the orders of a swap made by the sender
+    pub fn resolve(&self, contract_address: &Addr, sender: &Addr) -> Addr {
+        match self {
+            FeeRecipient::Address(addr) => addr.to_owned(),
+            FeeRecipient::SwapContract => contract_address.to_owned(),
+            FeeRecipient::Sender => sender.to_owned(),
+        }
+    }
 }
 
 #[cw_serde]
@@ -23,10 +39,17 @@ pub enum ExecuteMsg {
     SwapMinOutput {
         target_denom: String,
         min_output_quantity: FPDecimal,
+        integrator: Option<Integrator>,
+        // leave rounding leftovers of the swap in the buffer instead of getting them refunded
+        #[serde(default)]
+        tip_dust: bool,
     },
     SwapExactOutput {
         target_denom: String,
         target_output_quantity: FPDecimal,
+        integrator: Option<Integrator>,
+        #[serde(default)]
+        tip_dust: bool,
     },
     SetRoute {
         source_denom: String,
@@ -37,14 +60,95 @@ pub enum ExecuteMsg {
         source_denom: String,
         target_denom: String,
     },
+    // overrides of the config for swaps over a stored route, None falls back to the config
+    SetRouteFeeOverrides {
+        source_denom: String,
+        target_denom: String,
+        fee_recipient: Option<FeeRecipient>,
+        protocol_fee_bps: Option<u16>,
+    },
     UpdateConfig {
         admin: Option<Addr>,
         fee_recipient: Option<FeeRecipient>,
+        protocol_fee_bps: Option<u16>,
+        treasury: Option<Addr>,
+        max_integrator_fee_bps: Option<u16>,
+        burn_share_bps: Option<u16>,
+        depositor_fee_share_bps: Option<u16>,
+        depositor_rebate_share_bps: Option<u16>,
+        max_buffer_withdrawal_bps: Option<u16>,
+        accrue_protocol_fees: Option<bool>,
     },
     WithdrawSupportFunds {
         coins: Vec<Coin>,
         target_address: Addr,
     },
+    // withdraws whatever the contract holds beyond the buffer ledger and accrued protocol fees, down to the reserves
+    WithdrawAllExcessFunds {
+        target_address: Addr,
+    },
+    SetWithdrawalReserves {
+        reserves: Vec<FPCoin>,
+    },
+    // lets withdrawals of the admin go below the reserves
+    SetEmergencyMode {
+        enabled: bool,
+    },
+    // adds the funds sent along to the buffer in exchange for shares of it
+    DepositBuffer {},
+    // redeems buffer shares of a denom for their part of its buffer
+    WithdrawBuffer {
+        denom: String,
+        shares: FPDecimal,
+    },
+    // quote denoms the contract keeps buffers of, replacing the previous ones
+    SetBufferDenoms {
+        buffers: Vec<BufferDenom>,
+    },
+    // limits of the buffer swaps can use by denom, replacing the previous ones
+    SetBufferCaps {
+        caps: Vec<BufferCap>,
+    },
+    // moves accrued protocol fees into buffers up to their targets and sends the rest to the treasury, anyone can call it
+    TopUpBuffers {},
+    SetBufferPriceSources {
+        sources: Vec<BufferPricing>,
+    },
+    // swaps buffer funds of a denom into the buffer of target denom over the stored route, admin or keepers only. Output
+    // below the value of the coin by the buffer price sources less max slippage fails the rebalance
+    RebalanceBuffer {
+        coin: Coin,
+        target_denom: String,
+        max_slippage_bps: u16,
+    },
+    UpdateBufferKeepers {
+        add: Vec<Addr>,
+        remove: Vec<Addr>,
+    },
+    ResetBufferPnlBaseline {},
+    // protocol fee discounts by rolling 30-day swap volume valued in reference denom, empty tiers disable discounts
+    SetFeeTiers {
+        reference_denom: String,
+        tiers: Vec<FeeTier>,
+    },
+    // addresses that pay neither protocol nor integrator fees, and draw the buffer only up to the exempt budget
+    UpdateFeeExemptAddresses {
+        add: Vec<Addr>,
+        remove: Vec<Addr>,
+    },
+    // total buffer fee-exempt addresses may still draw, by denom, their swaps draw it down instead of the buffer caps
+    SetExemptBufferBudget {
+        budget: Vec<FPCoin>,
+    },
+    // swaps accrued protocol fees into target denom over stored routes and sends the results to the treasury. Fees only
+    // accrue once accrue_protocol_fees is turned on, with the default config they go to the treasury on each swap and
+    // there is nothing to convert. Output below the value of a coin by the buffer price sources less max slippage fails
+    // the conversion
+    ConvertFees {
+        coins: Vec<Coin>,
+        target_denom: String,
+        max_slippage_bps: u16,
+    },
 }
 
 #[cw_serde]
@@ -57,15 +161,93 @@ pub enum QueryMsg {
         from_quantity: FPDecimal,
         source_denom: String,
         target_denom: String,
+        // quote using this route instead of the stored one
+        route: Option<Vec<MarketId>>,
+        // quote with protocol fee discount of this trader
+        trader: Option<Addr>,
+        // quote including fee of this integrator
+        integrator: Option<Integrator>,
     },
     GetInputQuantity {
         to_quantity: FPDecimal,
         source_denom: String,
         target_denom: String,
+        // quote using this route instead of the stored one
+        route: Option<Vec<MarketId>>,
+        // quote with protocol fee discount of this trader
+        trader: Option<Addr>,
+        // quote including fee of this integrator
+        integrator: Option<Integrator>,
     },
     GetAllRoutes {
         start_after: Option<(String, String)>,
         limit: Option<u32>,
     },
     GetConfig {},
+    // relayer fee rebates received by the contract, by denom
+    GetAccruedRebates {},
+    // protocol fees deposited to the burn auction, by denom
+    GetBurnAuctionContributions {},
+    // sources of the buffer funds held by the contract, by denom
+    GetBufferLedger {},
+    GetBufferCaps {},
+    // fees and rounding of the buffer since the baseline, valued with the configured price sources
+    GetBufferPnl {},
+    // withdrawal reserves by denom and whether emergency mode is on
+    GetWithdrawalReserves {},
+    // protocol fees waiting for TopUpBuffers, by denom
+    GetAccruedProtocolFees {},
+    // configured buffers with their balances
+    GetBuffers {},
+    // buffer shares of the depositor and what they are worth, by denom
+    GetBufferPositions {
+        depositor: Addr,
+    },
+    GetMaxSwapSize {
+        source_denom: String,
+        target_denom: String,
+        route: Option<Vec<MarketId>>,
+        // quote with protocol fee discount of this trader
+        trader: Option<Addr>,
+        // quote including fee of this integrator
+        integrator: Option<Integrator>,
+    },
+    GetPriceLadder {
+        source_denom: String,
+        target_denom: String,
+        // input quantities of source denom to quote
+        sizes: Vec<FPDecimal>,
+        // quote using this route instead of the stored one
+        route: Option<Vec<MarketId>>,
+        trader: Option<Addr>,
+        integrator: Option<Integrator>,
+    },
+    GetFeeTiers {},
+    GetFeeExemptAddresses {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    IsFeeExempt {
+        address: Addr,
+    },
+    GetBufferKeepers {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    GetExemptBufferBudget {},
+    // rolling 30-day swap volume of the trader and protocol fee discount it qualifies for
+    GetTraderVolume {
+        trader: Addr,
+    },
+    GetHypotheticalSwapResult {
+        source_denom: String,
+        target_denom: String,
+        swap_quantity: SwapQuantity,
+        // markets of the route, in order from source to target denom
+        markets: Vec<HypotheticalMarket>,
+        // buffer used for rounding, contract balances are used if not provided. Buffer caps and critical balances do not apply
+        buffer: Option<Vec<FPCoin>>,
+        trader: Option<Addr>,
+        integrator: Option<Integrator>,
+    },
 }
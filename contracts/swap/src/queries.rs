@@ -1,12 +1,22 @@
-use cosmwasm_std::{Addr, Deps, Env, StdError, StdResult};
-use injective_cosmwasm::{InjectiveQuerier, InjectiveQueryWrapper, MarketId, OrderSide, PriceLevel, SpotMarket};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{ensure, Addr, Deps, Env, StdError, StdResult};
+use injective_cosmwasm::{InjectiveQueryWrapper, MarketId, OrderSide, PriceLevel, SpotMarket};
 use injective_math::utils::round_to_min_tick;
 use injective_math::FPDecimal;
 
+use crate::admin::{validate_route, validate_route_with_markets};
+use crate::fees::{bps_to_dec, get_output_before_fees, get_output_fee_rates, get_output_fees, get_tier_discount_bps, OutputFees};
 use crate::helpers::round_up_to_min_tick;
-use crate::state::{read_swap_route, CONFIG};
-use crate::types::{FPCoin, StepExecutionEstimate, SwapEstimationAmount, SwapEstimationResult};
-
+use crate::market_data::{CachedMarketData, HypotheticalMarketData, LiveMarketData, MarketDataSource, TraderMarketData};
+use crate::msg::FeeRecipient;
+use crate::state::{get_trader_volume, read_swap_route, CONFIG, FEE_TIERS};
+use crate::types::{
+    FPCoin, HypotheticalMarket, Integrator, MaxSwapSizeResponse, OutputFeeRates, PriceLadderPoint, StepExecutionEstimate, SwapEstimationAmount,
+    SwapEstimationResult, SwapRoute, TraderVolumeResponse,
+};
+use crate::ContractError;
+
+#[cw_serde]
 pub enum SwapQuantity {
     InputQuantity(FPDecimal),
     OutputQuantity(FPDecimal),
@@ -18,22 +28,59 @@ pub fn estimate_swap_result(
     source_denom: String,
     target_denom: String,
     swap_quantity: SwapQuantity,
-) -> StdResult<SwapEstimationResult> {
+    fee_rates: &OutputFeeRates,
+) -> Result<SwapEstimationResult, ContractError> {
+    let route = read_swap_route(deps.storage, &source_denom, &target_denom)?;
+
+    estimate_swap_result_for_route(
+        deps,
+        env,
+        &LiveMarketData::new(deps, env),
+        &route,
+        source_denom,
+        target_denom,
+        swap_quantity,
+        fee_rates,
+    )
+}
+
+// Returns the route passed by the caller (validated the same way as in set_route) or the stored one, if none was passed
+pub fn resolve_swap_route(
+    deps: Deps<InjectiveQueryWrapper>,
+    source_denom: &str,
+    target_denom: &str,
+    route: Option<Vec<MarketId>>,
+) -> StdResult<SwapRoute> {
+    match route {
+        Some(steps) => Ok(validate_route(deps, source_denom.to_string(), target_denom.to_string(), steps)?),
+        None => read_swap_route(deps.storage, source_denom, target_denom),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn estimate_swap_result_for_route(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    market_data: &dyn MarketDataSource,
+    route: &SwapRoute,
+    source_denom: String,
+    target_denom: String,
+    swap_quantity: SwapQuantity,
+    fee_rates: &OutputFeeRates,
+) -> Result<SwapEstimationResult, ContractError> {
     match swap_quantity {
         SwapQuantity::InputQuantity(quantity) => {
             if quantity.is_zero() || quantity.is_negative() {
-                return Err(StdError::generic_err("source_quantity must be positive"));
+                return Err(StdError::generic_err("source_quantity must be positive").into());
             }
         }
         SwapQuantity::OutputQuantity(quantity) => {
             if quantity.is_zero() || quantity.is_negative() {
-                return Err(StdError::generic_err("target_quantity must be positive"));
+                return Err(StdError::generic_err("target_quantity must be positive").into());
             }
         }
     }
 
-    let route = read_swap_route(deps.storage, &source_denom, &target_denom)?;
-
     let (steps, mut current_swap) = match swap_quantity {
         SwapQuantity::InputQuantity(quantity) => (
             route.steps_from(&source_denom),
@@ -48,20 +95,23 @@ pub fn estimate_swap_result(
             (
                 steps,
                 FPCoin {
-                    amount: quantity,
-                    denom: target_denom,
+                    amount: get_output_before_fees(quantity, fee_rates),
+                    denom: target_denom.clone(),
                 },
             )
         }
     };
 
+    let fee_recipient = route.effective_fee_recipient(&CONFIG.load(deps.storage)?);
     let mut fees: Vec<FPCoin> = vec![];
 
     for step in steps {
         let swap_estimate = estimate_single_swap_execution(
             &deps,
             env,
+            market_data,
             &step,
+            &fee_recipient,
             match swap_quantity {
                 SwapQuantity::InputQuantity(_) => SwapEstimationAmount::InputQuantity(current_swap.clone()),
                 SwapQuantity::OutputQuantity(_) => SwapEstimationAmount::ReceiveQuantity(current_swap.clone()),
@@ -77,39 +127,295 @@ pub fn estimate_swap_result(
         fees.push(step_fee);
     }
 
-    Ok(SwapEstimationResult {
-        expected_fees: fees,
-        result_quantity: current_swap.amount,
+    match swap_quantity {
+        SwapQuantity::InputQuantity(_) => {
+            let output_fees = get_output_fees(current_swap.amount, fee_rates);
+            push_output_fees(&mut fees, &output_fees, &target_denom);
+
+            Ok(SwapEstimationResult {
+                expected_fees: fees,
+                result_quantity: current_swap.amount - output_fees.total(),
+                input_padding: None,
+            })
+        }
+        SwapQuantity::OutputQuantity(quantity) => {
+            let output_fees = get_output_fees(get_output_before_fees(quantity, fee_rates), fee_rates);
+            push_output_fees(&mut fees, &output_fees, &target_denom);
+
+            let first_market_id = &route.steps_from(&source_denom)[0];
+            let required_input = get_required_input(market_data, first_market_id, &source_denom, current_swap.amount)?;
+
+            Ok(SwapEstimationResult {
+                expected_fees: fees,
+                result_quantity: required_input,
+                input_padding: Some(required_input - current_swap.amount),
+            })
+        }
+    }
+}
+
+fn push_output_fees(fees: &mut Vec<FPCoin>, output_fees: &OutputFees, target_denom: &str) {
+    for fee in [output_fees.protocol_fee, output_fees.integrator_fee] {
+        if fee > FPDecimal::ZERO {
+            fees.push(FPCoin {
+                amount: fee,
+                denom: target_denom.to_string(),
+            });
+        }
+    }
+}
+
+// Input that swap execution demands for an estimated input of an exact output swap. Quote inputs are rounded down
+// to an integer and increased by one, base inputs are rounded up to min quantity tick of the first market.
+pub fn get_required_input(
+    market_data: &dyn MarketDataSource,
+    first_market_id: &MarketId,
+    source_denom: &str,
+    estimated_input: FPDecimal,
+) -> StdResult<FPDecimal> {
+    let first_market = market_data.spot_market(first_market_id)?.expect("market should be available");
+
+    if first_market.quote_denom == source_denom {
+        Ok(estimated_input.int() + FPDecimal::ONE)
+    } else {
+        Ok(round_up_to_min_tick(estimated_input, first_market.min_quantity_tick_size))
+    }
+}
+
+pub fn get_max_swap_size(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    source_denom: String,
+    target_denom: String,
+    route: Option<Vec<MarketId>>,
+    trader: Option<&Addr>,
+    integrator: Option<&Integrator>,
+) -> StdResult<MaxSwapSizeResponse> {
+    let route = resolve_swap_route(deps, &source_denom, &target_denom, route)?;
+    let fee_rates = &get_output_fee_rates(deps, env, &route, trader, integrator)?;
+    // every orderbook is fetched once and shared by all estimations of the search
+    let live_market_data = LiveMarketData::new(deps, env);
+    let trader_market_data = TraderMarketData::new(deps, &live_market_data, trader)?;
+    let market_data = CachedMarketData::new(&trader_market_data);
+    let (input_depth, output_depth) = get_route_depth(&market_data, &route, &source_denom, &target_denom)?;
+
+    let estimate = |swap_quantity: SwapQuantity| {
+        estimate_swap_result_for_route(
+            deps,
+            env,
+            &market_data,
+            &route,
+            source_denom.clone(),
+            target_denom.clone(),
+            swap_quantity,
+            fee_rates,
+        )
+    };
+
+    // fees and rounding padding never double the input a swap takes from the first orderbook
+    let max_input_quantity = find_max_supported_quantity(input_depth * FPDecimal::TWO, |quantity| estimate(SwapQuantity::InputQuantity(quantity)))?;
+    let max_output_quantity = find_max_supported_quantity(output_depth, |quantity| estimate(SwapQuantity::OutputQuantity(quantity)))?;
+
+    Ok(MaxSwapSizeResponse {
+        max_input_quantity,
+        max_output_quantity,
     })
 }
 
+// Liquidity the first orderbook of the route offers for the source denom and the last one offers of the target denom,
+// neither can be swapped beyond
+fn get_route_depth(
+    market_data: &dyn MarketDataSource,
+    route: &SwapRoute,
+    source_denom: &str,
+    target_denom: &str,
+) -> StdResult<(FPDecimal, FPDecimal)> {
+    let steps = route.steps_from(source_denom);
+    let market = |market_id: &MarketId| -> StdResult<SpotMarket> {
+        market_data
+            .spot_market(market_id)?
+            .ok_or_else(|| StdError::generic_err(format!("Market {} not found", market_id.as_str())))
+    };
+    let depth = |market: &SpotMarket, order_side: OrderSide, calc: fn(&PriceLevel) -> FPDecimal| -> StdResult<FPDecimal> {
+        Ok(market_data
+            .orderbook(&market.market_id, order_side, None, None)?
+            .iter()
+            .fold(FPDecimal::ZERO, |sum, level| sum + calc(level)))
+    };
+
+    let first_market = market(steps.first().expect("route should have steps"))?;
+    let input_depth = if first_market.base_denom == source_denom {
+        depth(&first_market, OrderSide::Buy, |level| level.q)?
+    } else {
+        depth(&first_market, OrderSide::Sell, |level| level.q * level.p)?
+    };
+
+    let last_market = market(steps.last().expect("route should have steps"))?;
+    let output_depth = if last_market.base_denom == target_denom {
+        depth(&last_market, OrderSide::Sell, |level| level.q)?
+    } else {
+        depth(&last_market, OrderSide::Buy, |level| level.q * level.p)?
+    };
+
+    Ok((input_depth, output_depth))
+}
+
+// Estimates swapping each of the sizes over the stored route. Every orderbook is fetched once and shared by all sizes.
+#[allow(clippy::too_many_arguments)]
+pub fn get_price_ladder(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    source_denom: String,
+    target_denom: String,
+    sizes: Vec<FPDecimal>,
+    route: Option<Vec<MarketId>>,
+    trader: Option<&Addr>,
+    integrator: Option<&Integrator>,
+) -> StdResult<Vec<PriceLadderPoint>> {
+    let route = resolve_swap_route(deps, &source_denom, &target_denom, route)?;
+    let fee_rates = &get_output_fee_rates(deps, env, &route, trader, integrator)?;
+    let live_market_data = LiveMarketData::new(deps, env);
+    let trader_market_data = TraderMarketData::new(deps, &live_market_data, trader)?;
+    let market_data = CachedMarketData::new(&trader_market_data);
+
+    sizes
+        .into_iter()
+        .map(|size| {
+            let estimation = estimate_swap_result_for_route(
+                deps,
+                env,
+                &market_data,
+                &route,
+                source_denom.clone(),
+                target_denom.clone(),
+                SwapQuantity::InputQuantity(size),
+                fee_rates,
+            );
+            // sizes the orderbooks or the buffer cannot take have no point, other errors fail the whole ladder
+            let result_quantity = match estimation {
+                Ok(estimation) => Some(estimation.result_quantity),
+                Err(err) if err.is_size_limit() => None,
+                Err(err) => return Err(err.into()),
+            };
+
+            Ok(PriceLadderPoint {
+                size,
+                result_quantity,
+                effective_rate: result_quantity.map(|quantity| quantity / size),
+            })
+        })
+        .collect()
+}
+
+// Runs the same estimation as GetOutputQuantity/GetInputQuantity, but against caller-supplied markets and orderbooks.
+// Route goes through the passed markets in order and is validated the same way as in set_route.
+#[allow(clippy::too_many_arguments)]
+pub fn get_hypothetical_swap_result(
+    deps: Deps<InjectiveQueryWrapper>,
+    env: &Env,
+    source_denom: String,
+    target_denom: String,
+    swap_quantity: SwapQuantity,
+    markets: Vec<HypotheticalMarket>,
+    buffer: Option<Vec<FPCoin>>,
+    trader: Option<&Addr>,
+    integrator: Option<&Integrator>,
+) -> StdResult<SwapEstimationResult> {
+    let steps = markets.iter().map(|market| market.market.market_id.to_owned()).collect();
+    let hypothetical_market_data = HypotheticalMarketData::new(deps, env, markets, buffer)?;
+    let market_data = TraderMarketData::new(deps, &hypothetical_market_data, trader)?;
+
+    let route = validate_route_with_markets(source_denom.to_owned(), target_denom.to_owned(), steps, |market_id| {
+        market_data.spot_market(market_id)
+    })?;
+    let fee_rates = &get_output_fee_rates(deps, env, &route, trader, integrator)?;
+
+    Ok(estimate_swap_result_for_route(
+        deps,
+        env,
+        &market_data,
+        &route,
+        source_denom,
+        target_denom,
+        swap_quantity,
+        fee_rates,
+    )?)
+}
+
+pub fn get_trader_volume_response(deps: Deps<InjectiveQueryWrapper>, env: &Env, trader: &Addr) -> StdResult<TraderVolumeResponse> {
+    let volume = get_trader_volume(deps.storage, trader, env.block.time)?;
+    let discount_bps = FEE_TIERS
+        .may_load(deps.storage)?
+        .map_or(0, |schedule| get_tier_discount_bps(&schedule, volume));
+
+    Ok(TraderVolumeResponse { volume, discount_bps })
+}
+
+// Bank amounts are integers, so we look for the largest whole quantity for which estimation succeeds by bisecting up to
+// the liquidity of the orderbooks. Estimations failing with a size limit, like lack of liquidity or buffer caps, mean the
+// quantity is too large, other errors are returned.
+fn find_max_supported_quantity(
+    depth: FPDecimal,
+    estimate: impl Fn(FPDecimal) -> Result<SwapEstimationResult, ContractError>,
+) -> StdResult<FPDecimal> {
+    let is_supported = |quantity: FPDecimal| match estimate(quantity) {
+        Ok(_) => Ok(true),
+        Err(err) if err.is_size_limit() => Ok(false),
+        Err(err) => Err(err),
+    };
+
+    let mut lower = FPDecimal::ZERO;
+    let mut upper = depth.int() + FPDecimal::ONE;
+    if is_supported(upper)? {
+        return Ok(upper);
+    }
+
+    while upper - lower > FPDecimal::ONE {
+        let middle = ((lower + upper) / FPDecimal::TWO).int();
+
+        if is_supported(middle)? {
+            lower = middle;
+        } else {
+            upper = middle;
+        }
+    }
+
+    Ok(lower)
+}
+
 pub fn estimate_single_swap_execution(
     deps: &Deps<InjectiveQueryWrapper>,
     env: &Env,
+    market_data: &dyn MarketDataSource,
     market_id: &MarketId,
+    fee_recipient: &FeeRecipient,
     swap_estimation_amount: SwapEstimationAmount,
     is_simulation: bool,
-) -> StdResult<StepExecutionEstimate> {
-    let querier = InjectiveQuerier::new(&deps.querier);
-
+) -> Result<StepExecutionEstimate, ContractError> {
     let balance_in = match swap_estimation_amount.to_owned() {
         SwapEstimationAmount::InputQuantity(fp) => fp,
         SwapEstimationAmount::ReceiveQuantity(fp) => fp,
     };
 
-    let market = querier.query_spot_market(market_id)?.market.expect("market should be available");
+    let market = market_data.spot_market(market_id)?.expect("market should be available");
 
     let has_invalid_denom = balance_in.denom != market.quote_denom && balance_in.denom != market.base_denom;
     if has_invalid_denom {
-        return Err(StdError::generic_err("Invalid swap denom - neither base nor quote"));
+        return Err(StdError::generic_err("Invalid swap denom - neither base nor quote").into());
     }
 
-    let config = CONFIG.load(deps.storage)?;
-    let is_self_relayer = config.fee_recipient == env.contract.address;
+    let is_self_relayer = match fee_recipient {
+        FeeRecipient::Address(addr) => addr == env.contract.address,
+        FeeRecipient::SwapContract => true,
+        FeeRecipient::Sender => false,
+    };
 
-    let fee_multiplier = querier.query_market_atomic_execution_fee_multiplier(market_id)?.multiplier;
+    let fee_multiplier = market_data.atomic_execution_fee_multiplier(market_id)?;
 
-    let fee_percent = market.taker_fee_rate * fee_multiplier * (FPDecimal::ONE - get_effective_fee_discount_rate(&market, is_self_relayer));
+    let depositor_rebate_share_bps = CONFIG.load(deps.storage)?.depositor_rebate_share_bps;
+    let fee_percent = market.taker_fee_rate
+        * fee_multiplier
+        * (FPDecimal::ONE - get_effective_fee_discount_rate(&market, is_self_relayer, depositor_rebate_share_bps));
 
     let is_estimating_from_target = matches!(swap_estimation_amount, SwapEstimationAmount::ReceiveQuantity(_));
 
@@ -119,43 +425,37 @@ pub fn estimate_single_swap_execution(
         balance_in.denom != market.base_denom
     };
 
-    if is_buy {
-        estimate_execution_buy(
-            deps,
-            &querier,
-            &env.contract.address,
-            &market,
-            swap_estimation_amount,
-            fee_percent,
-            is_simulation,
-        )
+    let mut estimate = if is_buy {
+        estimate_execution_buy(deps, market_data, &market, swap_estimation_amount, fee_percent, is_simulation)
     } else {
-        estimate_execution_sell(deps, &querier, &market, swap_estimation_amount, fee_percent)
+        estimate_execution_sell(deps, market_data, &market, swap_estimation_amount, fee_percent)
+    }?;
+
+    // full fee is charged to the contract, but the exchange pays the relayer share of it straight to the swapper
+    if *fee_recipient == FeeRecipient::Sender {
+        if let Some(fee_estimate) = estimate.fee_estimate.as_mut() {
+            fee_estimate.amount *= FPDecimal::ONE - market.relayer_fee_share_rate;
+        }
     }
+
+    Ok(estimate)
 }
 
 fn estimate_execution_buy_from_source(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
-    contract_address: &Addr,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     input_quote_quantity: FPDecimal,
     fee_percent: FPDecimal,
     is_simulation: bool,
-) -> StdResult<StepExecutionEstimate> {
+) -> Result<StepExecutionEstimate, ContractError> {
     let available_swap_quote_funds = input_quote_quantity / (FPDecimal::ONE + fee_percent);
 
-    let orders = querier.query_spot_market_orderbook(&market.market_id, OrderSide::Sell, None, Some(available_swap_quote_funds))?;
-    let top_orders = get_minimum_liquidity_levels(
-        deps,
-        &orders.sells_price_level,
-        available_swap_quote_funds,
-        |l| l.q * l.p,
-        market.min_quantity_tick_size,
-    )?;
+    let orders = market_data.orderbook(&market.market_id, OrderSide::Sell, None, Some(available_swap_quote_funds))?;
+    let top_orders = get_minimum_liquidity_levels(deps, &orders, available_swap_quote_funds, |l| l.q * l.p, market.min_quantity_tick_size)?;
 
     // lets overestimate amount for buys means rounding average price up -> higher buy price -> worse
-    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, true);
+    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, true)?;
     let worst_price = get_worst_price_from_orders(&top_orders);
 
     let expected_base_quantity = available_swap_quote_funds / average_price;
@@ -164,12 +464,8 @@ fn estimate_execution_buy_from_source(
 
     // check if user funds + contract funds are enough to create order
     let required_funds = worst_price * expected_base_quantity * (FPDecimal::ONE + fee_percent);
-    let funds_in_contract = deps
-        .querier
-        .query_balance(contract_address, &market.quote_denom)
-        .expect("query own balance should not fail")
-        .amount
-        .into();
+    let buffer_needed = check_buffer_budget(market_data, &market.quote_denom, required_funds, input_quote_quantity, is_simulation)?;
+    let funds_in_contract = market_data.buffer_balance(&market.quote_denom)?;
 
     let funds_for_margin = match is_simulation {
         false => funds_in_contract, // in execution mode funds_in_contract already contain user funds so we don't want to count them double
@@ -177,9 +473,11 @@ fn estimate_execution_buy_from_source(
     };
 
     if required_funds > funds_for_margin {
-        return Err(StdError::generic_err(format!(
-            "Swap amount too high, required funds: {required_funds}, available funds: {funds_for_margin}",
-        )));
+        return Err(ContractError::SwapAmountTooHigh {
+            denom: market.quote_denom.to_owned(),
+            required: required_funds,
+            available: funds_for_margin,
+        });
     }
 
     Ok(StepExecutionEstimate {
@@ -191,31 +489,79 @@ fn estimate_execution_buy_from_source(
             denom: market.quote_denom.clone(),
             amount: fee_estimate,
         }),
+        buffer_needed,
     })
 }
 
+// Part of the funds locked for a buy that is not covered by the swapper has to come from the buffer. Quotes and executed
+// swaps both go through this check, so a quote never promises a swap that execution rejects.
+fn check_buffer_budget(
+    market_data: &dyn MarketDataSource,
+    denom: &str,
+    required_funds: FPDecimal,
+    swapper_funds: FPDecimal,
+    is_simulation: bool,
+) -> Result<FPDecimal, ContractError> {
+    let buffer_needed = (required_funds - swapper_funds).max(FPDecimal::ZERO);
+
+    if let Some(budget) = market_data.buffer_budget(denom)? {
+        ensure!(
+            buffer_needed <= budget,
+            ContractError::ExemptBufferBudgetExceeded {
+                denom: denom.to_string(),
+                required: buffer_needed,
+                budget,
+            }
+        );
+    }
+
+    if let Some(cap) = market_data.buffer_cap(denom)? {
+        ensure!(
+            buffer_needed <= cap,
+            ContractError::BufferCapExceeded {
+                denom: denom.to_string(),
+                required: buffer_needed,
+                available: cap,
+            }
+        );
+    }
+
+    // below its critical balance a buffer only covers buys needing at most critical_max_use_bps of it, funds of an
+    // executing swap are already held by the contract but are not buffer
+    if let Some(buffer) = market_data.buffer_denom(denom)? {
+        let held_swapper_funds = if is_simulation { FPDecimal::ZERO } else { swapper_funds };
+        let balance = market_data.buffer_balance(denom)? - held_swapper_funds;
+        if buffer.is_critical(balance) {
+            let available = balance.max(FPDecimal::ZERO) * bps_to_dec(buffer.critical_max_use_bps);
+            ensure!(
+                buffer_needed <= available,
+                ContractError::BufferCritical {
+                    denom: denom.to_string(),
+                    required: buffer_needed,
+                    available,
+                }
+            );
+        }
+    }
+
+    Ok(buffer_needed)
+}
+
 fn estimate_execution_buy_from_target(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
-    contract_address: &Addr,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     target_base_output_quantity: FPDecimal,
     fee_percent: FPDecimal,
     is_simulation: bool,
-) -> StdResult<StepExecutionEstimate> {
+) -> Result<StepExecutionEstimate, ContractError> {
     let rounded_target_base_output_quantity = round_up_to_min_tick(target_base_output_quantity, market.min_quantity_tick_size);
 
-    let orders = querier.query_spot_market_orderbook(&market.market_id, OrderSide::Sell, Some(rounded_target_base_output_quantity), None)?;
-    let top_orders = get_minimum_liquidity_levels(
-        deps,
-        &orders.sells_price_level,
-        rounded_target_base_output_quantity,
-        |l| l.q,
-        market.min_quantity_tick_size,
-    )?;
+    let orders = market_data.orderbook(&market.market_id, OrderSide::Sell, Some(rounded_target_base_output_quantity), None)?;
+    let top_orders = get_minimum_liquidity_levels(deps, &orders, rounded_target_base_output_quantity, |l| l.q, market.min_quantity_tick_size)?;
 
     // lets overestimate amount for buys means rounding average price up -> higher buy price -> worse
-    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, true);
+    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, true)?;
     let worst_price = get_worst_price_from_orders(&top_orders);
 
     let expected_exchange_quote_quantity = rounded_target_base_output_quantity * average_price;
@@ -224,13 +570,15 @@ fn estimate_execution_buy_from_target(
 
     // check if user funds + contract funds are enough to create order
     let required_funds = worst_price * rounded_target_base_output_quantity * (FPDecimal::ONE + fee_percent);
+    let buffer_needed = check_buffer_budget(
+        market_data,
+        &market.quote_denom,
+        required_funds,
+        required_input_quote_quantity,
+        is_simulation,
+    )?;
 
-    let funds_in_contract = deps
-        .querier
-        .query_balance(contract_address, &market.quote_denom)
-        .expect("query own balance should not fail")
-        .amount
-        .into();
+    let funds_in_contract = market_data.buffer_balance(&market.quote_denom)?;
 
     let funds_for_margin = match is_simulation {
         false => funds_in_contract, // in execution mode funds_in_contract already contain user funds so we don't want to count them double
@@ -238,9 +586,11 @@ fn estimate_execution_buy_from_target(
     };
 
     if required_funds > funds_for_margin {
-        return Err(StdError::generic_err(format!(
-            "Swap amount too high, required funds: {required_funds}, available funds: {funds_for_margin}",
-        )));
+        return Err(ContractError::SwapAmountTooHigh {
+            denom: market.quote_denom.to_owned(),
+            required: required_funds,
+            available: funds_for_margin,
+        });
     }
 
     Ok(StepExecutionEstimate {
@@ -252,18 +602,18 @@ fn estimate_execution_buy_from_target(
             denom: market.quote_denom.clone(),
             amount: fee_estimate,
         }),
+        buffer_needed,
     })
 }
 
 fn estimate_execution_buy(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
-    contract_address: &Addr,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     swap_estimation_amount: SwapEstimationAmount,
     fee_percent: FPDecimal,
     is_simulation: bool,
-) -> StdResult<StepExecutionEstimate> {
+) -> Result<StepExecutionEstimate, ContractError> {
     let amount_coin = match swap_estimation_amount.to_owned() {
         SwapEstimationAmount::InputQuantity(fp) => fp,
         SwapEstimationAmount::ReceiveQuantity(fp) => fp,
@@ -272,31 +622,25 @@ fn estimate_execution_buy(
     let is_estimating_from_target = matches!(swap_estimation_amount, SwapEstimationAmount::ReceiveQuantity(_));
 
     if is_estimating_from_target {
-        estimate_execution_buy_from_target(deps, querier, contract_address, market, amount_coin.amount, fee_percent, is_simulation)
+        estimate_execution_buy_from_target(deps, market_data, market, amount_coin.amount, fee_percent, is_simulation)
     } else {
-        estimate_execution_buy_from_source(deps, querier, contract_address, market, amount_coin.amount, fee_percent, is_simulation)
+        estimate_execution_buy_from_source(deps, market_data, market, amount_coin.amount, fee_percent, is_simulation)
     }
 }
 
 fn estimate_execution_sell_from_source(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     input_base_quantity: FPDecimal,
     fee_percent: FPDecimal,
-) -> StdResult<StepExecutionEstimate> {
-    let orders = querier.query_spot_market_orderbook(&market.market_id, OrderSide::Buy, Some(input_base_quantity), None)?;
+) -> Result<StepExecutionEstimate, ContractError> {
+    let orders = market_data.orderbook(&market.market_id, OrderSide::Buy, Some(input_base_quantity), None)?;
 
-    let top_orders = get_minimum_liquidity_levels(
-        deps,
-        &orders.buys_price_level,
-        input_base_quantity,
-        |l| l.q,
-        market.min_quantity_tick_size,
-    )?;
+    let top_orders = get_minimum_liquidity_levels(deps, &orders, input_base_quantity, |l| l.q, market.min_quantity_tick_size)?;
 
     // lets overestimate amount for sells means rounding average price down -> lower sell price -> worse
-    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, false);
+    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, false)?;
     let worst_price = get_worst_price_from_orders(&top_orders);
 
     let expected_exchange_quantity = input_base_quantity * average_price;
@@ -312,30 +656,31 @@ fn estimate_execution_sell_from_source(
             denom: market.quote_denom.clone(),
             amount: fee_estimate,
         }),
+        buffer_needed: FPDecimal::ZERO,
     })
 }
 
 fn estimate_execution_sell_from_target(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     target_quote_output_quantity: FPDecimal,
     fee_percent: FPDecimal,
-) -> StdResult<StepExecutionEstimate> {
+) -> Result<StepExecutionEstimate, ContractError> {
     let required_swap_quantity_in_quote = target_quote_output_quantity / (FPDecimal::ONE - fee_percent);
     let required_fee = required_swap_quantity_in_quote - target_quote_output_quantity;
 
-    let orders = querier.query_spot_market_orderbook(&market.market_id, OrderSide::Buy, None, Some(required_swap_quantity_in_quote))?;
+    let orders = market_data.orderbook(&market.market_id, OrderSide::Buy, None, Some(required_swap_quantity_in_quote))?;
     let top_orders = get_minimum_liquidity_levels(
         deps,
-        &orders.buys_price_level,
+        &orders,
         required_swap_quantity_in_quote,
         |l| l.q * l.p,
         market.min_quantity_tick_size,
     )?;
 
     // lets overestimate amount for sells means rounding average price down -> lower sell price -> worse
-    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, false);
+    let average_price = get_average_price_from_orders(&top_orders, market.min_price_tick_size, false)?;
     let worst_price = get_worst_price_from_orders(&top_orders);
 
     let required_swap_input_quantity_in_base = required_swap_quantity_in_quote / average_price;
@@ -349,16 +694,17 @@ fn estimate_execution_sell_from_target(
             denom: market.quote_denom.clone(),
             amount: required_fee,
         }),
+        buffer_needed: FPDecimal::ZERO,
     })
 }
 
 fn estimate_execution_sell(
     deps: &Deps<InjectiveQueryWrapper>,
-    querier: &InjectiveQuerier,
+    market_data: &dyn MarketDataSource,
     market: &SpotMarket,
     swap_estimation_amount: SwapEstimationAmount,
     fee_percent: FPDecimal,
-) -> StdResult<StepExecutionEstimate> {
+) -> Result<StepExecutionEstimate, ContractError> {
     let amount_coin = match swap_estimation_amount.to_owned() {
         SwapEstimationAmount::InputQuantity(fp) => fp,
         SwapEstimationAmount::ReceiveQuantity(fp) => fp,
@@ -367,9 +713,9 @@ fn estimate_execution_sell(
     let is_estimating_from_target = matches!(swap_estimation_amount, SwapEstimationAmount::ReceiveQuantity(_));
 
     if is_estimating_from_target {
-        estimate_execution_sell_from_target(deps, querier, market, amount_coin.amount, fee_percent)
+        estimate_execution_sell_from_target(deps, market_data, market, amount_coin.amount, fee_percent)
     } else {
-        estimate_execution_sell_from_source(deps, querier, market, amount_coin.amount, fee_percent)
+        estimate_execution_sell_from_source(deps, market_data, market, amount_coin.amount, fee_percent)
     }
 }
 
@@ -379,13 +725,18 @@ pub fn get_minimum_liquidity_levels(
     total: FPDecimal,
     calc: fn(&PriceLevel) -> FPDecimal,
     min_quantity_tick_size: FPDecimal,
-) -> StdResult<Vec<PriceLevel>> {
+) -> Result<Vec<PriceLevel>, ContractError> {
     let mut sum = FPDecimal::ZERO;
     let mut orders: Vec<PriceLevel> = Vec::new();
 
     for level in levels {
         let value = calc(level);
-        assert_ne!(value, FPDecimal::ZERO, "Price level with zero value, this should not happen");
+        ensure!(
+            !value.is_zero(),
+            ContractError::CustomError {
+                val: "Price level with zero value".to_string()
+            }
+        );
 
         let order_to_add = if sum + value > total {
             let excess = value + sum - total;
@@ -411,40 +762,42 @@ pub fn get_minimum_liquidity_levels(
     }
 
     if sum < total {
-        return Err(StdError::generic_err("Not enough liquidity to fulfill order"));
+        return Err(ContractError::InsufficientLiquidity {});
     }
 
     Ok(orders)
 }
 
-fn get_average_price_from_orders(levels: &[PriceLevel], min_price_tick_size: FPDecimal, is_rounding_up: bool) -> FPDecimal {
+fn get_average_price_from_orders(levels: &[PriceLevel], min_price_tick_size: FPDecimal, is_rounding_up: bool) -> Result<FPDecimal, ContractError> {
     let (total_quantity, total_notional) = levels
         .iter()
         .fold((FPDecimal::ZERO, FPDecimal::ZERO), |acc, pl| (acc.0 + pl.q, acc.1 + pl.p * pl.q));
 
-    assert_ne!(
-        total_quantity,
-        FPDecimal::ZERO,
-        "total_quantity was zero and would result in division by zero"
+    ensure!(
+        !total_quantity.is_zero(),
+        ContractError::CustomError {
+            val: "Price levels with zero total quantity have no average price".to_string()
+        }
     );
     let average_price = total_notional / total_quantity;
 
-    if is_rounding_up {
+    Ok(if is_rounding_up {
         round_up_to_min_tick(average_price, min_price_tick_size)
     } else {
         round_to_min_tick(average_price, min_price_tick_size)
-    }
+    })
 }
 
 fn get_worst_price_from_orders(levels: &[PriceLevel]) -> FPDecimal {
     levels.last().unwrap().p // assume there's at least one element
 }
 
-fn get_effective_fee_discount_rate(market: &SpotMarket, is_self_relayer: bool) -> FPDecimal {
+// Part of the relayer fee rebate passed on to the swapper, the depositor share of it stays in the buffer
+fn get_effective_fee_discount_rate(market: &SpotMarket, is_self_relayer: bool, depositor_rebate_share_bps: u16) -> FPDecimal {
     if !is_self_relayer {
         FPDecimal::ZERO
     } else {
-        market.relayer_fee_share_rate
+        market.relayer_fee_share_rate * (FPDecimal::ONE - bps_to_dec(depositor_rebate_share_bps))
     }
 }
 
@@ -460,7 +813,7 @@ mod tests {
     fn test_average_price_simple() {
         let levels = vec![create_price_level(1, 200), create_price_level(2, 200), create_price_level(3, 200)];
 
-        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), false);
+        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), false).unwrap();
         assert_eq!(avg, FPDecimal::from(2u128));
     }
 
@@ -468,7 +821,7 @@ mod tests {
     fn test_average_price_simple_round_down() {
         let levels = vec![create_price_level(1, 300), create_price_level(2, 200), create_price_level(3, 100)];
 
-        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), false);
+        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), false).unwrap();
         assert_eq!(avg, FPDecimal::must_from_str("1.66")); //we round down
     }
 
@@ -476,7 +829,7 @@ mod tests {
     fn test_average_price_simple_round_up() {
         let levels = vec![create_price_level(1, 300), create_price_level(2, 200), create_price_level(3, 100)];
 
-        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), true);
+        let avg = get_average_price_from_orders(&levels, FPDecimal::must_from_str("0.01"), true).unwrap();
         assert_eq!(avg, FPDecimal::must_from_str("1.67")); //we round up
     }
 
@@ -499,8 +852,7 @@ mod tests {
             |l| l.q,
             FPDecimal::must_from_str("0.01"),
         );
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), StdError::generic_err("Not enough liquidity to fulfill order"));
+        assert!(matches!(result, Err(ContractError::InsufficientLiquidity {})));
     }
 
     #[test]
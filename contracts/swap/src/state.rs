@@ -1,18 +1,116 @@
-use crate::types::{Config, CurrentSwapOperation, CurrentSwapStep, SwapResults, SwapRoute};
+use crate::fees::{BPS_DENOMINATOR, MAX_INTEGRATOR_FEE_BPS, MAX_PROTOCOL_FEE_BPS};
+use crate::types::{
+    BufferCap, BufferDenom, BufferLedger, BufferLedgerEntry, BufferPnlBaseline, BufferPosition, BufferPricing, BufferRebalance, Config,
+    CurrentSwapOperation, CurrentSwapStep, FPCoin, FeeConversion, FeeTierSchedule, SwapResults, SwapRoute, WithdrawalReservesResponse,
+};
 
-use cosmwasm_std::{Order, StdError, StdResult, Storage};
+use cosmwasm_std::{Addr, Empty, Order, StdError, StdResult, Storage, Timestamp};
 use cw_storage_plus::{Bound, Item, Map};
+use injective_math::FPDecimal;
 
 pub const SWAP_ROUTES: Map<(String, String), SwapRoute> = Map::new("swap_routes");
 pub const SWAP_OPERATION_STATE: Item<CurrentSwapOperation> = Item::new("current_swap_cache");
 pub const STEP_STATE: Item<CurrentSwapStep> = Item::new("current_step_cache");
 pub const SWAP_RESULTS: Item<Vec<SwapResults>> = Item::new("swap_results");
+// rounding leftovers of the swap in progress that belong to the swapper, by denom
+pub const SWAP_DUST: Item<Vec<FPCoin>> = Item::new("swap_dust");
 pub const CONFIG: Item<Config> = Item::new("config");
+// relayer fee rebates received by the contract since it was deployed, by denom
+pub const ACCRUED_REBATES: Map<String, FPDecimal> = Map::new("accrued_rebates");
+// protocol fees deposited to the burn auction since it was deployed, by denom
+pub const BURN_AUCTION_CONTRIBUTIONS: Map<String, FPDecimal> = Map::new("burn_auction_contributions");
+pub const BUFFER_LEDGER: Map<String, BufferLedger> = Map::new("buffer_ledger");
+// treasury share of protocol fees kept in the contract until the next buffer top-up, by denom
+pub const ACCRUED_PROTOCOL_FEES: Map<String, FPDecimal> = Map::new("accrued_protocol_fees");
+// buffer shares of depositors, by depositor and denom
+pub const BUFFER_SHARES: Map<(String, &Addr), FPDecimal> = Map::new("buffer_shares");
+pub const TOTAL_BUFFER_SHARES: Map<String, FPDecimal> = Map::new("total_buffer_shares");
+pub const BUFFER_DENOMS: Map<String, BufferDenom> = Map::new("buffer_denoms");
+pub const BUFFER_CAPS: Map<String, BufferCap> = Map::new("buffer_caps");
+pub const BUFFER_PNL_BASELINE: Item<BufferPnlBaseline> = Item::new("buffer_pnl_baseline");
+pub const BUFFER_PRICE_SOURCES: Map<String, BufferPricing> = Map::new("buffer_price_sources");
+// block height and buffer used by swaps in that block, by denom
+pub const BUFFER_USAGE: Map<String, (u64, FPDecimal)> = Map::new("buffer_usage");
+// block height and amount of the latest withdrawals of depositors, by denom
+pub const BUFFER_WITHDRAWALS: Map<String, (u64, FPDecimal)> = Map::new("buffer_withdrawals");
+pub const FEE_CONVERSION: Item<FeeConversion> = Item::new("fee_conversion");
+pub const BUFFER_REBALANCE: Item<BufferRebalance> = Item::new("buffer_rebalance");
+pub const FEE_TIERS: Item<FeeTierSchedule> = Item::new("fee_tiers");
+// swap volume in reference denom of fee tiers, by trader and day
+pub const TRADER_VOLUMES: Map<(&Addr, u64), FPDecimal> = Map::new("trader_volumes");
+// volume of the swap in progress, set by the first step trading reference denom
+pub const SWAP_VOLUME: Item<FPDecimal> = Item::new("current_swap_volume");
+
+pub const FEE_EXEMPT_ADDRESSES: Map<&Addr, Empty> = Map::new("fee_exempt_addresses");
+// most of the buffer a single swap of a fee-exempt address may draw, by denom
+pub const EXEMPT_BUFFER_BUDGET: Map<String, FPDecimal> = Map::new("exempt_buffer_budget");
+// balance withdrawals of the admin cannot go below outside of emergency mode, by denom
+pub const WITHDRAWAL_RESERVES: Map<String, FPDecimal> = Map::new("withdrawal_reserves");
+pub const EMERGENCY_MODE: Item<bool> = Item::new("emergency_mode");
+// addresses besides the admin that may rebalance buffers between denoms
+pub const BUFFER_KEEPERS: Map<&Addr, Empty> = Map::new("buffer_keepers");
+
+pub const VOLUME_WINDOW_DAYS: u64 = 30;
+const SECONDS_PER_DAY: u64 = 86_400;
 
 pub const DEFAULT_LIMIT: u32 = 100u32;
 
 impl Config {
     pub fn validate(self) -> StdResult<()> {
+        if self.protocol_fee_bps > MAX_PROTOCOL_FEE_BPS {
+            return Err(StdError::generic_err(format!(
+                "Protocol fee cannot be higher than {MAX_PROTOCOL_FEE_BPS} bps"
+            )));
+        }
+
+        if self.max_integrator_fee_bps > MAX_INTEGRATOR_FEE_BPS {
+            return Err(StdError::generic_err(format!(
+                "Max integrator fee cannot be higher than {MAX_INTEGRATOR_FEE_BPS} bps"
+            )));
+        }
+
+        if self.burn_share_bps + self.depositor_fee_share_bps > BPS_DENOMINATOR {
+            return Err(StdError::generic_err(format!(
+                "Burn and depositor shares together cannot be higher than {BPS_DENOMINATOR} bps"
+            )));
+        }
+
+        if self.depositor_rebate_share_bps > BPS_DENOMINATOR {
+            return Err(StdError::generic_err(format!(
+                "Depositor rebate share cannot be higher than {BPS_DENOMINATOR} bps"
+            )));
+        }
+
+        if self.max_buffer_withdrawal_bps > BPS_DENOMINATOR {
+            return Err(StdError::generic_err(format!(
+                "Max buffer withdrawal cannot be higher than {BPS_DENOMINATOR} bps"
+            )));
+        }
+
+        if self.protocol_fee_bps > 0 && self.treasury.is_none() {
+            return Err(StdError::generic_err("Treasury must be set to charge protocol fee"));
+        }
+
+        Ok(())
+    }
+}
+
+impl FeeTierSchedule {
+    pub fn validate(&self) -> StdResult<()> {
+        if self.reference_denom.is_empty() {
+            return Err(StdError::generic_err("Reference denom cannot be empty"));
+        }
+
+        if self.tiers.iter().any(|tier| tier.discount_bps > BPS_DENOMINATOR) {
+            return Err(StdError::generic_err(format!(
+                "Fee tier discount cannot be higher than {BPS_DENOMINATOR} bps"
+            )));
+        }
+
+        if self.tiers.windows(2).any(|pair| pair[0].min_volume >= pair[1].min_volume) {
+            return Err(StdError::generic_err("Fee tiers must be ordered by strictly increasing min_volume"));
+        }
+
         Ok(())
     }
 }
@@ -48,6 +146,398 @@ pub fn get_all_swap_routes(storage: &dyn Storage, start_after: Option<(String, S
     Ok(routes)
 }
 
+pub fn record_rebate(storage: &mut dyn Storage, denom: &str, rebate: FPDecimal) -> StdResult<()> {
+    if rebate.is_zero() {
+        return Ok(());
+    }
+
+    ACCRUED_REBATES.update(storage, denom.to_string(), |accrued| -> StdResult<_> {
+        Ok(accrued.unwrap_or_default() + rebate)
+    })?;
+
+    Ok(())
+}
+
+pub fn get_accrued_rebates(storage: &dyn Storage) -> StdResult<Vec<FPCoin>> {
+    ACCRUED_REBATES
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| FPCoin { amount, denom }))
+        .collect()
+}
+
+pub fn record_burn_auction_contribution(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    BURN_AUCTION_CONTRIBUTIONS.update(storage, denom.to_string(), |contributed| -> StdResult<_> {
+        Ok(contributed.unwrap_or_default() + amount)
+    })?;
+
+    Ok(())
+}
+
+pub fn get_burn_auction_contributions(storage: &dyn Storage) -> StdResult<Vec<FPCoin>> {
+    BURN_AUCTION_CONTRIBUTIONS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| FPCoin { amount, denom }))
+        .collect()
+}
+
+pub fn record_swap_dust(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let mut dust = SWAP_DUST.may_load(storage)?.unwrap_or_default();
+    match dust.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount += amount,
+        None => dust.push(FPCoin {
+            amount,
+            denom: denom.to_string(),
+        }),
+    }
+
+    SWAP_DUST.save(storage, &dust)
+}
+
+pub fn record_accrued_protocol_fee(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    ACCRUED_PROTOCOL_FEES.update(storage, denom.to_string(), |accrued| -> StdResult<_> {
+        Ok(accrued.unwrap_or(FPDecimal::ZERO) + amount)
+    })?;
+
+    Ok(())
+}
+
+// removes amount from the accrued protocol fees of denom, dropping the entry once nothing is left
+pub fn take_accrued_protocol_fee(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    let accrued = ACCRUED_PROTOCOL_FEES.may_load(storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO);
+    if accrued < amount {
+        return Err(StdError::generic_err(format!(
+            "Cannot take {amount}{denom}, only {accrued}{denom} of protocol fees accrued"
+        )));
+    }
+
+    if accrued == amount {
+        ACCRUED_PROTOCOL_FEES.remove(storage, denom.to_string());
+    } else {
+        ACCRUED_PROTOCOL_FEES.save(storage, denom.to_string(), &(accrued - amount))?;
+    }
+
+    Ok(())
+}
+
+pub fn get_accrued_protocol_fees(storage: &dyn Storage) -> StdResult<Vec<FPCoin>> {
+    ACCRUED_PROTOCOL_FEES
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| FPCoin { amount, denom }))
+        .collect()
+}
+
+pub fn add_buffer_shares(storage: &mut dyn Storage, depositor: &Addr, denom: &str, shares: FPDecimal) -> StdResult<()> {
+    BUFFER_SHARES.update(storage, (denom.to_string(), depositor), |owned| -> StdResult<_> {
+        Ok(owned.unwrap_or(FPDecimal::ZERO) + shares)
+    })?;
+    TOTAL_BUFFER_SHARES.update(storage, denom.to_string(), |total| -> StdResult<_> {
+        Ok(total.unwrap_or(FPDecimal::ZERO) + shares)
+    })?;
+
+    Ok(())
+}
+
+pub fn remove_buffer_shares(storage: &mut dyn Storage, depositor: &Addr, denom: &str, shares: FPDecimal) -> StdResult<()> {
+    let owned = BUFFER_SHARES.load(storage, (denom.to_string(), depositor))? - shares;
+    if owned.is_zero() {
+        BUFFER_SHARES.remove(storage, (denom.to_string(), depositor));
+    } else {
+        BUFFER_SHARES.save(storage, (denom.to_string(), depositor), &owned)?;
+    }
+
+    let total = TOTAL_BUFFER_SHARES.load(storage, denom.to_string())? - shares;
+    if total.is_zero() {
+        TOTAL_BUFFER_SHARES.remove(storage, denom.to_string());
+    } else {
+        TOTAL_BUFFER_SHARES.save(storage, denom.to_string(), &total)?;
+    }
+
+    Ok(())
+}
+
+// Value the ledger credits to a buffer nobody holds shares of, like funding before the first deposit or rounding left
+// after the last withdrawal, belongs to the operators. It is given to the admin as shares, so it can be withdrawn.
+// Once there are shares, gains belong to their holders and raise the share price instead.
+fn assign_unowned_buffer_value(storage: &mut dyn Storage, denom: &str) -> StdResult<()> {
+    let total_shares = TOTAL_BUFFER_SHARES.may_load(storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO);
+    if !total_shares.is_zero() {
+        return Ok(());
+    }
+
+    let value = get_buffer_value(storage, denom)?;
+    if value <= FPDecimal::ZERO {
+        return Ok(());
+    }
+
+    let admin = CONFIG.load(storage)?.admin;
+    add_buffer_shares(storage, &admin, denom, value)
+}
+
+pub fn record_buffer_deposit(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    BUFFER_LEDGER.update(storage, denom.to_string(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        ledger.principal += amount;
+        Ok(ledger)
+    })?;
+
+    assign_unowned_buffer_value(storage, denom)
+}
+
+pub fn record_buffer_fees(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    BUFFER_LEDGER.update(storage, denom.to_string(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        ledger.fees += amount;
+        Ok(ledger)
+    })?;
+
+    assign_unowned_buffer_value(storage, denom)
+}
+
+// Rebates go to the swapper, the ledger only counts them next to the balance
+pub fn record_buffer_rebate(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    BUFFER_LEDGER.update(storage, denom.to_string(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        ledger.rebates_passed_on += amount;
+        Ok(ledger)
+    })?;
+
+    Ok(())
+}
+
+// Withdrawals are taken from principal, which goes negative once more than deposited is withdrawn
+pub fn record_buffer_withdrawal(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    record_buffer_deposit(storage, denom, -amount)
+}
+
+// Records a rebalance moving input out of the buffer of its denom and output into the buffer of another
+pub fn record_buffer_rebalance(storage: &mut dyn Storage, input: &FPCoin, output: &FPCoin) -> StdResult<()> {
+    BUFFER_LEDGER.update(storage, input.denom.to_owned(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        ledger.rebalanced_out += input.amount;
+        Ok(ledger)
+    })?;
+    BUFFER_LEDGER.update(storage, output.denom.to_owned(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        ledger.rebalanced_in += output.amount;
+        Ok(ledger)
+    })?;
+
+    assign_unowned_buffer_value(storage, &output.denom)
+}
+
+// Records what a swap step left in the contract (positive) or took from the buffer (negative)
+pub fn record_buffer_rounding(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    BUFFER_LEDGER.update(storage, denom.to_string(), |ledger| -> StdResult<_> {
+        let mut ledger = ledger.unwrap_or_default();
+        if amount.is_negative() {
+            ledger.rounding_losses += amount.abs();
+        } else {
+            ledger.rounding_gains += amount;
+        }
+        Ok(ledger)
+    })?;
+
+    assign_unowned_buffer_value(storage, denom)
+}
+
+pub fn get_buffer_caps(storage: &dyn Storage) -> StdResult<Vec<BufferCap>> {
+    BUFFER_CAPS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, cap)| cap))
+        .collect()
+}
+
+fn get_buffer_used_in_block(storage: &dyn Storage, denom: &str, height: u64) -> StdResult<FPDecimal> {
+    Ok(match BUFFER_USAGE.may_load(storage, denom.to_string())? {
+        Some((used_height, used)) if used_height == height => used,
+        _ => FPDecimal::ZERO,
+    })
+}
+
+// Most of the buffer of a denom a swap can still use in the block, None if the denom is not capped
+pub fn get_buffer_cap_left(storage: &dyn Storage, denom: &str, height: u64) -> StdResult<Option<FPDecimal>> {
+    let Some(cap) = BUFFER_CAPS.may_load(storage, denom.to_string())? else {
+        return Ok(None);
+    };
+
+    let block_cap_left = match cap.per_block {
+        Some(per_block) => Some((per_block - get_buffer_used_in_block(storage, denom, height)?).max(FPDecimal::ZERO)),
+        None => None,
+    };
+
+    Ok(match (cap.per_swap, block_cap_left) {
+        (Some(per_swap), Some(block_cap_left)) => Some(per_swap.min(block_cap_left)),
+        (per_swap, block_cap_left) => per_swap.or(block_cap_left),
+    })
+}
+
+// Only usage of denoms with a block cap is kept
+pub fn record_buffer_usage(storage: &mut dyn Storage, denom: &str, height: u64, amount: FPDecimal) -> StdResult<()> {
+    let has_block_cap = BUFFER_CAPS
+        .may_load(storage, denom.to_string())?
+        .is_some_and(|cap| cap.per_block.is_some());
+    if !has_block_cap || amount.is_zero() {
+        return Ok(());
+    }
+
+    let used = get_buffer_used_in_block(storage, denom, height)? + amount;
+    BUFFER_USAGE.save(storage, denom.to_string(), &(height, used))
+}
+
+// Buffer used by fee-exempt addresses is taken out of what is left of their budget, not out of the block caps of other swaps
+pub fn record_exempt_buffer_usage(storage: &mut dyn Storage, denom: &str, amount: FPDecimal) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let budget = EXEMPT_BUFFER_BUDGET.may_load(storage, denom.to_string())?.unwrap_or(FPDecimal::ZERO);
+    if budget < amount {
+        return Err(StdError::generic_err(format!(
+            "Cannot use {amount}{denom} of buffer, only {budget}{denom} is left in the buffer budget of fee-exempt addresses"
+        )));
+    }
+
+    EXEMPT_BUFFER_BUDGET.save(storage, denom.to_string(), &(budget - amount))
+}
+
+pub fn get_buffer_value(storage: &dyn Storage, denom: &str) -> StdResult<FPDecimal> {
+    Ok(BUFFER_LEDGER.may_load(storage, denom.to_string())?.unwrap_or_default().balance())
+}
+
+pub fn get_buffer_positions(storage: &dyn Storage, depositor: &Addr) -> StdResult<Vec<BufferPosition>> {
+    let mut positions = vec![];
+    for item in TOTAL_BUFFER_SHARES.range(storage, None, None, Order::Ascending) {
+        let (denom, total_shares) = item?;
+        let Some(shares) = BUFFER_SHARES.may_load(storage, (denom.to_owned(), depositor))? else {
+            continue;
+        };
+
+        let amount = shares * get_buffer_value(storage, &denom)? / total_shares;
+        positions.push(BufferPosition {
+            denom,
+            shares,
+            total_shares,
+            amount,
+        });
+    }
+
+    Ok(positions)
+}
+
+pub fn get_buffer_ledger(storage: &dyn Storage) -> StdResult<Vec<BufferLedgerEntry>> {
+    BUFFER_LEDGER
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, ledger)| BufferLedgerEntry { denom, ledger }))
+        .collect()
+}
+
+pub fn is_fee_exempt(storage: &dyn Storage, address: &Addr) -> bool {
+    FEE_EXEMPT_ADDRESSES.has(storage, address)
+}
+
+pub fn get_fee_exempt_addresses(storage: &dyn Storage, start_after: Option<Addr>, limit: Option<u32>) -> StdResult<Vec<Addr>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT) as usize;
+
+    FEE_EXEMPT_ADDRESSES
+        .keys(storage, start_after.as_ref().map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect()
+}
+
+pub fn is_buffer_keeper(storage: &dyn Storage, address: &Addr) -> bool {
+    BUFFER_KEEPERS.has(storage, address)
+}
+
+pub fn get_buffer_keepers(storage: &dyn Storage, start_after: Option<Addr>, limit: Option<u32>) -> StdResult<Vec<Addr>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT) as usize;
+
+    BUFFER_KEEPERS
+        .keys(storage, start_after.as_ref().map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect()
+}
+
+pub fn get_exempt_buffer_budget(storage: &dyn Storage) -> StdResult<Vec<FPCoin>> {
+    EXEMPT_BUFFER_BUDGET
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| FPCoin { amount, denom }))
+        .collect()
+}
+
+pub fn get_withdrawal_reserves(storage: &dyn Storage) -> StdResult<WithdrawalReservesResponse> {
+    let reserves = WITHDRAWAL_RESERVES
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| FPCoin { amount, denom }))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(WithdrawalReservesResponse {
+        reserves,
+        emergency_mode: EMERGENCY_MODE.may_load(storage)?.unwrap_or_default(),
+    })
+}
+
+fn volume_window(time: Timestamp) -> (u64, u64) {
+    let today = time.seconds() / SECONDS_PER_DAY;
+    (today.saturating_sub(VOLUME_WINDOW_DAYS - 1), today)
+}
+
+pub fn record_trader_volume(storage: &mut dyn Storage, trader: &Addr, time: Timestamp, volume: FPDecimal) -> StdResult<()> {
+    let (first_day, today) = volume_window(time);
+
+    // days which left the window will never be read again
+    let expired_days = TRADER_VOLUMES
+        .prefix(trader)
+        .keys(storage, None, Some(Bound::exclusive(first_day)), Order::Ascending)
+        .collect::<StdResult<Vec<u64>>>()?;
+    for day in expired_days {
+        TRADER_VOLUMES.remove(storage, (trader, day));
+    }
+
+    TRADER_VOLUMES.update(storage, (trader, today), |day_volume| -> StdResult<_> {
+        Ok(day_volume.unwrap_or_default() + volume)
+    })?;
+
+    Ok(())
+}
+
+pub fn get_trader_volume(storage: &dyn Storage, trader: &Addr, time: Timestamp) -> StdResult<FPDecimal> {
+    let (first_day, today) = volume_window(time);
+
+    TRADER_VOLUMES
+        .prefix(trader)
+        .range(
+            storage,
+            Some(Bound::inclusive(first_day)),
+            Some(Bound::inclusive(today)),
+            Order::Ascending,
+        )
+        .try_fold(FPDecimal::ZERO, |total, item| item.map(|(_, day_volume)| total + day_volume))
+}
+
 pub fn remove_swap_route(storage: &mut dyn Storage, source_denom: &str, target_denom: &str) {
     let key = route_key(source_denom, target_denom);
     SWAP_ROUTES.remove(storage, key)
@@ -1,16 +1,35 @@
 use crate::{
-    contract::ATOMIC_ORDER_REPLY_ID,
+    admin::{verify_sender_is_admin, verify_withdrawal_reserve, withdrawal_event},
+    buffer::{
+        get_buffer_low_events, get_equivalent_buffer_amount, get_route_buffer_balances, top_up_buffer, verify_rebalance_source_buffer,
+        verify_swap_buffers,
+    },
+    contract::{ATOMIC_ORDER_REPLY_ID, FEE_CONVERSION_REPLY_ID},
     error::ContractError,
-    helpers::{dec_scale_factor, round_up_to_min_tick},
-    queries::{estimate_single_swap_execution, estimate_swap_result, SwapQuantity},
-    state::{read_swap_route, CONFIG, STEP_STATE, SWAP_OPERATION_STATE, SWAP_RESULTS},
-    types::{CurrentSwapOperation, CurrentSwapStep, FPCoin, SwapEstimationAmount, SwapQuantityMode, SwapResults},
+    fees::{bps_to_dec, get_output_fee, get_output_fee_rates, get_output_fees, BPS_DENOMINATOR, BURN_AUCTION_SUBACCOUNT_ID},
+    helpers::{dec_scale_factor, sum_coins_by_denom},
+    market_data::{LiveMarketData, TraderMarketData},
+    msg::FeeRecipient,
+    queries::{estimate_single_swap_execution, estimate_swap_result_for_route, SwapQuantity},
+    state::{
+        get_accrued_protocol_fees, get_buffer_value, is_buffer_keeper, is_fee_exempt, read_swap_route, record_accrued_protocol_fee,
+        record_buffer_fees, record_buffer_rebalance, record_buffer_rebate, record_buffer_rounding, record_buffer_usage,
+        record_burn_auction_contribution, record_exempt_buffer_usage, record_rebate, record_swap_dust, record_trader_volume,
+        take_accrued_protocol_fee, BUFFER_DENOMS, BUFFER_REBALANCE, CONFIG, FEE_CONVERSION, FEE_TIERS, STEP_STATE, SWAP_DUST, SWAP_OPERATION_STATE,
+        SWAP_RESULTS, SWAP_VOLUME,
+    },
+    types::{
+        BufferRebalance, CurrentSwapOperation, CurrentSwapStep, FPCoin, FeeConversion, Integrator, OutputFeeRates, SwapEstimationAmount,
+        SwapQuantityMode, SwapResponse, SwapResults,
+    },
 };
 
-use cosmwasm_std::{BankMsg, Coin, DepsMut, Env, Event, MessageInfo, Reply, Response, StdResult, SubMsg};
+use cosmwasm_std::{
+    ensure, to_json_binary, Addr, BankMsg, Coin, DepsMut, Env, Event, MessageInfo, Reply, ReplyOn, Response, StdError, StdResult, SubMsg,
+};
 use injective_cosmwasm::{
-    create_spot_market_order_msg, get_default_subaccount_id_for_checked_address, InjectiveMsgWrapper, InjectiveQuerier, InjectiveQueryWrapper,
-    OrderType, SpotOrder,
+    create_deposit_msg, create_spot_market_order_msg, get_default_subaccount_id_for_checked_address, InjectiveMsgWrapper, InjectiveQuerier,
+    InjectiveQueryWrapper, OrderType, SpotOrder, SubaccountId,
 };
 use injective_math::{round_to_min_tick, FPDecimal};
 use injective_std::types::injective::exchange::v1beta1::MsgCreateSpotMarketOrderResponse;
@@ -23,6 +42,8 @@ pub fn start_swap_flow(
     info: MessageInfo,
     target_denom: String,
     swap_quantity_mode: SwapQuantityMode,
+    integrator: Option<Integrator>,
+    tip_dust: bool,
 ) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     if info.funds.len() != 1 {
         return Err(ContractError::CustomError {
@@ -43,6 +64,17 @@ pub fn start_swap_flow(
     let source_denom = &info.funds[0].denom;
     let route = read_swap_route(deps.storage, source_denom, &target_denom)?;
     let steps = route.steps_from(source_denom);
+    verify_swap_buffers(deps.as_ref(), &env, &steps, &info.funds[0])?;
+
+    // integrator fee is sent at the end of the swap, an invalid address would only fail it then
+    if let Some(integrator) = integrator.as_ref() {
+        deps.api.addr_validate(integrator.address.as_str())?;
+    }
+
+    let output_fee_rates = get_output_fee_rates(deps.as_ref(), &env, &route, Some(&info.sender), integrator.as_ref())?;
+    let fee_recipient = route
+        .effective_fee_recipient(&CONFIG.load(deps.storage)?)
+        .resolve(&env.contract.address, &info.sender);
 
     let sender_address = info.sender;
     let coin_provided = &info.funds[0];
@@ -52,25 +84,19 @@ pub fn start_swap_flow(
     let refund_amount = if matches!(swap_quantity_mode, SwapQuantityMode::ExactOutputQuantity(..)) {
         let target_output_quantity = quantity;
 
-        let estimation = estimate_swap_result(
+        let estimation = estimate_swap_result_for_route(
             deps.as_ref(),
             &env,
+            &TraderMarketData::new(deps.as_ref(), &LiveMarketData::new(deps.as_ref(), &env), Some(&sender_address))?,
+            &route,
             source_denom.to_owned(),
             target_denom,
             SwapQuantity::OutputQuantity(target_output_quantity),
+            &output_fee_rates,
         )?;
 
-        let querier = InjectiveQuerier::new(&deps.querier);
-        let first_market_id = steps[0].to_owned();
-        let first_market = querier.query_spot_market(&first_market_id)?.market.expect("market should be available");
-
-        let is_input_quote = first_market.quote_denom == *source_denom;
-
-        let required_input = if is_input_quote {
-            estimation.result_quantity.int() + FPDecimal::ONE
-        } else {
-            round_up_to_min_tick(estimation.result_quantity, first_market.min_quantity_tick_size)
-        };
+        // estimation already includes padding for rounding of the input
+        let required_input = estimation.result_quantity;
 
         let fp_coins: FPDecimal = coin_provided.amount.into();
 
@@ -88,18 +114,230 @@ pub fn start_swap_flow(
         FPDecimal::ZERO
     };
 
+    let buffer_balances_before = get_route_buffer_balances(deps.as_ref(), &env, &steps, coin_provided)?;
     let swap_operation = CurrentSwapOperation {
         sender_address,
         swap_steps: steps,
         swap_quantity_mode,
         refund: Coin::new(refund_amount, source_denom.to_owned()),
         input_funds: coin_provided.to_owned(),
+        integrator,
+        output_fee_rates,
+        fee_recipient,
+        tip_dust,
+        internal: false,
+        buffer_balances_before,
+    };
+
+    SWAP_RESULTS.save(deps.storage, &Vec::new())?;
+    SWAP_OPERATION_STATE.save(deps.storage, &swap_operation)?;
+
+    execute_swap_step(deps, env, swap_operation, 0, current_balance)
+}
+
+// Swaps protocol fees accrued in the contract into target denom over stored routes and sends the results to the treasury.
+// Coins are swapped one after another, the next swap starts when the messages of the previous one have run. Fees only
+// accrue in the contract while `accrue_protocol_fees` is set, otherwise they are sent to the treasury right away.
+pub fn convert_fees(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: &Addr,
+    coins: Vec<Coin>,
+    target_denom: String,
+    max_slippage_bps: u16,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if !is_buffer_keeper(deps.storage, sender) {
+        verify_sender_is_admin(deps.as_ref(), sender)?;
+    }
+
+    ensure!(
+        max_slippage_bps <= BPS_DENOMINATOR,
+        ContractError::CustomError {
+            val: format!("Max slippage cannot be above {BPS_DENOMINATOR} bps")
+        }
+    );
+
+    if CONFIG.load(deps.storage)?.treasury.is_none() {
+        return Err(ContractError::CustomError {
+            val: "Treasury must be set to convert fees".to_string(),
+        });
+    }
+
+    if coins.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "No fees to convert".to_string(),
+        });
+    }
+
+    if get_accrued_protocol_fees(deps.storage)?.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "No protocol fees accrued in the contract, they only accrue while accrue_protocol_fees is set".to_string(),
+        });
+    }
+
+    // conversions take funds out of the contract like withdrawals, so they keep the reserves too
+    for total in sum_coins_by_denom(&coins) {
+        let balance: FPDecimal = deps.querier.query_balance(&env.contract.address, &total.denom)?.amount.into();
+        verify_withdrawal_reserve(deps.as_ref(), &total.denom, balance, total.amount.into())?;
+    }
+
+    for coin in coins.iter() {
+        if coin.amount.is_zero() || coin.denom == target_denom {
+            return Err(ContractError::CustomError {
+                val: format!("Cannot convert {coin} into {target_denom}"),
+            });
+        }
+
+        read_swap_route(deps.storage, &coin.denom, &target_denom)?;
+
+        // only protocol fees accrued in the contract are converted, never the buffer
+        take_accrued_protocol_fee(deps.storage, &coin.denom, coin.amount.into())?;
+    }
+
+    FEE_CONVERSION.save(
+        deps.storage,
+        &FeeConversion {
+            target_denom,
+            pending: coins,
+            max_slippage_bps,
+        },
+    )?;
+
+    let response = start_next_fee_conversion_swap(deps, env)?.expect("conversion should have pending fees");
+
+    Ok(response.add_attribute("method", "convert_fees"))
+}
+
+// Starts swapping the next pending coin of the fee conversion in progress, if there is one
+fn start_next_fee_conversion_swap(deps: DepsMut<InjectiveQueryWrapper>, env: Env) -> Result<Option<Response<InjectiveMsgWrapper>>, ContractError> {
+    let Some(mut conversion) = FEE_CONVERSION.may_load(deps.storage)? else {
+        return Ok(None);
+    };
+
+    let input_funds = conversion.pending.remove(0);
+    if conversion.pending.is_empty() {
+        FEE_CONVERSION.remove(deps.storage);
+    } else {
+        FEE_CONVERSION.save(deps.storage, &conversion)?;
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    let treasury = config
+        .treasury
+        .to_owned()
+        .ok_or_else(|| StdError::generic_err("Treasury must be set to convert fees"))?;
+    let route = read_swap_route(deps.storage, &input_funds.denom, &conversion.target_denom)?;
+    let min_output = get_equivalent_buffer_amount(deps.as_ref(), &env, &input_funds.to_owned().into(), &conversion.target_denom)?
+        * (FPDecimal::ONE - bps_to_dec(conversion.max_slippage_bps));
+
+    // treasury receives the output, fees are not charged on our own conversion
+    let steps = route.steps_from(&input_funds.denom);
+    let buffer_balances_before = get_route_buffer_balances(deps.as_ref(), &env, &steps, &input_funds)?;
+    let swap_operation = CurrentSwapOperation {
+        sender_address: treasury.to_owned(),
+        swap_steps: steps,
+        swap_quantity_mode: SwapQuantityMode::MinOutputQuantity(min_output),
+        refund: Coin::new(0u128, input_funds.denom.to_owned()),
+        input_funds: input_funds.to_owned(),
+        integrator: None,
+        output_fee_rates: OutputFeeRates::default(),
+        fee_recipient: route.effective_fee_recipient(&config).resolve(&env.contract.address, &treasury),
+        tip_dust: false,
+        internal: true,
+        buffer_balances_before,
+    };
+
+    SWAP_RESULTS.save(deps.storage, &Vec::new())?;
+    SWAP_OPERATION_STATE.save(deps.storage, &swap_operation)?;
+
+    execute_swap_step(deps, env, swap_operation, 0, input_funds.into()).map(Some)
+}
+
+// Swaps buffer funds of one denom into the buffer of another over the stored route, as buffers drift apart with
+// multi-quote routes. The output must be worth the coin by the buffer price sources, less the max slippage.
+pub fn rebalance_buffer(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    env: Env,
+    sender: &Addr,
+    coin: Coin,
+    target_denom: String,
+    max_slippage_bps: u16,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if !is_buffer_keeper(deps.storage, sender) {
+        verify_sender_is_admin(deps.as_ref(), sender)?;
+    }
+
+    ensure!(
+        max_slippage_bps <= BPS_DENOMINATOR,
+        ContractError::CustomError {
+            val: format!("Max slippage cannot be above {BPS_DENOMINATOR} bps")
+        }
+    );
+
+    if coin.amount.is_zero() || coin.denom == target_denom {
+        return Err(ContractError::CustomError {
+            val: format!("Cannot rebalance {coin} into {target_denom}"),
+        });
+    }
+
+    for denom in [&coin.denom, &target_denom] {
+        ensure!(
+            BUFFER_DENOMS.has(deps.storage, denom.to_owned()),
+            ContractError::BufferNotConfigured { denom: denom.to_owned() }
+        );
+    }
+
+    let buffer_value = get_buffer_value(deps.storage, &coin.denom)?;
+    if buffer_value < coin.amount.into() {
+        return Err(ContractError::CustomError {
+            val: format!("Cannot rebalance {coin}, buffer only holds {buffer_value}{}", coin.denom),
+        });
+    }
+
+    let route = read_swap_route(deps.storage, &coin.denom, &target_denom)?;
+    let steps = route.steps_from(&coin.denom);
+    verify_rebalance_source_buffer(deps.as_ref(), &env, &coin)?;
+    verify_swap_buffers(deps.as_ref(), &env, &steps, &coin)?;
+
+    let min_output =
+        get_equivalent_buffer_amount(deps.as_ref(), &env, &coin.to_owned().into(), &target_denom)? * (FPDecimal::ONE - bps_to_dec(max_slippage_bps));
+
+    BUFFER_REBALANCE.save(
+        deps.storage,
+        &BufferRebalance {
+            keeper: sender.to_owned(),
+            input: coin.to_owned(),
+            min_output,
+        },
+    )?;
+
+    // output stays in the contract, fees are not charged on our own rebalance
+    let config = CONFIG.load(deps.storage)?;
+    let swap_operation = CurrentSwapOperation {
+        sender_address: env.contract.address.to_owned(),
+        swap_steps: steps,
+        swap_quantity_mode: SwapQuantityMode::MinOutputQuantity(min_output),
+        refund: Coin::new(0u128, coin.denom.to_owned()),
+        input_funds: coin.to_owned(),
+        integrator: None,
+        output_fee_rates: OutputFeeRates::default(),
+        fee_recipient: route
+            .effective_fee_recipient(&config)
+            .resolve(&env.contract.address, &env.contract.address),
+        tip_dust: true,
+        internal: true,
+        // rebalances report the buffers they move in their own event
+        buffer_balances_before: vec![],
     };
 
     SWAP_RESULTS.save(deps.storage, &Vec::new())?;
     SWAP_OPERATION_STATE.save(deps.storage, &swap_operation)?;
 
-    execute_swap_step(deps, env, swap_operation, 0, current_balance).map_err(ContractError::Std)
+    let response = execute_swap_step(deps, env, swap_operation, 0, coin.into())?;
+
+    Ok(response
+        .add_attribute("method", "rebalance_buffer")
+        .add_attribute("min_output", min_output.to_string()))
 }
 
 pub fn execute_swap_step(
@@ -108,20 +346,30 @@ pub fn execute_swap_step(
     swap_operation: CurrentSwapOperation,
     step_idx: u16,
     current_balance: FPCoin,
-) -> StdResult<Response<InjectiveMsgWrapper>> {
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
     let market_id = swap_operation.swap_steps[usize::from(step_idx)].clone();
     let contract = &env.contract.address;
     let subaccount_id = get_default_subaccount_id_for_checked_address(contract);
 
+    let live_market_data = LiveMarketData::new(deps.as_ref(), &env);
+    let market_data = TraderMarketData::new(deps.as_ref(), &live_market_data, Some(&swap_operation.sender_address))?;
+
     let estimation = estimate_single_swap_execution(
         &deps.as_ref(),
         &env,
+        &market_data,
         &market_id,
+        &FeeRecipient::Address(swap_operation.fee_recipient.to_owned()),
         SwapEstimationAmount::InputQuantity(current_balance.clone()),
         false,
     )?;
 
-    let fee_recipient = &CONFIG.load(deps.storage)?.fee_recipient;
+    // fee-exempt addresses draw down their own budget and leave the block caps to other swaps
+    if is_fee_exempt(deps.storage, &swap_operation.sender_address) {
+        record_exempt_buffer_usage(deps.storage, &current_balance.denom, estimation.buffer_needed)?;
+    } else {
+        record_buffer_usage(deps.storage, &current_balance.denom, env.block.height, estimation.buffer_needed)?;
+    }
 
     let order = SpotOrder::new(
         estimation.worst_price,
@@ -137,7 +385,7 @@ pub fn execute_swap_step(
         },
         &market_id,
         subaccount_id,
-        Some(fee_recipient.to_owned()),
+        Some(swap_operation.fee_recipient.to_owned()),
         None,
     );
 
@@ -176,9 +424,44 @@ pub fn handle_atomic_order_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env,
 
     let current_step = STEP_STATE.load(deps.storage).map_err(ContractError::Std)?;
 
-    let new_quantity = if current_step.is_buy { quantity } else { quantity * average_price - fee };
-
     let swap = SWAP_OPERATION_STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let current_market_id = swap.swap_steps[(current_step.step_idx) as usize].to_owned();
+
+    let querier = InjectiveQuerier::new(&deps.querier);
+    let current_market = querier.query_spot_market(&current_market_id)?.market.expect("market should be available");
+
+    // swap volume is valued by the first step trading the reference denom of fee tiers
+    if let Some(schedule) = FEE_TIERS.may_load(deps.storage)? {
+        if !SWAP_VOLUME.exists(deps.storage) {
+            if current_market.quote_denom == schedule.reference_denom {
+                SWAP_VOLUME.save(deps.storage, &(quantity * average_price))?;
+            } else if current_market.base_denom == schedule.reference_denom {
+                SWAP_VOLUME.save(deps.storage, &quantity)?;
+            }
+        }
+    }
+
+    // when the contract is the fee recipient, the exchange pays relayer share of the fee back to it, depositor share of the
+    // rebate stays in the buffer and the rest is passed on to the swapper
+    let rebate = if swap.fee_recipient == env.contract.address {
+        let received_rebate = fee * current_market.relayer_fee_share_rate;
+        let depositor_share = received_rebate * bps_to_dec(config.depositor_rebate_share_bps);
+        let rebate = received_rebate - depositor_share;
+        record_rebate(deps.storage, &current_market.quote_denom, received_rebate)?;
+        record_buffer_rebate(deps.storage, &current_market.quote_denom, rebate)?;
+        record_buffer_fees(deps.storage, &current_market.quote_denom, depositor_share)?;
+        rebate
+    } else {
+        FPDecimal::ZERO
+    };
+
+    // buys were already sized as if only the discounted fee was charged, proceeds of sells are topped up with the rebate
+    let new_quantity = if current_step.is_buy {
+        quantity
+    } else {
+        quantity * average_price - fee + rebate
+    };
 
     let has_next_market = swap.swap_steps.len() > (current_step.step_idx + 1) as usize;
 
@@ -198,21 +481,40 @@ pub fn handle_atomic_order_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env,
         new_quantity
     };
 
+    // what the step spent and received differs from what is carried on by the rounding, leftovers belong to the swapper
+    // and are refunded at the end of the swap, shortfalls come from the buffer
+    let source_leftover = if current_step.is_buy {
+        current_step.current_balance.amount - (quantity * average_price + fee - rebate)
+    } else {
+        current_step.current_balance.amount - quantity
+    };
+    if source_leftover.is_negative() {
+        record_buffer_rounding(deps.storage, &current_step.current_balance.denom, source_leftover)?;
+    } else {
+        record_swap_dust(deps.storage, &current_step.current_balance.denom, source_leftover)?;
+    }
+    record_swap_dust(deps.storage, &current_step.step_target_denom, new_quantity - new_rounded_quantity)?;
+
     let new_balance = FPCoin {
         amount: new_rounded_quantity,
         denom: current_step.step_target_denom,
     };
 
     swap_results.push(SwapResults {
-        market_id: swap.swap_steps[(current_step.step_idx) as usize].to_owned(),
+        market_id: current_market_id,
         price: average_price,
         quantity: new_rounded_quantity,
         fee,
+        rebate,
     });
 
     if current_step.step_idx < (swap.swap_steps.len() - 1) as u16 {
         SWAP_RESULTS.save(deps.storage, &swap_results)?;
-        return execute_swap_step(deps, env, swap, current_step.step_idx + 1, new_balance).map_err(ContractError::Std);
+        return execute_swap_step(deps, env, swap, current_step.step_idx + 1, new_balance);
+    }
+
+    if let Some(rebalance) = BUFFER_REBALANCE.may_load(deps.storage)? {
+        return finish_buffer_rebalance(deps, rebalance, new_balance, swap_results);
     }
 
     let min_output_quantity = match swap.swap_quantity_mode {
@@ -220,14 +522,54 @@ pub fn handle_atomic_order_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env,
         SwapQuantityMode::ExactOutputQuantity(q) => q,
     };
 
-    if new_balance.amount < min_output_quantity {
+    let output_fees = get_output_fees(new_balance.amount, &swap.output_fee_rates);
+    let output_balance = FPCoin {
+        amount: new_balance.amount - output_fees.total(),
+        denom: new_balance.denom.to_owned(),
+    };
+
+    if output_balance.amount < min_output_quantity {
         return Err(ContractError::MinOutputAmountNotReached(min_output_quantity));
     }
 
+    // only whole units of the output are sent, the fraction stays in the buffer
+    record_buffer_rounding(deps.storage, &output_balance.denom, output_balance.amount - output_balance.amount.int())?;
+
+    // whole units of the leftovers of the steps are refunded unless the swapper tips them, the rest stays in the buffer
+    let mut dust = SWAP_DUST.may_load(deps.storage)?.unwrap_or_default();
+    dust.sort_by(|a, b| a.denom.cmp(&b.denom));
+    let mut dust_refund = vec![];
+    for coin in dust.iter() {
+        let refunded = if swap.tip_dust { FPDecimal::ZERO } else { coin.amount.int() };
+        record_buffer_rounding(deps.storage, &coin.denom, coin.amount - refunded)?;
+        if refunded > FPDecimal::ZERO {
+            dust_refund.push(FPCoin {
+                amount: refunded,
+                denom: coin.denom.to_owned(),
+            });
+        }
+    }
+
     // last step, finalize and send back funds to a caller
     let send_message = BankMsg::Send {
         to_address: swap.sender_address.to_string(),
-        amount: vec![new_balance.clone().into()],
+        amount: vec![output_balance.clone().into()],
+    };
+
+    let swap_response = SwapResponse {
+        output: output_balance.clone().into(),
+        refund: swap.refund.to_owned(),
+        protocol_fee: FPCoin {
+            amount: output_fees.protocol_fee,
+            denom: output_balance.denom.to_owned(),
+        }
+        .into(),
+        integrator_fee: FPCoin {
+            amount: output_fees.integrator_fee,
+            denom: output_balance.denom.to_owned(),
+        }
+        .into(),
+        swap_results: swap_results.to_owned(),
     };
 
     let swap_results_json = serde_json_wasm::to_string(&swap_results).unwrap();
@@ -236,15 +578,114 @@ pub fn handle_atomic_order_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env,
         .add_attribute("swap_input_amount", swap.input_funds.amount)
         .add_attribute("swap_input_denom", swap.input_funds.denom)
         .add_attribute("refund_amount", swap.refund.amount.to_owned())
-        .add_attribute("swap_final_amount", new_balance.amount.to_string())
-        .add_attribute("swap_final_denom", new_balance.denom)
+        .add_attribute("swap_final_amount", output_balance.amount.to_string())
+        .add_attribute("swap_final_denom", output_balance.denom)
+        .add_attribute("protocol_fee_amount", output_fees.protocol_fee.to_string())
+        .add_attribute("integrator_fee_amount", output_fees.integrator_fee.to_string())
+        .add_attribute("dust", format_coins(&dust))
+        .add_attribute("dust_refund", format_coins(&dust_refund))
+        .add_attribute("dust_tipped", swap.tip_dust.to_string())
         .add_attribute("swap_results", swap_results_json);
 
+    if let Some(volume) = SWAP_VOLUME.may_load(deps.storage)? {
+        if !swap.internal {
+            record_trader_volume(deps.storage, &swap.sender_address, env.block.time, volume)?;
+        }
+        SWAP_VOLUME.remove(deps.storage);
+    }
+
     SWAP_OPERATION_STATE.remove(deps.storage);
     STEP_STATE.remove(deps.storage);
     SWAP_RESULTS.remove(deps.storage);
+    SWAP_DUST.remove(deps.storage);
+
+    let mut response = Response::new()
+        .add_message(send_message)
+        .add_event(swap_event)
+        .set_data(to_json_binary(&swap_response)?);
+
+    // fee conversions take funds out of the contract like admin withdrawals, so they are reported the same way
+    if swap.internal {
+        let mut sent = vec![swap_response.output.to_owned()];
+        sent.extend(dust_refund.iter().cloned().map(Coin::from));
+        for coin in sum_coins_by_denom(&sent) {
+            let balance_before: FPDecimal = deps.querier.query_balance(&env.contract.address, &coin.denom)?.amount.into();
+            response = response.add_event(withdrawal_event(&coin, balance_before, &swap.sender_address));
+        }
+    }
+
+    // burn share of the protocol fee is deposited to the burn auction, depositor share stays in the buffer and the rest
+    // tops up the buffer to its target and goes to the treasury, or accrues in the contract until the next top-up
+    let burn_share = get_output_fee(output_fees.protocol_fee, config.burn_share_bps);
+    let depositor_share = get_output_fee(output_fees.protocol_fee, config.depositor_fee_share_bps);
+    let mut treasury_share = output_fees.protocol_fee - burn_share - depositor_share;
 
-    let mut response = Response::new().add_message(send_message).add_event(swap_event);
+    record_buffer_fees(deps.storage, &new_balance.denom, depositor_share)?;
+
+    let mut kept_in_buffer = depositor_share;
+    if config.accrue_protocol_fees {
+        record_accrued_protocol_fee(deps.storage, &new_balance.denom, treasury_share)?;
+        treasury_share = FPDecimal::ZERO;
+    } else {
+        let top_up = top_up_buffer(deps.storage, &new_balance.denom, treasury_share)?;
+        if top_up > FPDecimal::ZERO {
+            treasury_share -= top_up;
+            kept_in_buffer += top_up;
+            response = response.add_attribute("buffer_top_up_amount", top_up.to_string());
+        }
+    }
+
+    // output and fees of the swap that leave the contract or accrue for the treasury, and the refunds, are not buffer
+    let mut swap_funds = vec![
+        FPCoin {
+            amount: new_balance.amount - kept_in_buffer,
+            denom: new_balance.denom.to_owned(),
+        },
+        swap.refund.to_owned().into(),
+    ];
+    swap_funds.extend(dust_refund.iter().cloned());
+    response = response.add_events(get_buffer_low_events(deps.as_ref(), &env, &swap.buffer_balances_before, &swap_funds)?);
+
+    if burn_share > FPDecimal::ZERO {
+        record_burn_auction_contribution(deps.storage, &new_balance.denom, burn_share)?;
+        let burn_auction_message = create_deposit_msg(
+            env.contract.address.to_owned(),
+            SubaccountId::new(BURN_AUCTION_SUBACCOUNT_ID)?,
+            FPCoin {
+                amount: burn_share,
+                denom: new_balance.denom.to_owned(),
+            }
+            .into(),
+        );
+        response = response
+            .add_message(burn_auction_message)
+            .add_attribute("burn_auction_amount", burn_share.to_string());
+    }
+
+    if treasury_share > FPDecimal::ZERO {
+        let treasury = config.treasury.expect("treasury should be set when protocol fee is charged");
+        let protocol_fee_message = BankMsg::Send {
+            to_address: treasury.to_string(),
+            amount: vec![FPCoin {
+                amount: treasury_share,
+                denom: new_balance.denom.to_owned(),
+            }
+            .into()],
+        };
+        response = response.add_message(protocol_fee_message)
+    }
+
+    if let Some(integrator) = swap.integrator.filter(|_| output_fees.integrator_fee > FPDecimal::ZERO) {
+        let integrator_fee_message = BankMsg::Send {
+            to_address: integrator.address.to_string(),
+            amount: vec![FPCoin {
+                amount: output_fees.integrator_fee,
+                denom: new_balance.denom,
+            }
+            .into()],
+        };
+        response = response.add_message(integrator_fee_message)
+    }
 
     if !swap.refund.amount.is_zero() {
         let refund_message = BankMsg::Send {
@@ -254,9 +695,80 @@ pub fn handle_atomic_order_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env,
         response = response.add_message(refund_message)
     }
 
+    if !dust_refund.is_empty() {
+        let dust_refund_message = BankMsg::Send {
+            to_address: swap.sender_address.to_string(),
+            amount: dust_refund.into_iter().map(Coin::from).collect(),
+        };
+        response = response.add_message(dust_refund_message)
+    }
+
+    // the next fee coin is swapped in the reply of the last message, so it sees the balances after every send of this swap
+    if FEE_CONVERSION.exists(deps.storage) {
+        if let Some(last_message) = response.messages.last_mut() {
+            last_message.id = FEE_CONVERSION_REPLY_ID;
+            last_message.reply_on = ReplyOn::Success;
+        }
+    }
+
     Ok(response)
 }
 
+pub fn handle_fee_conversion_reply(deps: DepsMut<InjectiveQueryWrapper>, env: Env) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    let response = start_next_fee_conversion_swap(deps, env)?.ok_or_else(|| StdError::generic_err("No fee conversion in progress"))?;
+
+    Ok(response.add_attribute("method", "convert_fees"))
+}
+
+// Output and leftovers of the steps of a rebalance stay in the contract, the ledger moves the input out of the buffer of
+// its denom and the output into the one of target denom
+fn finish_buffer_rebalance(
+    deps: DepsMut<InjectiveQueryWrapper>,
+    rebalance: BufferRebalance,
+    output: FPCoin,
+    swap_results: Vec<SwapResults>,
+) -> Result<Response<InjectiveMsgWrapper>, ContractError> {
+    if output.amount < rebalance.min_output {
+        return Err(ContractError::RebalanceSlippageExceeded {
+            denom: output.denom,
+            output: output.amount,
+            min_output: rebalance.min_output,
+        });
+    }
+
+    record_buffer_rebalance(deps.storage, &rebalance.input.to_owned().into(), &output)?;
+
+    let dust = SWAP_DUST.may_load(deps.storage)?.unwrap_or_default();
+    for coin in dust.iter() {
+        record_buffer_rounding(deps.storage, &coin.denom, coin.amount)?;
+    }
+
+    SWAP_OPERATION_STATE.remove(deps.storage);
+    STEP_STATE.remove(deps.storage);
+    SWAP_RESULTS.remove(deps.storage);
+    SWAP_DUST.remove(deps.storage);
+    SWAP_VOLUME.remove(deps.storage);
+    BUFFER_REBALANCE.remove(deps.storage);
+
+    let rebalance_event = Event::new("buffer_rebalance")
+        .add_attribute("keeper", rebalance.keeper)
+        .add_attribute("input", rebalance.input.to_string())
+        .add_attribute("output", format_coins(&[output]))
+        .add_attribute("min_output", rebalance.min_output.to_string())
+        .add_attribute("dust", format_coins(&dust))
+        .add_attribute("swap_results", serde_json_wasm::to_string(&swap_results).unwrap());
+
+    Ok(Response::new().add_event(rebalance_event))
+}
+
+fn format_coins(coins: &[FPCoin]) -> String {
+    coins
+        .iter()
+        .map(|coin| format!("{}{}", coin.amount, coin.denom))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 pub fn parse_market_order_response(msg: Reply) -> StdResult<MsgCreateSpotMarketOrderResponse> {
     let binding = msg.result.into_result().map_err(ContractError::SubMsgFailure).unwrap();
 
@@ -0,0 +1,877 @@
+use crate::{
+    admin::set_route,
+    contract::{execute, query},
+    error::ContractError,
+    msg::{ExecuteMsg, FeeRecipient, QueryMsg},
+    state::{
+        record_accrued_protocol_fee, record_buffer_deposit, record_buffer_fees, record_buffer_rebate, record_buffer_rounding, BUFFER_DENOMS, CONFIG,
+    },
+    testing::test_utils::{mock_deps_eth_inj, test_config, MultiplierQueryBehavior, TEST_CONTRACT_ADDR, TEST_USER_ADDR},
+    types::{
+        BufferDenom, BufferLedger, BufferLedgerEntry, BufferPnl, BufferPnlResponse, BufferPosition, BufferPriceSource, BufferPricing, BufferStatus,
+        Config, FPCoin, WithdrawalReservesResponse,
+    },
+};
+
+use cosmwasm_std::testing::{message_info, mock_env};
+use cosmwasm_std::{coin, coins, from_json, Addr, BankMsg, CosmosMsg, Deps, DepsMut, Env, Storage};
+use injective_cosmwasm::{
+    create_oracle_query_handler, inj_mock_deps, InjectiveQueryWrapper, MarketId, OracleType, OwnedDepsExt, TEST_MARKET_ID_1, TEST_MARKET_ID_2,
+};
+use injective_math::FPDecimal;
+
+const ALICE: &str = "alice";
+const BOB: &str = "bob";
+
+fn config_with_max_buffer_withdrawal(max_buffer_withdrawal_bps: u16) -> Config {
+    Config {
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        max_buffer_withdrawal_bps,
+        ..test_config()
+    }
+}
+
+// Deposits are only taken in denoms that have a buffer
+fn set_usdt_buffer(storage: &mut dyn Storage) {
+    BUFFER_DENOMS
+        .save(
+            storage,
+            "usdt".to_string(),
+            &BufferDenom {
+                denom: "usdt".to_string(),
+                min_balance: FPDecimal::ZERO,
+                target_balance: None,
+                low_balance: None,
+                critical_balance: None,
+                critical_max_use_bps: 0,
+            },
+        )
+        .unwrap();
+}
+
+fn get_positions(deps: Deps<InjectiveQueryWrapper>, env: &Env, depositor: &str) -> Vec<BufferPosition> {
+    from_json(
+        query(
+            deps,
+            env.to_owned(),
+            QueryMsg::GetBufferPositions {
+                depositor: Addr::unchecked(depositor),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+fn usdt_position(shares: u128, total_shares: u128, amount: u128) -> Vec<BufferPosition> {
+    vec![BufferPosition {
+        denom: "usdt".to_string(),
+        shares: FPDecimal::from(shares),
+        total_shares: FPDecimal::from(total_shares),
+        amount: FPDecimal::from(amount),
+    }]
+}
+
+#[test]
+fn anyone_can_deposit_buffer_for_shares_and_redeem_them() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_usdt_buffer(deps.as_mut_deps().storage);
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(1000, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    // swaps left 100 usdt in the buffer, so a share is worth 1.1 usdt when bob deposits
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", FPDecimal::from(100u128)).unwrap();
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(BOB), &coins(550, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, ALICE),
+        usdt_position(1000, 1500, 1100),
+        "wrong position of alice"
+    );
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, BOB),
+        usdt_position(500, 1500, 550),
+        "wrong position of bob"
+    );
+
+    let res = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(BOB), &[]),
+        ExecuteMsg::WithdrawBuffer {
+            denom: "usdt".to_string(),
+            shares: FPDecimal::from(501u128),
+        },
+    );
+    assert!(res.is_err(), "expected error when withdrawing more shares than owned");
+
+    let res = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &[]),
+        ExecuteMsg::WithdrawBuffer {
+            denom: "usdt".to_string(),
+            shares: FPDecimal::from(500u128),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: ALICE.to_string(),
+            amount: vec![coin(550u128, "usdt")],
+        }),
+        "shares should be redeemed at their current value"
+    );
+    let event = res.events.iter().find(|e| e.ty == "withdrawal").expect("withdrawal event expected");
+    let attribute = |key: &str| event.attributes.iter().find(|a| a.key == key).unwrap().value.to_owned();
+    assert_eq!(
+        (attribute("amount"), attribute("target_address")),
+        ("550".to_string(), ALICE.to_string()),
+        "buffer withdrawal should be reported like admin withdrawals"
+    );
+
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, ALICE),
+        usdt_position(500, 1000, 550),
+        "wrong position of alice"
+    );
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    assert_eq!(
+        ledger,
+        vec![BufferLedgerEntry {
+            denom: "usdt".to_string(),
+            ledger: BufferLedger {
+                principal: FPDecimal::from(1000u128),
+                rounding_gains: FPDecimal::from(100u128),
+                ..BufferLedger::default()
+            },
+        }],
+        "principal should be deposits minus withdrawals"
+    );
+}
+
+#[test]
+fn admin_gets_shares_of_buffer_value_nobody_holds_shares_of() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_usdt_buffer(deps.as_mut_deps().storage);
+
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(500, "eth")),
+        ExecuteMsg::DepositBuffer {},
+    );
+    assert!(
+        matches!(result, Err(ContractError::BufferNotConfigured { ref denom }) if denom == "eth"),
+        "deposit of a denom without buffer should be rejected, got {result:?}"
+    );
+
+    record_buffer_deposit(deps.as_mut_deps().storage, "usdt", FPDecimal::from(500u128)).unwrap();
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, TEST_USER_ADDR),
+        usdt_position(500, 500, 500),
+        "buffer funded before the first deposit should belong to admin"
+    );
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(500, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, TEST_USER_ADDR),
+        usdt_position(500, 1000, 500),
+        "wrong position of admin"
+    );
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, ALICE),
+        usdt_position(500, 1000, 500),
+        "wrong position of alice"
+    );
+
+    let withdraw = |shares: u128| ExecuteMsg::WithdrawBuffer {
+        denom: "usdt".to_string(),
+        shares: FPDecimal::from(shares),
+    };
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+    execute(deps.as_mut(), env.to_owned(), admin.to_owned(), withdraw(500)).unwrap();
+    execute(deps.as_mut(), env.to_owned(), message_info(&Addr::unchecked(ALICE), &[]), withdraw(500)).unwrap();
+
+    // gains after everybody withdrew are not stuck in the ledger
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", FPDecimal::from(7u128)).unwrap();
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, TEST_USER_ADDR),
+        usdt_position(7, 7, 7),
+        "gains of a buffer without shares should belong to admin"
+    );
+    let res = execute(deps.as_mut(), env, admin, withdraw(7)).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: TEST_USER_ADDR.to_string(),
+            amount: vec![coin(7u128, "usdt")],
+        }),
+        "admin should withdraw the gains through its shares"
+    );
+}
+
+#[test]
+fn gains_of_buffer_with_depositors_raise_their_share_price_without_admin_shares() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_usdt_buffer(deps.as_mut_deps().storage);
+
+    for depositor in [ALICE, BOB] {
+        execute(
+            deps.as_mut(),
+            env.to_owned(),
+            message_info(&Addr::unchecked(depositor), &coins(500, "usdt")),
+            ExecuteMsg::DepositBuffer {},
+        )
+        .unwrap();
+    }
+
+    // depositor shares of a protocol fee and a relayer rebate, and a rounding gain
+    record_buffer_fees(deps.as_mut_deps().storage, "usdt", FPDecimal::from(60u128)).unwrap();
+    record_buffer_fees(deps.as_mut_deps().storage, "usdt", FPDecimal::from(30u128)).unwrap();
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", FPDecimal::from(10u128)).unwrap();
+
+    assert!(
+        get_positions(deps.as_ref(), &env, TEST_USER_ADDR).is_empty(),
+        "admin should get no shares of gains that belong to depositors"
+    );
+    for depositor in [ALICE, BOB] {
+        assert_eq!(
+            get_positions(deps.as_ref(), &env, depositor),
+            usdt_position(500, 1000, 550),
+            "gains should raise the share price of {depositor}"
+        );
+    }
+}
+
+#[test]
+fn deposits_restart_buffer_that_lost_all_its_value() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_usdt_buffer(deps.as_mut_deps().storage);
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(100, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    // swaps took 150 usdt from a buffer of 100, the shares of alice are worthless
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", -FPDecimal::from(150u128)).unwrap();
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(BOB), &coins(100, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    assert!(get_positions(deps.as_ref(), &env, ALICE).is_empty(), "worthless shares should be voided");
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, BOB),
+        usdt_position(100, 100, 100),
+        "new depositor should not pay for the deficit"
+    );
+}
+
+#[test]
+fn buffer_withdrawals_are_limited_per_block() {
+    let mut env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(2500)).unwrap();
+    set_usdt_buffer(deps.as_mut_deps().storage);
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(1000, "usdt")),
+        ExecuteMsg::DepositBuffer {},
+    )
+    .unwrap();
+
+    let withdraw = |shares: u128| ExecuteMsg::WithdrawBuffer {
+        denom: "usdt".to_string(),
+        shares: FPDecimal::from(shares),
+    };
+    let alice = message_info(&Addr::unchecked(ALICE), &[]);
+
+    execute(deps.as_mut(), env.to_owned(), alice.to_owned(), withdraw(200)).unwrap();
+    let res = execute(deps.as_mut(), env.to_owned(), alice.to_owned(), withdraw(100));
+    assert!(res.is_err(), "expected error when withdrawing more than 25% of the buffer in one block");
+
+    env.block.height += 1;
+    execute(deps.as_mut(), env, alice, withdraw(100)).unwrap();
+}
+
+#[test]
+fn routes_need_buffers_of_their_quote_denoms_once_buffers_are_configured() {
+    let env = mock_env();
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+
+    let set_buffers = |deps: DepsMut<InjectiveQueryWrapper>, denom: &str| {
+        execute(
+            deps,
+            mock_env(),
+            message_info(&admin, &[]),
+            ExecuteMsg::SetBufferDenoms {
+                buffers: vec![BufferDenom {
+                    denom: denom.to_string(),
+                    min_balance: FPDecimal::from(1000u128),
+                    target_balance: None,
+                    low_balance: None,
+                    critical_balance: None,
+                    critical_max_use_bps: 0,
+                }],
+            },
+        )
+    };
+    let set_eth_route = |deps: DepsMut<InjectiveQueryWrapper>| {
+        execute(
+            deps,
+            mock_env(),
+            message_info(&admin, &[]),
+            ExecuteMsg::SetRoute {
+                source_denom: "eth".to_string(),
+                target_denom: "usdt".to_string(),
+                route: vec![MarketId::unchecked(TEST_MARKET_ID_1)],
+            },
+        )
+    };
+
+    set_buffers(deps.as_mut_deps(), "usdc").unwrap();
+    let result = set_eth_route(deps.as_mut_deps());
+    assert!(
+        matches!(result, Err(ContractError::BufferNotConfigured { ref denom }) if denom == "usdt"),
+        "route quoted in a denom without buffer should be rejected, got {result:?}"
+    );
+
+    set_buffers(deps.as_mut_deps(), "usdt").unwrap();
+    set_eth_route(deps.as_mut_deps()).unwrap();
+
+    let buffers: Vec<BufferStatus> = from_json(query(deps.as_ref(), env, QueryMsg::GetBuffers {}).unwrap()).unwrap();
+    assert_eq!(
+        buffers,
+        vec![BufferStatus {
+            denom: "usdt".to_string(),
+            min_balance: FPDecimal::from(1000u128),
+            balance: FPDecimal::from(1_000_000_000_000_000u128),
+            ledger_balance: FPDecimal::ZERO,
+            is_underfunded: false,
+            is_low: false,
+            is_critical: false,
+        }],
+        "wrong buffer status"
+    );
+
+    let result = set_buffers(deps.as_mut_deps(), "usdc");
+    assert!(
+        result
+            .as_ref()
+            .is_err_and(|err| err.to_string().contains("Route eth -> usdt still needs a buffer of usdt")),
+        "removing the buffer of a stored route should be rejected, got {result:?}"
+    );
+}
+
+#[test]
+fn swaps_fail_early_when_buffer_they_buy_with_is_underfunded() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+
+    // contract holds 10^15 of every denom, swapper funds are not counted
+    let min_balance = FPDecimal::from(1_000_000_000_000_000u128);
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::SetBufferDenoms {
+            buffers: vec![BufferDenom {
+                denom: "usdt".to_string(),
+                min_balance,
+                target_balance: None,
+                low_balance: None,
+                critical_balance: None,
+                critical_max_use_bps: 0,
+            }],
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![MarketId::unchecked(TEST_MARKET_ID_2)],
+    )
+    .unwrap();
+
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &coins(1000, "usdt")),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "inj".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: None,
+            tip_dust: false,
+        },
+    );
+    assert!(
+        matches!(result, Err(ContractError::BufferUnderfunded { ref denom, balance, .. })
+            if denom == "usdt" && balance == min_balance - FPDecimal::from(1000u128)),
+        "buy with underfunded buffer should fail early, got {result:?}"
+    );
+
+    // sells do not need the buffer
+    execute(
+        deps.as_mut(),
+        env,
+        message_info(&Addr::unchecked(ALICE), &coins(10, "inj")),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "usdt".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: None,
+            tip_dust: false,
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn buffer_pnl_is_counted_from_baseline_and_valued_with_price_sources() {
+    let mut env = mock_env();
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler = create_oracle_query_handler(
+        FPDecimal::must_from_str("0.5"),
+        FPDecimal::ONE,
+        FPDecimal::ONE,
+        FPDecimal::ZERO,
+        FPDecimal::ZERO,
+        0,
+        0,
+    );
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![MarketId::unchecked(TEST_MARKET_ID_2)],
+    )
+    .unwrap();
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::SetBufferPriceSources {
+            sources: vec![
+                BufferPricing {
+                    denom: "inj".to_string(),
+                    decimals: 0,
+                    source: BufferPriceSource::Route {
+                        target_denom: "usdt".to_string(),
+                        quantity: FPDecimal::from(10u128),
+                        target_decimals: 0,
+                    },
+                },
+                BufferPricing {
+                    denom: "usdt".to_string(),
+                    decimals: 0,
+                    source: BufferPriceSource::Oracle {
+                        oracle_type: OracleType::PriceFeed,
+                        base: "usdt".to_string(),
+                        quote: "usd".to_string(),
+                    },
+                },
+            ],
+        },
+    )
+    .unwrap();
+
+    // principal is not profit
+    record_buffer_deposit(deps.as_mut_deps().storage, "usdt", FPDecimal::from(1000u128)).unwrap();
+    record_buffer_fees(deps.as_mut_deps().storage, "usdt", FPDecimal::from(10u128)).unwrap();
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", FPDecimal::from(3u128)).unwrap();
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", -FPDecimal::ONE).unwrap();
+    record_buffer_rounding(deps.as_mut_deps().storage, "eth", FPDecimal::from(2u128)).unwrap();
+    record_buffer_fees(deps.as_mut_deps().storage, "inj", FPDecimal::from(20u128)).unwrap();
+    // rebates go to swappers, they are reported but not profit
+    record_buffer_rebate(deps.as_mut_deps().storage, "usdt", FPDecimal::from(4u128)).unwrap();
+
+    let get_pnl = |deps: Deps<InjectiveQueryWrapper>, env: &Env| -> BufferPnlResponse {
+        from_json(query(deps, env.to_owned(), QueryMsg::GetBufferPnl {}).unwrap()).unwrap()
+    };
+
+    let pnl = get_pnl(deps.as_ref(), &env);
+    assert_eq!(pnl.baseline_time, None, "baseline should not be set");
+    assert_eq!(
+        pnl.denoms[2],
+        BufferPnl {
+            denom: "usdt".to_string(),
+            fees: FPDecimal::from(10u128),
+            rounding_gains: FPDecimal::from(3u128),
+            rounding_losses: FPDecimal::ONE,
+            rebalanced: FPDecimal::ZERO,
+            net: FPDecimal::from(12u128),
+            rebates_passed_on: FPDecimal::from(4u128),
+            price: Some(FPDecimal::must_from_str("0.5")),
+            net_value: Some(FPDecimal::from(6u128)),
+        },
+        "wrong usdt pnl"
+    );
+    assert_eq!(pnl.denoms[0].denom, "eth", "eth should be reported");
+    assert_eq!(pnl.denoms[0].net_value, None, "eth has no price source");
+    // 10 inj sell into the best inj/usdt bid of 800 for 7980 usdt after fees
+    assert_eq!(
+        pnl.denoms[1].price,
+        Some(FPDecimal::from(798u128)),
+        "inj should be priced through its route"
+    );
+    assert_eq!(pnl.denoms[1].net_value, Some(FPDecimal::from(15960u128)), "wrong inj value");
+    assert_eq!(pnl.total_net_value, FPDecimal::from(15966u128), "only valued denoms add to total");
+
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &[]),
+        ExecuteMsg::ResetBufferPnlBaseline {},
+    );
+    assert!(result.is_err(), "only admin can reset the baseline");
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::ResetBufferPnlBaseline {},
+    )
+    .unwrap();
+    record_buffer_rounding(deps.as_mut_deps().storage, "usdt", -FPDecimal::from(4u128)).unwrap();
+    env.block.time = env.block.time.plus_seconds(60);
+
+    let pnl = get_pnl(deps.as_ref(), &env);
+    assert_eq!(pnl.baseline_time, Some(mock_env().block.time), "baseline should be set at reset");
+    assert_eq!(pnl.denoms[2].fees, FPDecimal::ZERO, "fees before the baseline should not count");
+    assert_eq!(pnl.denoms[2].net, -FPDecimal::from(4u128), "wrong usdt net since baseline");
+    assert_eq!(pnl.total_net_value, -FPDecimal::from(2u128), "wrong total since baseline");
+}
+
+#[test]
+fn buffer_pnl_values_denoms_of_different_decimals_in_whole_units() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|querier| {
+        querier.oracle_price_response_handler = create_oracle_query_handler(
+            FPDecimal::must_from_str("0.5"),
+            FPDecimal::ONE,
+            FPDecimal::ONE,
+            FPDecimal::ZERO,
+            FPDecimal::ZERO,
+            0,
+            0,
+        );
+    });
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+
+    let oracle_pricing = |denom: &str, decimals: u8| BufferPricing {
+        denom: denom.to_string(),
+        decimals,
+        source: BufferPriceSource::Oracle {
+            oracle_type: OracleType::PriceFeed,
+            base: denom.to_string(),
+            quote: "usd".to_string(),
+        },
+    };
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::SetBufferPriceSources {
+            sources: vec![
+                oracle_pricing("eth", 18),
+                oracle_pricing("usdt", 6),
+                // there is no inj/atom route to price inj with
+                BufferPricing {
+                    denom: "inj".to_string(),
+                    decimals: 18,
+                    source: BufferPriceSource::Route {
+                        target_denom: "atom".to_string(),
+                        quantity: FPDecimal::ONE,
+                        target_decimals: 6,
+                    },
+                },
+            ],
+        },
+    )
+    .unwrap();
+
+    // 2 eth and 3 usdt, both at 0.5 usd
+    record_buffer_fees(deps.as_mut_deps().storage, "eth", FPDecimal::from(2_000_000_000_000_000_000u128)).unwrap();
+    record_buffer_fees(deps.as_mut_deps().storage, "usdt", FPDecimal::from(3_000_000u128)).unwrap();
+    record_buffer_fees(deps.as_mut_deps().storage, "inj", FPDecimal::from(1_000_000_000_000_000_000u128)).unwrap();
+
+    let pnl: BufferPnlResponse = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferPnl {}).unwrap()).unwrap();
+    assert_eq!(pnl.denoms[0].net_value, Some(FPDecimal::ONE), "wrong eth value");
+    assert_eq!(pnl.denoms[2].net_value, Some(FPDecimal::must_from_str("1.5")), "wrong usdt value");
+    assert_eq!(pnl.denoms[1].price, None, "failing price source should leave inj unpriced");
+    assert_eq!(
+        pnl.total_net_value,
+        FPDecimal::must_from_str("2.5"),
+        "values should add up in whole units"
+    );
+}
+
+#[test]
+fn buys_below_critical_buffer_balance_are_limited_to_share_of_buffer() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![MarketId::unchecked(TEST_MARKET_ID_2)],
+    )
+    .unwrap();
+
+    let set_critical_use = |deps: DepsMut<InjectiveQueryWrapper>, critical_max_use_bps: u16| {
+        execute(
+            deps,
+            mock_env(),
+            message_info(&admin, &[]),
+            ExecuteMsg::SetBufferDenoms {
+                buffers: vec![BufferDenom {
+                    denom: "usdt".to_string(),
+                    min_balance: FPDecimal::ZERO,
+                    target_balance: None,
+                    low_balance: Some(FPDecimal::from(3_000_000_000_000_000u128)),
+                    // contract holds 10^15 of every denom
+                    critical_balance: Some(FPDecimal::from(2_000_000_000_000_000u128)),
+                    critical_max_use_bps,
+                }],
+            },
+        )
+        .unwrap();
+    };
+    let buy_inj = |deps: DepsMut<InjectiveQueryWrapper>| {
+        execute(
+            deps,
+            env.to_owned(),
+            message_info(&Addr::unchecked(ALICE), &coins(1000000, "usdt")),
+            ExecuteMsg::SwapMinOutput {
+                target_denom: "inj".to_string(),
+                min_output_quantity: FPDecimal::ONE,
+                integrator: None,
+                tip_dust: false,
+            },
+        )
+    };
+
+    set_critical_use(deps.as_mut_deps(), 0);
+    let result = buy_inj(deps.as_mut_deps());
+    assert!(
+        matches!(result, Err(ContractError::BufferCritical { ref denom, available, .. }) if denom == "usdt" && available.is_zero()),
+        "buy needing buffer below critical balance should be rejected, got {result:?}"
+    );
+
+    let buffers: Vec<BufferStatus> = from_json(query(deps.as_ref(), env.to_owned(), QueryMsg::GetBuffers {}).unwrap()).unwrap();
+    assert!(buffers[0].is_low && buffers[0].is_critical, "buffer should be reported low and critical");
+
+    set_critical_use(deps.as_mut_deps(), 100);
+    buy_inj(deps.as_mut_deps()).unwrap();
+}
+
+#[test]
+fn admin_withdrawals_keep_reserve_unless_in_emergency_mode() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+
+    // contract holds 10^15 of every denom
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetWithdrawalReserves {
+            reserves: vec![FPCoin {
+                amount: FPDecimal::from(999_999_999_999_000u128),
+                denom: "usdt".to_string(),
+            }],
+        },
+    )
+    .unwrap();
+
+    let withdraw = |amount: u128| ExecuteMsg::WithdrawSupportFunds {
+        coins: coins(amount, "usdt"),
+        target_address: Addr::unchecked(BOB),
+    };
+
+    let result = execute(deps.as_mut(), env.to_owned(), admin.to_owned(), withdraw(1001));
+    assert!(
+        matches!(result, Err(ContractError::WithdrawalBelowReserve { ref denom, .. }) if denom == "usdt"),
+        "withdrawal below reserve should be rejected, got {result:?}"
+    );
+
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::WithdrawSupportFunds {
+            coins: vec![coin(600, "usdt"), coin(401, "usdt")],
+            target_address: Addr::unchecked(BOB),
+        },
+    );
+    assert!(
+        matches!(result, Err(ContractError::WithdrawalBelowReserve { ref denom, .. }) if denom == "usdt"),
+        "amounts of the same denom should be checked against the reserve together, got {result:?}"
+    );
+
+    let res = execute(deps.as_mut(), env.to_owned(), admin.to_owned(), withdraw(1000)).unwrap();
+    let event = res.events.iter().find(|e| e.ty == "withdrawal").expect("withdrawal event expected");
+    let attribute = |key: &str| event.attributes.iter().find(|a| a.key == key).unwrap().value.to_owned();
+    assert_eq!(attribute("balance_before"), "1000000000000000", "wrong balance before withdrawal");
+    assert_eq!(attribute("balance_after"), "999999999999000", "wrong balance after withdrawal");
+
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &[]),
+        ExecuteMsg::SetEmergencyMode { enabled: true },
+    );
+    assert!(result.is_err(), "expected error on non-admin enabling emergency mode");
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetEmergencyMode { enabled: true },
+    )
+    .unwrap();
+    execute(deps.as_mut(), env.to_owned(), admin, withdraw(1001)).unwrap();
+
+    let reserves: WithdrawalReservesResponse = from_json(query(deps.as_ref(), env, QueryMsg::GetWithdrawalReserves {}).unwrap()).unwrap();
+    assert!(reserves.emergency_mode, "emergency mode should be reported");
+}
+
+#[test]
+fn admin_withdraws_funds_beyond_buffer_ledger_and_accrued_fees_as_excess() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+
+    // contract holds 10^15 inj, of which 4 * 10^14 back the buffer and 10^14 are accrued protocol fees
+    record_buffer_deposit(deps.as_mut_deps().storage, "inj", FPDecimal::from(400_000_000_000_000u128)).unwrap();
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "inj", FPDecimal::from(100_000_000_000_000u128)).unwrap();
+
+    let withdraw_excess = ExecuteMsg::WithdrawAllExcessFunds {
+        target_address: Addr::unchecked(BOB),
+    };
+    let result = execute(
+        deps.as_mut(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(ALICE), &[]),
+        withdraw_excess.to_owned(),
+    );
+    assert!(result.is_err(), "expected error on non-admin withdrawing excess funds");
+
+    let res = execute(deps.as_mut(), env.to_owned(), admin.to_owned(), withdraw_excess.to_owned()).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: BOB.to_string(),
+            amount: vec![coin(500_000_000_000_000, "inj")],
+        }),
+        "excess beyond ledger and accrued fees should be withdrawn"
+    );
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetWithdrawalReserves {
+            reserves: vec![FPCoin {
+                amount: FPDecimal::from(600_000_000_000_000u128),
+                denom: "inj".to_string(),
+            }],
+        },
+    )
+    .unwrap();
+
+    let res = execute(deps.as_mut(), env, admin, withdraw_excess).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: BOB.to_string(),
+            amount: vec![coin(400_000_000_000_000, "inj")],
+        }),
+        "excess withdrawal should stop at the reserve"
+    );
+}
+
+#[test]
+fn admin_support_fund_withdrawals_leave_buffer_and_accrued_fees_alone() {
+    let env = mock_env();
+    let mut deps = inj_mock_deps(|_| {});
+    CONFIG.save(deps.as_mut_deps().storage, &config_with_max_buffer_withdrawal(0)).unwrap();
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+
+    // contract holds 10^15 usdt, of which 4 * 10^14 back the buffer and 10^14 are accrued protocol fees
+    record_buffer_deposit(deps.as_mut_deps().storage, "usdt", FPDecimal::from(400_000_000_000_000u128)).unwrap();
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "usdt", FPDecimal::from(100_000_000_000_000u128)).unwrap();
+
+    let withdraw = |amount: u128| ExecuteMsg::WithdrawSupportFunds {
+        coins: coins(amount, "usdt"),
+        target_address: Addr::unchecked(BOB),
+    };
+    let result = execute(deps.as_mut(), env.to_owned(), admin.to_owned(), withdraw(500_000_000_000_001));
+    assert!(result.is_err(), "withdrawal of funds owed to the buffer should be rejected");
+
+    execute(deps.as_mut(), env.to_owned(), admin, withdraw(500_000_000_000_000)).unwrap();
+
+    // the admin holds shares of the whole buffer, which still backs them
+    assert_eq!(
+        get_positions(deps.as_ref(), &env, TEST_USER_ADDR),
+        usdt_position(400_000_000_000_000, 400_000_000_000_000, 400_000_000_000_000),
+        "support fund withdrawal should not reduce the buffer"
+    );
+}
@@ -1,13 +1,14 @@
 use crate::{
-    contract::execute,
-    msg::{ExecuteMsg, FeeRecipient},
+    contract::{execute, query},
+    fees::MAX_PROTOCOL_FEE_BPS,
+    msg::{ExecuteMsg, FeeRecipient, QueryMsg},
     state::CONFIG,
-    testing::test_utils::{TEST_CONTRACT_ADDR, TEST_USER_ADDR},
+    testing::test_utils::{test_config, TEST_CONTRACT_ADDR, TEST_USER_ADDR},
     types::Config,
 };
 
 use cosmwasm_std::testing::{message_info, mock_env};
-use cosmwasm_std::{coins, Addr};
+use cosmwasm_std::{coins, from_json, Addr, Storage};
 
 use injective_cosmwasm::{inj_mock_deps, OwnedDepsExt};
 
@@ -16,8 +17,8 @@ pub fn admin_can_update_config() {
     let mut deps = inj_mock_deps(|_| {});
 
     let config = Config {
-        fee_recipient: Addr::unchecked(TEST_CONTRACT_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        ..test_config()
     };
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -29,6 +30,14 @@ pub fn admin_can_update_config() {
     let msg = ExecuteMsg::UpdateConfig {
         admin: Some(new_admin.clone()),
         fee_recipient: Some(FeeRecipient::Address(new_fee_recipient.clone())),
+        protocol_fee_bps: None,
+        treasury: None,
+        max_integrator_fee_bps: None,
+        burn_share_bps: None,
+        depositor_fee_share_bps: None,
+        depositor_rebate_share_bps: None,
+        max_buffer_withdrawal_bps: None,
+        accrue_protocol_fees: None,
     };
 
     let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -36,7 +45,11 @@ pub fn admin_can_update_config() {
 
     let config = CONFIG.load(deps.as_mut_deps().storage).unwrap();
     assert_eq!(config.admin, new_admin, "admin was not updated");
-    assert_eq!(config.fee_recipient, new_fee_recipient, "fee_recipient was not updated");
+    assert_eq!(
+        config.fee_recipient,
+        FeeRecipient::Address(new_fee_recipient.clone()),
+        "fee_recipient was not updated"
+    );
 
     res.events
         .iter()
@@ -62,8 +75,8 @@ pub fn non_admin_cannot_update_config() {
     let mut deps = inj_mock_deps(|_| {});
 
     let config = Config {
-        fee_recipient: Addr::unchecked(TEST_CONTRACT_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        ..test_config()
     };
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -75,8 +88,159 @@ pub fn non_admin_cannot_update_config() {
     let msg = ExecuteMsg::UpdateConfig {
         admin: Some(new_admin),
         fee_recipient: Some(FeeRecipient::Address(new_fee_recipient)),
+        protocol_fee_bps: None,
+        treasury: None,
+        max_integrator_fee_bps: None,
+        burn_share_bps: None,
+        depositor_fee_share_bps: None,
+        depositor_rebate_share_bps: None,
+        max_buffer_withdrawal_bps: None,
+        accrue_protocol_fees: None,
     };
 
     let res = execute(deps.as_mut(), mock_env(), info, msg);
     assert!(res.is_err(), "expected error on non-admin update config");
 }
+
+#[test]
+pub fn admin_cannot_set_protocol_fee_without_treasury() {
+    let mut deps = inj_mock_deps(|_| {});
+
+    let config = Config {
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        ..test_config()
+    };
+    CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
+
+    let info = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+
+    let msg = ExecuteMsg::UpdateConfig {
+        admin: None,
+        fee_recipient: None,
+        protocol_fee_bps: Some(30),
+        treasury: None,
+        max_integrator_fee_bps: None,
+        burn_share_bps: None,
+        depositor_fee_share_bps: None,
+        depositor_rebate_share_bps: None,
+        max_buffer_withdrawal_bps: None,
+        accrue_protocol_fees: None,
+    };
+    let res = execute(deps.as_mut(), mock_env(), info.to_owned(), msg);
+    assert!(res.is_err(), "expected error when setting protocol fee without treasury");
+
+    let msg = ExecuteMsg::UpdateConfig {
+        admin: None,
+        fee_recipient: None,
+        protocol_fee_bps: Some(MAX_PROTOCOL_FEE_BPS + 1),
+        treasury: Some(Addr::unchecked("treasury")),
+        max_integrator_fee_bps: None,
+        burn_share_bps: None,
+        depositor_fee_share_bps: None,
+        depositor_rebate_share_bps: None,
+        max_buffer_withdrawal_bps: None,
+        accrue_protocol_fees: None,
+    };
+    let res = execute(deps.as_mut(), mock_env(), info.to_owned(), msg);
+    assert!(res.is_err(), "expected error when setting protocol fee above maximum");
+
+    let msg = ExecuteMsg::UpdateConfig {
+        admin: None,
+        fee_recipient: None,
+        protocol_fee_bps: Some(30),
+        treasury: Some(Addr::unchecked("treasury")),
+        max_integrator_fee_bps: None,
+        burn_share_bps: None,
+        depositor_fee_share_bps: None,
+        depositor_rebate_share_bps: None,
+        max_buffer_withdrawal_bps: None,
+        accrue_protocol_fees: None,
+    };
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let config = CONFIG.load(deps.as_mut_deps().storage).unwrap();
+    assert_eq!(config.protocol_fee_bps, 30, "protocol_fee_bps was not updated");
+    assert_eq!(config.treasury, Some(Addr::unchecked("treasury")), "treasury was not updated");
+}
+
+#[test]
+pub fn admin_can_manage_fee_exempt_addresses() {
+    let mut deps = inj_mock_deps(|_| {});
+
+    let config = Config {
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        ..test_config()
+    };
+    CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
+
+    let rebalancer = Addr::unchecked("rebalancer");
+    let partner = Addr::unchecked("partner");
+
+    let msg = ExecuteMsg::UpdateFeeExemptAddresses {
+        add: vec![rebalancer.to_owned(), partner.to_owned()],
+        remove: vec![],
+    };
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        message_info(&Addr::unchecked("non_admin"), &[]),
+        msg.to_owned(),
+    );
+    assert!(res.is_err(), "expected error on non-admin update of fee-exempt addresses");
+
+    execute(deps.as_mut(), mock_env(), message_info(&Addr::unchecked(TEST_USER_ADDR), &[]), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateFeeExemptAddresses {
+        add: vec![],
+        remove: vec![partner.to_owned()],
+    };
+    execute(deps.as_mut(), mock_env(), message_info(&Addr::unchecked(TEST_USER_ADDR), &[]), msg).unwrap();
+
+    let exempt_addresses: Vec<Addr> = from_json(
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetFeeExemptAddresses {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(exempt_addresses, vec![rebalancer.to_owned()], "wrong fee-exempt addresses");
+
+    let is_exempt = |address: &Addr| -> bool {
+        from_json(query(deps.as_ref(), mock_env(), QueryMsg::IsFeeExempt { address: address.to_owned() }).unwrap()).unwrap()
+    };
+    assert!(is_exempt(&rebalancer), "rebalancer should be fee-exempt");
+    assert!(!is_exempt(&partner), "removed partner should not be fee-exempt");
+}
+
+#[test]
+fn it_reads_fee_recipient_address_of_configs_stored_before_fee_recipient_modes() {
+    let mut deps = inj_mock_deps(|_| {});
+    let stored_config = format!(r#"{{"fee_recipient":"{TEST_CONTRACT_ADDR}","admin":"{TEST_USER_ADDR}"}}"#);
+    deps.storage.set(b"config", stored_config.as_bytes());
+
+    let config = CONFIG.load(deps.as_ref().storage).unwrap();
+    assert_eq!(
+        config.fee_recipient,
+        FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        "fee recipient address of stored config was not read"
+    );
+    assert_eq!(config.admin, Addr::unchecked(TEST_USER_ADDR), "admin of stored config was not read");
+
+    for fee_recipient in [FeeRecipient::Sender, FeeRecipient::Address(Addr::unchecked(TEST_USER_ADDR))] {
+        let config = Config {
+            fee_recipient: fee_recipient.to_owned(),
+            ..config.to_owned()
+        };
+        CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+        assert_eq!(
+            CONFIG.load(deps.as_ref().storage).unwrap().fee_recipient,
+            fee_recipient,
+            "fee recipient was not stored"
+        );
+    }
+}
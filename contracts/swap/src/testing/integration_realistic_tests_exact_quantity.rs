@@ -2,14 +2,14 @@ use crate::{
     helpers::Scaled,
     msg::{ExecuteMsg, QueryMsg},
     testing::test_utils::{
-        are_fpdecimals_approximately_equal, assert_fee_is_as_expected, create_ninja_inj_both_side_orders,
+        are_fpdecimals_approximately_equal, assert_fee_is_as_expected, assert_swap_payouts, create_ninja_inj_both_side_orders,
         create_realistic_atom_usdt_sell_orders_from_spreadsheet, create_realistic_eth_usdt_buy_orders_from_spreadsheet,
         create_realistic_eth_usdt_sell_orders_from_spreadsheet, create_realistic_inj_usdt_buy_orders_from_spreadsheet,
         create_realistic_inj_usdt_sell_orders_from_spreadsheet, create_realistic_limit_order, create_realistic_usdt_usdc_both_side_orders,
         human_to_dec, init_rich_account, init_self_relaying_contract_and_get_address, initial_coin, launch_realistic_atom_usdt_spot_market,
         launch_realistic_inj_usdt_spot_market, launch_realistic_ninja_inj_spot_market, launch_realistic_usdt_usdc_spot_market,
         launch_realistic_weth_usdt_spot_market, must_init_account_with_funds_and_setting_denoms, query_all_bank_balances, query_bank_balance,
-        set_route_and_assert_success, str_coin, Decimals, OrderSide, ATOM, ETH, INJ, INJ_2, NINJA, USDC, USDT,
+        query_buffer_ledger_balance, set_route_and_assert_success, str_coin, Decimals, OrderSide, ATOM, ETH, INJ, INJ_2, NINJA, USDC, USDT,
     },
     types::{FPCoin, SwapEstimationResult},
 };
@@ -148,6 +148,9 @@ fn it_correctly_swaps_eth_to_get_very_high_exact_amount_of_atom() {
                 source_denom: ETH.to_string(),
                 target_denom: ATOM.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -157,6 +160,8 @@ fn it_correctly_swaps_eth_to_get_very_high_exact_amount_of_atom() {
         &ExecuteMsg::SwapExactOutput {
             target_denom: ATOM.to_string(),
             target_output_quantity: exact_quantity_to_receive,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(eth_to_swap, ETH, Decimals::Eighteen)],
         &swapper,
@@ -323,6 +328,9 @@ fn it_correctly_swaps_inj_to_get_very_high_exact_amount_of_atom() {
                 source_denom: INJ_2.to_string(),
                 target_denom: ATOM.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -332,6 +340,8 @@ fn it_correctly_swaps_inj_to_get_very_high_exact_amount_of_atom() {
         &ExecuteMsg::SwapExactOutput {
             target_denom: ATOM.to_string(),
             target_output_quantity: exact_quantity_to_receive,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
         &swapper,
@@ -490,6 +500,9 @@ fn it_swaps_inj_to_get_very_high_exact_amount_of_eth() {
                 source_denom: INJ_2.to_string(),
                 target_denom: ETH.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -499,6 +512,8 @@ fn it_swaps_inj_to_get_very_high_exact_amount_of_eth() {
         &ExecuteMsg::SwapExactOutput {
             target_denom: ETH.to_string(),
             target_output_quantity: exact_quantity_to_receive,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
         &swapper,
@@ -615,6 +630,9 @@ fn it_correctly_swaps_between_markets_using_different_quote_assets_self_relaying
                 to_quantity: to_output_quantity,
                 source_denom: INJ_2.to_string(),
                 target_denom: USDC.to_string(),
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -652,6 +670,8 @@ fn it_correctly_swaps_between_markets_using_different_quote_assets_self_relaying
         &ExecuteMsg::SwapExactOutput {
             target_denom: USDC.to_string(),
             target_output_quantity: to_output_quantity,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
         &swapper,
@@ -789,6 +809,8 @@ fn it_correctly_swaps_between_markets_using_different_quote_assets_self_relaying
         &ExecuteMsg::SwapExactOutput {
             target_denom: NINJA.to_string(),
             target_output_quantity: to_output_quantity,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(usdt_to_swap, USDT, Decimals::Six)],
         &swapper,
@@ -889,6 +911,8 @@ fn it_doesnt_lose_buffer_if_exact_swap_of_eth_to_atom_is_executed_multiple_times
             &ExecuteMsg::SwapExactOutput {
                 target_denom: ATOM.to_string(),
                 target_output_quantity: human_to_dec("906", Decimals::Six),
+                integrator: None,
+                tip_dust: false,
             },
             &[str_coin(eth_to_swap, ETH, Decimals::Eighteen)],
             &swapper,
@@ -990,6 +1014,9 @@ fn it_reverts_when_funds_provided_are_below_required_to_get_exact_amount() {
                 source_denom: INJ_2.to_string(),
                 target_denom: ATOM.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -1000,6 +1027,8 @@ fn it_reverts_when_funds_provided_are_below_required_to_get_exact_amount() {
             &ExecuteMsg::SwapExactOutput {
                 target_denom: ATOM.to_string(),
                 target_output_quantity: exact_quantity_to_receive,
+                integrator: None,
+                tip_dust: false,
             },
             &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
             &swapper,
@@ -1098,20 +1127,28 @@ fn exact_two_hop_eth_atom_swap_test_template(exact_quantity_to_receive: FPDecima
                 source_denom: ETH.to_string(),
                 target_denom: ATOM.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
 
-    wasm.execute(
-        &contr_addr,
-        &ExecuteMsg::SwapExactOutput {
-            target_denom: ATOM.to_string(),
-            target_output_quantity: exact_quantity_to_receive,
-        },
-        &[str_coin(eth_to_swap, ETH, Decimals::Eighteen)],
-        &swapper,
-    )
-    .unwrap();
+    let ledger_balance_before = query_buffer_ledger_balance(&wasm, &contr_addr, USDT);
+
+    let response = wasm
+        .execute(
+            &contr_addr,
+            &ExecuteMsg::SwapExactOutput {
+                target_denom: ATOM.to_string(),
+                target_output_quantity: exact_quantity_to_receive,
+                integrator: None,
+                tip_dust: false,
+            },
+            &[str_coin(eth_to_swap, ETH, Decimals::Eighteen)],
+            &swapper,
+        )
+        .unwrap();
 
     let expected_difference = human_to_dec(eth_to_swap, Decimals::Eighteen) - query_result.result_quantity;
     let swapper_eth_balance_after = query_bank_balance(&bank, ETH, swapper.address().as_str());
@@ -1158,6 +1195,17 @@ fn exact_two_hop_eth_atom_swap_test_template(exact_quantity_to_receive: FPDecima
         contract_usdt_balance_before.scaled(Decimals::Six.get_decimals().neg()),
         max_diff.scaled(Decimals::Six.get_decimals().neg())
     );
+
+    assert_swap_payouts(
+        &wasm,
+        &bank,
+        &contr_addr,
+        swapper.address().as_str(),
+        &response,
+        USDT,
+        contract_usdt_balance_before,
+        ledger_balance_before,
+    );
 }
 
 // source more or less similarly priced as target
@@ -1225,6 +1273,9 @@ fn exact_two_hop_inj_atom_swap_test_template(exact_quantity_to_receive: FPDecima
                 source_denom: INJ_2.to_string(),
                 target_denom: ATOM.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -1234,6 +1285,8 @@ fn exact_two_hop_inj_atom_swap_test_template(exact_quantity_to_receive: FPDecima
         &ExecuteMsg::SwapExactOutput {
             target_denom: ATOM.to_string(),
             target_output_quantity: exact_quantity_to_receive,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
         &swapper,
@@ -1351,6 +1404,9 @@ fn exact_two_hop_inj_eth_swap_test_template(exact_quantity_to_receive: FPDecimal
                 source_denom: INJ_2.to_string(),
                 target_denom: ETH.to_string(),
                 to_quantity: exact_quantity_to_receive,
+                route: None,
+                trader: None,
+                integrator: None,
             },
         )
         .unwrap();
@@ -1360,6 +1416,8 @@ fn exact_two_hop_inj_eth_swap_test_template(exact_quantity_to_receive: FPDecimal
         &ExecuteMsg::SwapExactOutput {
             target_denom: ETH.to_string(),
             target_output_quantity: exact_quantity_to_receive,
+            integrator: None,
+            tip_dust: false,
         },
         &[str_coin(inj_to_swap, INJ_2, Decimals::Eighteen)],
         &swapper,
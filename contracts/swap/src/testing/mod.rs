@@ -1,4 +1,5 @@
 mod authz_tests;
+mod buffer_tests;
 mod config_tests;
 mod integration_realistic_tests_exact_quantity;
 mod integration_realistic_tests_min_quantity;
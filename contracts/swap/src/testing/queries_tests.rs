@@ -1,22 +1,26 @@
 use crate::{
-    admin::set_route,
-    contract::instantiate,
+    admin::{set_buffer_caps, set_buffer_denoms, set_exempt_buffer_budget, set_fee_tiers, set_route, update_fee_exempt_addresses},
+    contract::{instantiate, query},
+    error::ContractError,
+    fees::get_output_fee_rates,
     helpers::Scaled,
-    msg::{FeeRecipient, InstantiateMsg},
-    queries::{estimate_swap_result, SwapQuantity},
-    state::get_all_swap_routes,
+    msg::{FeeRecipient, InstantiateMsg, QueryMsg},
+    queries::{estimate_swap_result, get_hypothetical_swap_result, get_max_swap_size, get_price_ladder, SwapQuantity},
+    state::{get_all_swap_routes, read_swap_route, record_trader_volume, CONFIG},
     testing::test_utils::{
-        are_fpdecimals_approximately_equal, human_to_dec, mock_deps_eth_inj, mock_realistic_deps_eth_atom, Decimals, MultiplierQueryBehavior,
-        TEST_USER_ADDR,
+        are_fpdecimals_approximately_equal, create_mock_spot_market, create_price_level, human_to_dec, mock_deps_eth_inj,
+        mock_realistic_deps_eth_atom, Decimals, MultiplierQueryBehavior, TEST_USER_ADDR,
+    },
+    types::{
+        BufferCap, BufferDenom, FPCoin, FeeTier, HypotheticalMarket, Integrator, OutputFeeRates, PriceLadderPoint, SwapEstimationResult, SwapRoute,
     },
-    types::{FPCoin, SwapRoute},
 };
 use cosmwasm_std::{
-    coin,
+    coin, from_json,
     testing::{message_info, mock_env},
-    Addr,
+    Addr, Deps, StdError,
 };
-use injective_cosmwasm::{OwnedDepsExt, TEST_MARKET_ID_1, TEST_MARKET_ID_2};
+use injective_cosmwasm::{InjectiveQueryWrapper, MarketId, OwnedDepsExt, PriceLevel, TEST_MARKET_ID_1, TEST_MARKET_ID_2};
 use injective_math::FPDecimal;
 
 use std::ops::Neg;
@@ -57,6 +61,7 @@ fn test_calculate_swap_price_external_fee_recipient_from_source_quantity() {
         "eth".to_string(),
         "inj".to_string(),
         SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -132,6 +137,7 @@ fn test_calculate_swap_price_external_fee_recipient_from_target_quantity() {
         "eth".to_string(),
         "inj".to_string(),
         SwapQuantity::OutputQuantity(FPDecimal::from_str("2888.221").unwrap()),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -207,6 +213,7 @@ fn test_calculate_swap_price_self_fee_recipient_from_source_quantity() {
         "eth".to_string(),
         "inj".to_string(),
         SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -278,6 +285,7 @@ fn test_calculate_swap_price_self_fee_recipient_from_target_quantity() {
         "eth".to_string(),
         "inj".to_string(),
         SwapQuantity::OutputQuantity(FPDecimal::from_str("2893.886").unwrap()),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -356,6 +364,7 @@ fn test_calculate_estimate_when_selling_both_quantity_directions_simple() {
         "eth".to_string(),
         "usdt".to_string(),
         SwapQuantity::InputQuantity(eth_input_amount),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -394,6 +403,7 @@ fn test_calculate_estimate_when_selling_both_quantity_directions_simple() {
         "eth".to_string(),
         "usdt".to_string(),
         SwapQuantity::OutputQuantity(expected_usdt_result_quantity),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -460,6 +470,7 @@ fn test_calculate_estimate_when_buying_both_quantity_directions_simple() {
         "usdt".to_string(),
         "eth".to_string(),
         SwapQuantity::InputQuantity(usdt_input_amount),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -498,6 +509,7 @@ fn test_calculate_estimate_when_buying_both_quantity_directions_simple() {
         "usdt".to_string(),
         "eth".to_string(),
         SwapQuantity::OutputQuantity(expected_eth_result_quantity),
+        &OutputFeeRates::default(),
     )
     .unwrap();
 
@@ -589,18 +601,24 @@ fn get_all_queries_returns_expected_array_if_routes_are_set() {
         source_denom: "eth".to_string(),
         target_denom: "inj".to_string(),
         steps: vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     let eth_usdt_route = SwapRoute {
         source_denom: "eth".to_string(),
         target_denom: "usdt".to_string(),
         steps: vec![TEST_MARKET_ID_1.into()],
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     let usdt_inj_route = SwapRoute {
         source_denom: "usdt".to_string(),
         target_denom: "inj".to_string(),
         steps: vec![TEST_MARKET_ID_2.into()],
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     let all_routes = all_routes_result.unwrap();
@@ -613,3 +631,1303 @@ fn get_all_queries_returns_expected_array_if_routes_are_set() {
     let all_routes_result_paginated = get_all_swap_routes(deps.as_ref().storage, None, Some(1u32));
     assert_eq!(all_routes_result_paginated.unwrap().len(), 1);
 }
+
+#[test]
+fn get_max_swap_size_returns_largest_quantities_supported_by_orderbooks() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &Addr::unchecked(TEST_USER_ADDR),
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let max_swap_size = get_max_swap_size(deps.as_ref(), &mock_env(), "eth".to_string(), "inj".to_string(), None, None, None).unwrap();
+
+    // ETH/USDT orderbook has 12 ETH of buy liquidity in total
+    assert_eq!(
+        max_swap_size.max_input_quantity,
+        FPDecimal::must_from_str("12"),
+        "Wrong max input quantity"
+    );
+
+    // 12 ETH can be swapped for 2888.221 INJ, so 2888 is the largest whole amount that can be requested
+    assert_eq!(
+        max_swap_size.max_output_quantity,
+        FPDecimal::must_from_str("2888"),
+        "Wrong max output quantity"
+    );
+
+    let over_max_input = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(max_swap_size.max_input_quantity + FPDecimal::ONE),
+        &OutputFeeRates::default(),
+    );
+    assert!(over_max_input.is_err(), "Swapping more than max input quantity should fail");
+
+    let over_max_output = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::OutputQuantity(max_swap_size.max_output_quantity + FPDecimal::ONE),
+        &OutputFeeRates::default(),
+    );
+    assert!(over_max_output.is_err(), "Requesting more than max output quantity should fail");
+}
+
+#[test]
+fn get_max_swap_size_is_limited_by_buffer_caps() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+    set_buffer_caps(
+        deps.as_mut_deps(),
+        admin,
+        vec![BufferCap {
+            denom: "usdt".to_string(),
+            per_swap: Some(FPDecimal::ZERO),
+            per_block: None,
+        }],
+    )
+    .unwrap();
+
+    let max_swap_size = get_max_swap_size(deps.as_ref(), &mock_env(), "eth".to_string(), "inj".to_string(), None, None, None).unwrap();
+    assert!(
+        max_swap_size.max_input_quantity < FPDecimal::must_from_str("12"),
+        "Buys needing buffer above the cap should not count as supported"
+    );
+
+    let over_max_input = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(max_swap_size.max_input_quantity + FPDecimal::ONE),
+        &OutputFeeRates::default(),
+    );
+    assert!(
+        matches!(over_max_input, Err(ContractError::BufferCapExceeded { ref denom, .. }) if denom == "usdt"),
+        "Swapping more than max input quantity should go over the cap, got {over_max_input:?}"
+    );
+}
+
+#[test]
+fn get_max_swap_size_is_limited_by_critical_buffer_balance() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    // contract holds 10^15 usdt in mocks, which is below the critical balance, where buys may not use the buffer
+    set_buffer_denoms(
+        deps.as_mut_deps(),
+        admin,
+        vec![BufferDenom {
+            denom: "usdt".to_string(),
+            min_balance: FPDecimal::ZERO,
+            target_balance: None,
+            low_balance: None,
+            critical_balance: Some(FPDecimal::from(2_000_000_000_000_000u128)),
+            critical_max_use_bps: 0,
+        }],
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let max_swap_size = get_max_swap_size(deps.as_ref(), &mock_env(), "eth".to_string(), "inj".to_string(), None, None, None).unwrap();
+    assert!(
+        max_swap_size.max_input_quantity < FPDecimal::must_from_str("12"),
+        "Buys needing buffer below its critical balance should not count as supported"
+    );
+
+    let over_max_input = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(max_swap_size.max_input_quantity + FPDecimal::ONE),
+        &OutputFeeRates::default(),
+    );
+    assert!(
+        matches!(over_max_input, Err(ContractError::BufferCritical { ref denom, .. }) if denom == "usdt"),
+        "Swapping more than max input quantity should use too much of the critical buffer, got {over_max_input:?}"
+    );
+}
+
+#[test]
+fn get_max_swap_size_returns_error_if_route_does_not_exist() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::SwapContract,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let max_swap_size = get_max_swap_size(deps.as_ref(), &mock_env(), "eth".to_string(), "inj".to_string(), None, None, None);
+    assert!(max_swap_size.is_err(), "Missing route should return an error");
+}
+
+#[test]
+fn it_quotes_explicit_route_that_is_not_stored() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let stored_route_quote = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOutputQuantity {
+            from_quantity: FPDecimal::from_str("12").unwrap(),
+            source_denom: "eth".to_string(),
+            target_denom: "inj".to_string(),
+            route: None,
+            trader: None,
+            integrator: None,
+        },
+    );
+    assert!(stored_route_quote.is_err(), "Quote without explicit route should need a stored route");
+
+    let explicit_route_quote: SwapEstimationResult = from_json(
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetOutputQuantity {
+                from_quantity: FPDecimal::from_str("12").unwrap(),
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                route: Some(vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()]),
+                trader: None,
+                integrator: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        explicit_route_quote.result_quantity,
+        FPDecimal::must_from_str("2888.221"),
+        "Wrong amount of swap execution estimate received when using explicit route"
+    );
+    assert_eq!(explicit_route_quote.expected_fees.len(), 2, "Wrong number of fee entries received");
+
+    let all_routes = get_all_swap_routes(deps.as_ref().storage, None, None).unwrap();
+    assert!(all_routes.is_empty(), "Quoting explicit route should not store it");
+}
+
+#[test]
+fn it_validates_explicit_route_like_set_route() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::SwapContract,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let duplicated_steps_quote = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetInputQuantity {
+            to_quantity: FPDecimal::from_str("100").unwrap(),
+            source_denom: "eth".to_string(),
+            target_denom: "inj".to_string(),
+            route: Some(vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()]),
+            trader: None,
+            integrator: None,
+        },
+    );
+    assert!(
+        duplicated_steps_quote
+            .unwrap_err()
+            .to_string()
+            .contains("Route cannot have duplicate steps!"),
+        "Wrong error for route with duplicate steps"
+    );
+
+    let wrong_target_quote = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOutputQuantity {
+            from_quantity: FPDecimal::from_str("1").unwrap(),
+            source_denom: "eth".to_string(),
+            target_denom: "inj".to_string(),
+            route: Some(vec![TEST_MARKET_ID_1.into()]),
+            trader: None,
+            integrator: None,
+        },
+    );
+    assert!(
+        wrong_target_quote
+            .unwrap_err()
+            .to_string()
+            .contains("Target denom not found in last market"),
+        "Wrong error for route not ending in target denom"
+    );
+}
+
+#[test]
+fn get_price_ladder_returns_same_results_as_separate_quotes() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        &Addr::unchecked(TEST_USER_ADDR),
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let sizes = vec![FPDecimal::ONE, FPDecimal::from(12u128), FPDecimal::from(100u128)];
+    let ladder = get_price_ladder(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        sizes.clone(),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(ladder.len(), sizes.len(), "Wrong number of ladder points received");
+
+    for point in ladder.iter().take(2) {
+        let separate_quote = estimate_swap_result(
+            deps.as_ref(),
+            &mock_env(),
+            "eth".to_string(),
+            "inj".to_string(),
+            SwapQuantity::InputQuantity(point.size),
+            &OutputFeeRates::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            point.result_quantity,
+            Some(separate_quote.result_quantity),
+            "Ladder point for size {} differs from separate quote",
+            point.size
+        );
+        assert_eq!(
+            point.effective_rate,
+            Some(separate_quote.result_quantity / point.size),
+            "Wrong effective rate for size {}",
+            point.size
+        );
+    }
+
+    assert_eq!(
+        ladder[1].result_quantity,
+        Some(FPDecimal::must_from_str("2888.221")),
+        "Wrong amount of swap execution estimate received for ladder point"
+    );
+    assert_eq!(ladder[2].result_quantity, None, "Size exceeding orderbook depth should have no result");
+    assert_eq!(ladder[2].effective_rate, None, "Size exceeding orderbook depth should have no rate");
+}
+
+#[test]
+fn get_price_ladder_quotes_explicit_route_without_stored_route() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let ladder_query = |route: Option<Vec<MarketId>>| {
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetPriceLadder {
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                sizes: vec![FPDecimal::from(12u128)],
+                route,
+                trader: None,
+                integrator: None,
+            },
+        )
+    };
+
+    assert!(ladder_query(None).is_err(), "Ladder without explicit route should need a stored route");
+
+    let ladder: Vec<PriceLadderPoint> = from_json(ladder_query(Some(vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()])).unwrap()).unwrap();
+    assert_eq!(
+        ladder[0].result_quantity,
+        Some(FPDecimal::must_from_str("2888.221")),
+        "Wrong amount of swap execution estimate received for ladder point of explicit route"
+    );
+
+    let invalid_route_ladder = ladder_query(Some(vec![TEST_MARKET_ID_1.into()]));
+    assert!(
+        invalid_route_ladder
+            .unwrap_err()
+            .to_string()
+            .contains("Target denom not found in last market"),
+        "Explicit route of ladder should be validated like set_route"
+    );
+
+    let all_routes = get_all_swap_routes(deps.as_ref().storage, None, None).unwrap();
+    assert!(all_routes.is_empty(), "Quoting explicit route should not store it");
+}
+
+// Same markets and orderbooks as in mock_deps_eth_inj
+fn hypothetical_eth_inj_markets() -> Vec<HypotheticalMarket> {
+    vec![
+        HypotheticalMarket {
+            market: create_mock_spot_market("eth", FPDecimal::must_from_str("0.001"), FPDecimal::must_from_str("0.001"), 0),
+            atomic_execution_fee_multiplier: FPDecimal::from_str("2.5").unwrap(),
+            buys: vec![
+                create_price_level(201000, 5),
+                create_price_level(195000, 4),
+                create_price_level(192000, 3),
+            ],
+            sells: vec![],
+        },
+        HypotheticalMarket {
+            market: create_mock_spot_market("inj", FPDecimal::must_from_str("0.001"), FPDecimal::must_from_str("0.001"), 1),
+            atomic_execution_fee_multiplier: FPDecimal::from_str("2.5").unwrap(),
+            buys: vec![],
+            sells: vec![
+                create_price_level(800, 800),
+                create_price_level(810, 800),
+                create_price_level(820, 800),
+                create_price_level(830, 800),
+            ],
+        },
+    ]
+}
+
+#[test]
+fn it_simulates_swap_on_hypothetical_markets_like_on_live_ones() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let hypothetical_result: SwapEstimationResult = from_json(
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetHypotheticalSwapResult {
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                swap_quantity: SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+                markets: hypothetical_eth_inj_markets(),
+                buffer: None,
+                trader: None,
+                integrator: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    let live_result = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOutputQuantity {
+            from_quantity: FPDecimal::from_str("12").unwrap(),
+            source_denom: "eth".to_string(),
+            target_denom: "inj".to_string(),
+            route: Some(vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()]),
+            trader: None,
+            integrator: None,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        hypothetical_result.result_quantity,
+        FPDecimal::must_from_str("2888.221"),
+        "Wrong amount of swap execution estimate received for hypothetical markets"
+    );
+    assert_eq!(
+        hypothetical_result,
+        from_json::<SwapEstimationResult>(live_result).unwrap(),
+        "Hypothetical markets identical to live ones should give the same estimate"
+    );
+}
+
+#[test]
+fn it_simulates_swap_on_thinned_hypothetical_orderbook() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let mut markets = hypothetical_eth_inj_markets();
+    markets[1].sells.truncate(1);
+
+    let result = get_hypothetical_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+        markets,
+        None,
+        None,
+        None,
+    );
+
+    assert!(result.is_err(), "Swap should fail when thinned orderbook cannot fill it");
+
+    let mut markets = hypothetical_eth_inj_markets();
+    markets.reverse();
+
+    let result = get_hypothetical_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+        markets,
+        None,
+        None,
+        None,
+    );
+
+    assert!(
+        result.unwrap_err().to_string().contains("Source denom not found in first market"),
+        "Hypothetical route should be validated like a stored one"
+    );
+}
+
+// Simulated buy of eth over two levels of the eth market, which needs buffer to place the order at the worse price
+fn simulate_eth_buy_needing_buffer(deps: Deps<InjectiveQueryWrapper>, buffer: Option<Vec<FPCoin>>) -> Result<SwapEstimationResult, StdError> {
+    let eth_market = hypothetical_eth_inj_markets().remove(0);
+    get_hypothetical_swap_result(
+        deps,
+        &mock_env(),
+        "usdt".to_string(),
+        "eth".to_string(),
+        SwapQuantity::InputQuantity(FPDecimal::from(1_500_000u128)),
+        vec![HypotheticalMarket {
+            sells: vec![create_price_level(201000, 5), create_price_level(202000, 4)],
+            ..eth_market
+        }],
+        buffer,
+        None,
+        None,
+    )
+}
+
+#[test]
+fn hypothetical_swaps_are_not_limited_by_buffer_caps() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    assert!(
+        simulate_eth_buy_needing_buffer(deps.as_ref(), Some(vec![])).is_err(),
+        "Simulated buy should need buffer"
+    );
+    let uncapped = simulate_eth_buy_needing_buffer(deps.as_ref(), None).unwrap();
+
+    set_buffer_caps(
+        deps.as_mut_deps(),
+        admin,
+        vec![BufferCap {
+            denom: "usdt".to_string(),
+            per_swap: Some(FPDecimal::ZERO),
+            per_block: Some(FPDecimal::ZERO),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        simulate_eth_buy_needing_buffer(deps.as_ref(), None).unwrap(),
+        uncapped,
+        "Buffer caps of the contract should not apply to hypothetical swaps"
+    );
+}
+
+#[test]
+fn hypothetical_swaps_are_not_limited_by_critical_buffer_balance() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    assert!(
+        simulate_eth_buy_needing_buffer(deps.as_ref(), Some(vec![])).is_err(),
+        "Simulated buy should need buffer"
+    );
+    let unlimited = simulate_eth_buy_needing_buffer(deps.as_ref(), None).unwrap();
+
+    // contract holds 10^15 usdt in mocks, which is below the critical balance, where buys may not use the buffer
+    set_buffer_denoms(
+        deps.as_mut_deps(),
+        admin,
+        vec![BufferDenom {
+            denom: "usdt".to_string(),
+            min_balance: FPDecimal::ZERO,
+            target_balance: None,
+            low_balance: None,
+            critical_balance: Some(FPDecimal::from(2_000_000_000_000_000u128)),
+            critical_max_use_bps: 0,
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        simulate_eth_buy_needing_buffer(deps.as_ref(), None).unwrap(),
+        unlimited,
+        "Critical buffer balance of the contract should not apply to hypothetical swaps"
+    );
+}
+
+#[test]
+fn it_rejects_hypothetical_markets_with_invalid_parameters() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    let simulate = |deps: Deps<InjectiveQueryWrapper>, markets: Vec<HypotheticalMarket>| {
+        get_hypothetical_swap_result(
+            deps,
+            &mock_env(),
+            "eth".to_string(),
+            "inj".to_string(),
+            SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+            markets,
+            None,
+            None,
+            None,
+        )
+    };
+    assert!(
+        simulate(deps.as_ref(), hypothetical_eth_inj_markets()).is_ok(),
+        "Valid hypothetical markets should be simulated"
+    );
+
+    type Invalidation = fn(&mut HypotheticalMarket);
+    let invalid_cases: Vec<(&str, Invalidation)> = vec![
+        ("zero price tick size", |market| market.market.min_price_tick_size = FPDecimal::ZERO),
+        ("negative price tick size", |market| {
+            market.market.min_price_tick_size = FPDecimal::must_from_str("-0.001")
+        }),
+        ("zero quantity tick size", |market| market.market.min_quantity_tick_size = FPDecimal::ZERO),
+        ("negative quantity tick size", |market| {
+            market.market.min_quantity_tick_size = FPDecimal::must_from_str("-0.001")
+        }),
+        ("negative taker fee rate", |market| {
+            market.market.taker_fee_rate = FPDecimal::must_from_str("-0.001")
+        }),
+        ("taker fee rate of 1", |market| market.market.taker_fee_rate = FPDecimal::ONE),
+        ("negative relayer fee share", |market| {
+            market.market.relayer_fee_share_rate = FPDecimal::must_from_str("-0.4")
+        }),
+        ("relayer fee share of 1", |market| market.market.relayer_fee_share_rate = FPDecimal::ONE),
+        ("zero atomic execution fee multiplier", |market| {
+            market.atomic_execution_fee_multiplier = FPDecimal::ZERO
+        }),
+        ("multiplier taking taker fee to 1", |market| {
+            market.atomic_execution_fee_multiplier = FPDecimal::from(1000u128)
+        }),
+        ("zero price level", |market| market.sells.push(create_price_level(0, 800))),
+        ("price level whose value rounds to zero", |market| {
+            market.sells.push(PriceLevel {
+                p: FPDecimal::must_from_str("0.0000000001"),
+                q: FPDecimal::must_from_str("0.0000000001"),
+            })
+        }),
+    ];
+
+    for (case, invalidate) in invalid_cases {
+        let mut markets = hypothetical_eth_inj_markets();
+        invalidate(&mut markets[1]);
+
+        let result = simulate(deps.as_ref(), markets);
+        assert!(
+            result.as_ref().is_err_and(|err| err.to_string().contains("of market")),
+            "Hypothetical market with {case} should be rejected, got {result:?}"
+        );
+    }
+}
+
+#[test]
+fn it_includes_protocol_fee_in_quotes() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.protocol_fee_bps = 30;
+    config.treasury = Some(Addr::unchecked("treasury"));
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+    let route = read_swap_route(deps.as_ref().storage, "eth", "inj").unwrap();
+    let fee_rates = get_output_fee_rates(deps.as_ref(), &mock_env(), &route, None, None).unwrap();
+
+    let input_quote = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+        &fee_rates,
+    )
+    .unwrap();
+
+    // 0.3% of 2888.221 rounded down
+    assert_eq!(
+        input_quote.result_quantity,
+        FPDecimal::must_from_str("2880.221"),
+        "Protocol fee should be deducted from quoted output"
+    );
+    assert_eq!(input_quote.expected_fees.len(), 3, "Protocol fee should be listed in expected fees");
+    assert_eq!(
+        input_quote.expected_fees[2],
+        FPCoin {
+            amount: FPDecimal::must_from_str("8"),
+            denom: "inj".to_string(),
+        },
+        "Wrong protocol fee in expected fees"
+    );
+
+    let output_quote = estimate_swap_result(
+        deps.as_ref(),
+        &mock_env(),
+        "eth".to_string(),
+        "inj".to_string(),
+        SwapQuantity::OutputQuantity(FPDecimal::from_str("2000").unwrap()),
+        &fee_rates,
+    )
+    .unwrap();
+
+    assert_eq!(output_quote.expected_fees.len(), 3, "Protocol fee should be listed in expected fees");
+    assert_eq!(
+        output_quote.expected_fees[2].denom, "inj",
+        "Protocol fee should be charged in target denom"
+    );
+    assert!(
+        output_quote.expected_fees[2].amount > FPDecimal::ZERO,
+        "Exact output quote should account for protocol fee"
+    );
+}
+
+#[test]
+fn it_includes_integrator_fee_in_quotes() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.max_integrator_fee_bps = 50;
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+
+    let integrator = Integrator {
+        address: Addr::unchecked("wallet"),
+        fee_bps: 50,
+    };
+
+    let quote: SwapEstimationResult = from_json(
+        query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetOutputQuantity {
+                from_quantity: FPDecimal::from_str("12").unwrap(),
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                route: None,
+                trader: None,
+                integrator: Some(integrator.to_owned()),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    // 0.5% of 2888.221 rounded down
+    assert_eq!(
+        quote.result_quantity,
+        FPDecimal::must_from_str("2874.221"),
+        "Integrator fee should be deducted from quoted output"
+    );
+    assert_eq!(
+        quote.expected_fees[2],
+        FPCoin {
+            amount: FPDecimal::must_from_str("14"),
+            denom: "inj".to_string(),
+        },
+        "Wrong integrator fee in expected fees"
+    );
+
+    let too_high_fee_quote = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOutputQuantity {
+            from_quantity: FPDecimal::from_str("12").unwrap(),
+            source_denom: "eth".to_string(),
+            target_denom: "inj".to_string(),
+            route: None,
+            trader: None,
+            integrator: Some(Integrator { fee_bps: 51, ..integrator }),
+        },
+    );
+
+    assert!(too_high_fee_quote.is_err(), "Quote with integrator fee above maximum should fail");
+}
+
+#[test]
+fn it_applies_fee_tier_discount_of_trader_in_quotes() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+    let trader = Addr::unchecked("trader");
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.protocol_fee_bps = 30;
+    config.treasury = Some(Addr::unchecked("treasury"));
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+
+    set_fee_tiers(
+        deps.as_mut_deps(),
+        admin,
+        "usdt".to_string(),
+        vec![FeeTier {
+            min_volume: FPDecimal::from(10_000u128),
+            discount_bps: 5_000,
+        }],
+    )
+    .unwrap();
+    record_trader_volume(deps.as_mut_deps().storage, &trader, mock_env().block.time, FPDecimal::from(10_000u128)).unwrap();
+
+    let quote_for = |trader: Option<Addr>| -> SwapEstimationResult {
+        from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::GetOutputQuantity {
+                    from_quantity: FPDecimal::from_str("12").unwrap(),
+                    source_denom: "eth".to_string(),
+                    target_denom: "inj".to_string(),
+                    route: None,
+                    trader,
+                    integrator: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    };
+
+    // 0.3% of 2888.221 rounded down
+    assert_eq!(
+        quote_for(None).result_quantity,
+        FPDecimal::must_from_str("2880.221"),
+        "Quote without trader should charge full protocol fee"
+    );
+    // 0.15% of 2888.221 rounded down
+    assert_eq!(
+        quote_for(Some(trader)).result_quantity,
+        FPDecimal::must_from_str("2884.221"),
+        "Quote for trader in discounted tier should charge reduced protocol fee"
+    );
+}
+
+#[test]
+fn it_skips_fees_and_limits_buffer_for_fee_exempt_trader() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+    let rebalancer = Addr::unchecked("rebalancer");
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.protocol_fee_bps = 30;
+    config.treasury = Some(Addr::unchecked("treasury"));
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+
+    update_fee_exempt_addresses(deps.as_mut_deps(), admin, vec![rebalancer.to_owned()], vec![]).unwrap();
+
+    let quote_for = |deps: Deps<InjectiveQueryWrapper>, trader: &Addr| {
+        query(
+            deps,
+            mock_env(),
+            QueryMsg::GetOutputQuantity {
+                from_quantity: FPDecimal::from_str("12").unwrap(),
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                route: None,
+                trader: Some(trader.to_owned()),
+                integrator: None,
+            },
+        )
+        .and_then(from_json::<SwapEstimationResult>)
+    };
+
+    let no_budget_quote = quote_for(deps.as_ref(), &rebalancer);
+    assert!(
+        no_budget_quote.unwrap_err().to_string().contains("buffer budget"),
+        "Fee-exempt trader should not draw on the buffer without a budget"
+    );
+
+    set_exempt_buffer_budget(
+        deps.as_mut_deps(),
+        admin,
+        vec![FPCoin {
+            amount: FPDecimal::from(1_000_000u128),
+            denom: "usdt".to_string(),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        quote_for(deps.as_ref(), &rebalancer).unwrap().result_quantity,
+        FPDecimal::must_from_str("2888.221"),
+        "Fee-exempt trader should not pay protocol fee"
+    );
+    // 0.3% of 2888.221 rounded down
+    assert_eq!(
+        quote_for(deps.as_ref(), admin).unwrap().result_quantity,
+        FPDecimal::must_from_str("2880.221"),
+        "Other traders should pay protocol fee"
+    );
+}
+
+#[test]
+fn ladder_max_size_and_hypothetical_quotes_apply_fee_exemption_like_output_quote() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let env = mock_env();
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+    let rebalancer = Addr::unchecked("rebalancer");
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.protocol_fee_bps = 30;
+    config.treasury = Some(Addr::unchecked("treasury"));
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+
+    update_fee_exempt_addresses(deps.as_mut_deps(), admin, vec![rebalancer.to_owned()], vec![]).unwrap();
+
+    let size = FPDecimal::from_str("12").unwrap();
+    let output_quote = |deps: Deps<InjectiveQueryWrapper>, quantity: FPDecimal| {
+        query(
+            deps,
+            mock_env(),
+            QueryMsg::GetOutputQuantity {
+                from_quantity: quantity,
+                source_denom: "eth".to_string(),
+                target_denom: "inj".to_string(),
+                route: None,
+                trader: Some(rebalancer.to_owned()),
+                integrator: None,
+            },
+        )
+        .and_then(from_json::<SwapEstimationResult>)
+    };
+    let ladder_quote = |deps: Deps<InjectiveQueryWrapper>| {
+        get_price_ladder(
+            deps,
+            &env,
+            "eth".to_string(),
+            "inj".to_string(),
+            vec![size],
+            None,
+            Some(&rebalancer),
+            None,
+        )
+        .unwrap()[0]
+            .result_quantity
+    };
+    let hypothetical_quote = |deps: Deps<InjectiveQueryWrapper>| {
+        get_hypothetical_swap_result(
+            deps,
+            &env,
+            "eth".to_string(),
+            "inj".to_string(),
+            SwapQuantity::InputQuantity(size),
+            hypothetical_eth_inj_markets(),
+            None,
+            Some(&rebalancer),
+            None,
+        )
+    };
+    let max_input = |deps: Deps<InjectiveQueryWrapper>, trader: &Addr| {
+        get_max_swap_size(deps, &env, "eth".to_string(), "inj".to_string(), None, Some(trader), None)
+            .unwrap()
+            .max_input_quantity
+    };
+
+    // without a budget the exempt trader cannot draw on the buffer in any quote
+    assert!(output_quote(deps.as_ref(), size).is_err(), "Output quote should need a buffer budget");
+    assert_eq!(
+        ladder_quote(deps.as_ref()),
+        None,
+        "Ladder should need a buffer budget like the output quote"
+    );
+    assert!(
+        hypothetical_quote(deps.as_ref()).unwrap_err().to_string().contains("buffer budget"),
+        "Hypothetical quote should need a buffer budget like the output quote"
+    );
+    let max_input_without_budget = max_input(deps.as_ref(), &rebalancer);
+    assert!(
+        max_input_without_budget < max_input(deps.as_ref(), admin),
+        "Max swap size should be limited by the missing buffer budget"
+    );
+    assert!(
+        output_quote(deps.as_ref(), max_input_without_budget).is_ok(),
+        "Output quote should support the max swap size of the exempt trader"
+    );
+
+    set_exempt_buffer_budget(
+        deps.as_mut_deps(),
+        admin,
+        vec![FPCoin {
+            amount: FPDecimal::from(1_000_000u128),
+            denom: "usdt".to_string(),
+        }],
+    )
+    .unwrap();
+
+    let expected_result = output_quote(deps.as_ref(), size).unwrap();
+    assert_eq!(
+        expected_result.result_quantity,
+        FPDecimal::must_from_str("2888.221"),
+        "Fee-exempt trader should not pay protocol fee"
+    );
+    assert_eq!(
+        ladder_quote(deps.as_ref()),
+        Some(expected_result.result_quantity),
+        "Ladder should quote the exempt trader like the output quote"
+    );
+    assert_eq!(
+        hypothetical_quote(deps.as_ref()).unwrap(),
+        expected_result,
+        "Hypothetical quote on the live markets should quote the exempt trader like the output quote"
+    );
+    let max_input_with_budget = max_input(deps.as_ref(), &rebalancer);
+    assert!(
+        max_input_with_budget > max_input_without_budget,
+        "Max swap size should grow with the buffer budget"
+    );
+    assert!(
+        output_quote(deps.as_ref(), max_input_with_budget).is_ok(),
+        "Output quote should support the max swap size of the exempt trader"
+    );
+}
+
+#[test]
+fn it_quotes_fees_net_of_rebate_in_sender_fee_recipient_mode() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let quote = |deps: Deps<InjectiveQueryWrapper>| {
+        estimate_swap_result(
+            deps,
+            &mock_env(),
+            "eth".to_string(),
+            "inj".to_string(),
+            SwapQuantity::InputQuantity(FPDecimal::from_str("12").unwrap()),
+            &OutputFeeRates::default(),
+        )
+        .unwrap()
+    };
+    let address_mode_quote = quote(deps.as_ref());
+
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.fee_recipient = FeeRecipient::Sender;
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+    let sender_mode_quote = quote(deps.as_ref());
+
+    assert_eq!(
+        sender_mode_quote.result_quantity, address_mode_quote.result_quantity,
+        "Swap output should not change, rebate is paid to the swapper by the exchange"
+    );
+    for (sender_mode_fee, address_mode_fee) in sender_mode_quote.expected_fees.iter().zip(address_mode_quote.expected_fees.iter()) {
+        // mock markets share 40% of fees with relayers
+        assert_eq!(
+            sender_mode_fee.amount,
+            address_mode_fee.amount * FPDecimal::must_from_str("0.6"),
+            "Fees should be quoted net of the rebate received by the swapper"
+        );
+    }
+}
+
+#[test]
+fn get_max_swap_size_returns_estimation_errors_other_than_lack_of_liquidity() {
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Fail);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        mock_env(),
+        message_info(&Addr::unchecked(admin), &[coin(1_000u128, "usdt")]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &Addr::unchecked(TEST_USER_ADDR),
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let max_swap_size = get_max_swap_size(deps.as_ref(), &mock_env(), "eth".to_string(), "inj".to_string(), None, None, None);
+    assert!(
+        max_swap_size.is_err(),
+        "failing fee multiplier query should be returned, not reported as no liquidity"
+    );
+}
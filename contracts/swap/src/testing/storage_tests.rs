@@ -1,8 +1,8 @@
 use crate::{
     admin::{delete_route, set_route},
     state::{read_swap_route, store_swap_route, CONFIG},
-    testing::test_utils::{mock_deps_eth_inj, MultiplierQueryBehavior, TEST_CONTRACT_ADDR, TEST_USER_ADDR},
-    types::{Config, SwapRoute},
+    testing::test_utils::{mock_deps_eth_inj, test_config, MultiplierQueryBehavior, TEST_CONTRACT_ADDR, TEST_USER_ADDR},
+    types::SwapRoute,
 };
 use cosmwasm_std::Addr;
 use injective_cosmwasm::{inj_mock_deps, MarketId, OwnedDepsExt, TEST_MARKET_ID_1, TEST_MARKET_ID_2, TEST_MARKET_ID_3};
@@ -17,6 +17,8 @@ fn it_can_store_and_read_swap_route() {
         steps: vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)],
         source_denom: source_denom.to_string(),
         target_denom: target_denom.to_string(),
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     store_swap_route(deps.as_mut().storage, &route).unwrap();
@@ -42,6 +44,8 @@ fn it_can_update_and_read_swap_route() {
         steps: vec![MarketId::unchecked(TEST_MARKET_ID_1)],
         source_denom: source_denom.to_string(),
         target_denom: target_denom.to_string(),
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     store_swap_route(deps.as_mut().storage, &route).unwrap();
@@ -55,6 +59,8 @@ fn it_can_update_and_read_swap_route() {
         steps: vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)],
         source_denom: source_denom.to_string(),
         target_denom: new_target_denom.to_string(),
+        fee_recipient: None,
+        protocol_fee_bps: None,
     };
 
     store_swap_route(deps.as_mut().storage, &updated_route).unwrap();
@@ -70,10 +76,7 @@ fn owner_can_set_valid_route() {
     let target_denom = "inj".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -103,10 +106,7 @@ fn owner_cannot_set_route_for_markets_using_target_denom_not_found_on_target_mar
     let target_denom = "atom".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -134,10 +134,7 @@ fn owner_cannot_set_route_for_markets_using_source_denom_not_present_on_source_m
     let target_denom = "eth".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -165,10 +162,7 @@ fn owner_can_set_route_single_step_route() {
     let target_denom = "usdt".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -198,10 +192,7 @@ fn owner_can_set_route_single_step_route_with_reverted_denoms() {
     let target_denom = "eth".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -231,10 +222,7 @@ fn it_returns_error_when_setting_route_for_the_same_denom_as_target_and_source()
     let target_denom = "eth".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
 
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -266,10 +254,7 @@ fn it_returns_error_when_setting_route_with_nonexistent_market_id() {
     let target_denom = "usdt".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_3)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
 
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -300,10 +285,7 @@ fn it_returns_error_when_setting_route_with_no_market_ids() {
     let target_denom = "usdt".to_string();
     let route = vec![];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
 
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -332,10 +314,7 @@ fn it_returns_error_when_setting_route_with_duplicated_market_ids() {
     let target_denom = "usdt".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_1)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
 
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
@@ -364,10 +343,7 @@ fn it_returns_error_if_non_admin_tries_to_set_route() {
     let target_denom = "inj".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let result = set_route(
@@ -395,10 +371,7 @@ fn it_allows_admint_to_delete_existing_route() {
     let target_denom = "inj".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let set_result = set_route(
@@ -434,10 +407,7 @@ fn it_doesnt_fail_if_admin_deletes_non_existent_route() {
     let target_denom = "inj".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let set_result = set_route(
@@ -470,10 +440,7 @@ fn it_returns_error_if_non_admin_tries_to_delete_route() {
     let target_denom = "inj".to_string();
     let route = vec![MarketId::unchecked(TEST_MARKET_ID_1), MarketId::unchecked(TEST_MARKET_ID_2)];
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     let set_result = set_route(
@@ -1,13 +1,124 @@
 use crate::{
-    admin::set_route,
-    queries::estimate_single_swap_execution,
-    state::CONFIG,
-    testing::test_utils::{mock_deps_eth_inj, str_coin, Decimals, MultiplierQueryBehavior, TEST_USER_ADDR},
-    types::{Config, FPCoin, SwapEstimationAmount},
+    admin::{set_buffer_caps, set_exempt_buffer_budget, set_route, update_fee_exempt_addresses},
+    contract::{execute, instantiate, query, reply, ATOMIC_ORDER_REPLY_ID, FEE_CONVERSION_REPLY_ID},
+    error::ContractError,
+    fees::BURN_AUCTION_SUBACCOUNT_ID,
+    helpers::Scaled,
+    market_data::LiveMarketData,
+    msg::{ExecuteMsg, FeeRecipient, InstantiateMsg, QueryMsg},
+    queries::{estimate_single_swap_execution, estimate_swap_result, SwapQuantity},
+    state::{
+        get_buffer_cap_left, get_exempt_buffer_budget, record_accrued_protocol_fee, record_buffer_deposit, record_swap_dust, record_trader_volume,
+        BUFFER_DENOMS, CONFIG, FEE_CONVERSION, STEP_STATE, SWAP_OPERATION_STATE, SWAP_RESULTS, WITHDRAWAL_RESERVES,
+    },
+    testing::test_utils::{
+        human_to_dec, mock_deps_eth_inj, mock_realistic_deps_eth_atom, str_coin, test_config, Decimals, MultiplierQueryBehavior, TEST_CONTRACT_ADDR,
+        TEST_USER_ADDR,
+    },
+    types::{
+        BufferCap, BufferDenom, BufferLedger, BufferLedgerEntry, BufferPnlResponse, BufferPriceSource, BufferPricing, Config, CurrentSwapOperation,
+        CurrentSwapStep, FPCoin, FeeTier, Integrator, OutputFeeRates, SwapEstimationAmount, SwapQuantityMode, SwapResponse, SwapResults,
+        TraderVolumeResponse,
+    },
 };
 
-use cosmwasm_std::{testing::mock_env, Addr};
-use injective_cosmwasm::{MarketId, OwnedDepsExt, TEST_MARKET_ID_1, TEST_MARKET_ID_2};
+use cosmwasm_std::{
+    coin, from_json,
+    testing::{message_info, mock_env},
+    Addr, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MsgResponse, Reply, ReplyOn, Response, StdResult, Storage, SubMsg, SubMsgResponse,
+    SubMsgResult,
+};
+use injective_cosmwasm::{
+    create_oracle_query_handler, InjectiveMsg, InjectiveMsgWrapper, InjectiveQueryWrapper, MarketId, OracleType, OwnedDepsExt, TEST_MARKET_ID_1,
+    TEST_MARKET_ID_2,
+};
+use injective_math::FPDecimal;
+use injective_std::types::injective::exchange::v1beta1::{MsgCreateSpotMarketOrderResponse, SpotMarketOrderResults};
+use prost::Message;
+
+const SWAPPER: &str = "swapper";
+const TREASURY: &str = "treasury";
+
+// Reply of an atomic order filled at given values, which are scaled by protobuf Dec factor like in a real response
+fn mock_atomic_order_reply(quantity: FPDecimal, price: FPDecimal, fee: FPDecimal) -> Reply {
+    let order_response = MsgCreateSpotMarketOrderResponse {
+        order_hash: "0x01".to_string(),
+        results: Some(SpotMarketOrderResults {
+            quantity: quantity.scaled(18).int().to_string(),
+            price: price.scaled(18).int().to_string(),
+            fee: fee.scaled(18).int().to_string(),
+        }),
+        cid: "".to_string(),
+    };
+
+    #[allow(deprecated)]
+    Reply {
+        id: ATOMIC_ORDER_REPLY_ID,
+        payload: Binary::default(),
+        gas_used: 0,
+        result: SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: None,
+            msg_responses: vec![MsgResponse {
+                type_url: "/injective.exchange.v1beta1.MsgCreateSpotMarketOrderResponse".to_string(),
+                value: order_response.encode_to_vec().into(),
+            }],
+        }),
+    }
+}
+
+// Stores state of a swap that sells 12 eth for usdt on a single market and waits for the order reply. Fees are
+// charged at the configured protocol fee rate, without volume discount.
+fn store_single_step_eth_usdt_swap(storage: &mut dyn Storage, swap_quantity_mode: SwapQuantityMode, integrator: Option<Integrator>) {
+    let config = CONFIG.load(storage).unwrap();
+    let output_fee_rates = OutputFeeRates {
+        protocol_fee_bps: config.protocol_fee_bps,
+        integrator_fee_bps: integrator.as_ref().map_or(0, |integrator| integrator.fee_bps),
+    };
+    SWAP_OPERATION_STATE
+        .save(
+            storage,
+            &CurrentSwapOperation {
+                sender_address: Addr::unchecked(SWAPPER),
+                swap_steps: vec![MarketId::unchecked(TEST_MARKET_ID_1)],
+                swap_quantity_mode,
+                input_funds: coin(12u128, "eth"),
+                refund: coin(0u128, "eth"),
+                integrator,
+                output_fee_rates,
+                fee_recipient: config.fee_recipient.resolve(&mock_env().contract.address, &Addr::unchecked(SWAPPER)),
+                tip_dust: false,
+                internal: false,
+                buffer_balances_before: vec![],
+            },
+        )
+        .unwrap();
+    STEP_STATE
+        .save(
+            storage,
+            &CurrentSwapStep {
+                step_idx: 0,
+                current_balance: FPCoin {
+                    amount: FPDecimal::from(12u128),
+                    denom: "eth".to_string(),
+                },
+                step_target_denom: "usdt".to_string(),
+                is_buy: false,
+            },
+        )
+        .unwrap();
+    SWAP_RESULTS.save(storage, &vec![]).unwrap();
+}
+
+fn get_bank_sends(messages: &[SubMsg<InjectiveMsgWrapper>]) -> Vec<(String, Vec<Coin>)> {
+    messages
+        .iter()
+        .filter_map(|message| match &message.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.to_owned(), amount.to_owned())),
+            _ => None,
+        })
+        .collect()
+}
 
 #[test]
 fn it_reverts_if_atomic_fee_multiplier_query_fails() {
@@ -15,10 +126,7 @@ fn it_reverts_if_atomic_fee_multiplier_query_fails() {
     let deps_binding = mock_deps_eth_inj(MultiplierQueryBehavior::Fail);
     let mut deps = deps_binding;
 
-    let config = Config {
-        fee_recipient: Addr::unchecked(TEST_USER_ADDR),
-        admin: Addr::unchecked(TEST_USER_ADDR),
-    };
+    let config = test_config();
     CONFIG.save(deps.as_mut_deps().storage, &config).expect("could not save config");
 
     set_route(
@@ -31,9 +139,11 @@ fn it_reverts_if_atomic_fee_multiplier_query_fails() {
     .unwrap();
 
     let response_1 = estimate_single_swap_execution(
-        &deps.as_mut_deps().as_ref(),
+        &deps.as_ref(),
         &env,
+        &LiveMarketData::new(deps.as_ref(), &env),
         &MarketId::unchecked(TEST_MARKET_ID_1.to_string()),
+        &config.fee_recipient,
         SwapEstimationAmount::InputQuantity(FPCoin::from(str_coin("1", "eth", Decimals::Eighteen))),
         true, // is_simulation
     );
@@ -44,3 +154,2052 @@ fn it_reverts_if_atomic_fee_multiplier_query_fails() {
         "wrong error message"
     );
 }
+
+#[test]
+fn it_accepts_exactly_the_quoted_input_for_exact_output_swap() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_realistic_deps_eth_atom(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::SwapContract,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+
+    let target_output_quantity = human_to_dec("3.994", Decimals::Eighteen);
+
+    let quote = estimate_swap_result(
+        deps.as_ref(),
+        &env.to_owned(),
+        "usdt".to_string(),
+        "eth".to_string(),
+        SwapQuantity::OutputQuantity(target_output_quantity),
+        &OutputFeeRates::default(),
+    )
+    .unwrap();
+
+    let input_padding = quote.input_padding.expect("exact output quote should include padding");
+    assert!(input_padding > FPDecimal::ZERO, "quote input should be padded");
+    assert_eq!(quote.result_quantity, quote.result_quantity.int(), "quote input should be a whole amount");
+
+    let quoted_input: u128 = quote.result_quantity.into();
+
+    let too_little_funds = execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&Addr::unchecked("swapper"), &[coin(quoted_input - 1, "usdt")]),
+        ExecuteMsg::SwapExactOutput {
+            target_denom: "eth".to_string(),
+            target_output_quantity,
+            integrator: None,
+            tip_dust: false,
+        },
+    );
+
+    assert!(
+        matches!(too_little_funds, Err(ContractError::InsufficientFundsProvided(..))),
+        "swap with less than quoted input should fail"
+    );
+
+    let response = execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&Addr::unchecked("swapper"), &[coin(quoted_input, "usdt")]),
+        ExecuteMsg::SwapExactOutput {
+            target_denom: "eth".to_string(),
+            target_output_quantity,
+            integrator: None,
+            tip_dust: false,
+        },
+    );
+
+    assert!(
+        response.is_ok(),
+        "swap with exactly quoted input should be accepted: {:?}",
+        response.err()
+    );
+}
+
+#[test]
+fn it_skims_protocol_fee_from_swap_output_and_sends_it_to_treasury() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    // 12 * 200000 - 100 = 2399900 usdt, protocol fee is 0.3% of it rounded down
+    let expected_protocol_fee = 7199u128;
+    let expected_output = 2399900u128 - expected_protocol_fee;
+
+    store_single_step_eth_usdt_swap(
+        deps.as_mut_deps().storage,
+        SwapQuantityMode::MinOutputQuantity(FPDecimal::from(expected_output + 1)),
+        None,
+    );
+    let result = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    );
+    assert!(
+        matches!(result, Err(ContractError::MinOutputAmountNotReached(..))),
+        "min output should be checked after protocol fee is skimmed"
+    );
+
+    store_single_step_eth_usdt_swap(
+        deps.as_mut_deps().storage,
+        SwapQuantityMode::MinOutputQuantity(FPDecimal::from(expected_output)),
+        None,
+    );
+    let response = reply(
+        deps.as_mut_deps(),
+        env,
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![
+            (SWAPPER.to_string(), vec![coin(expected_output, "usdt")]),
+            (TREASURY.to_string(), vec![coin(expected_protocol_fee, "usdt")]),
+        ],
+        "wrong funds sent after swap"
+    );
+
+    let swap_event = response.events.iter().find(|e| e.ty == "atomic_swap_execution").unwrap();
+    assert!(
+        swap_event
+            .attributes
+            .iter()
+            .any(|a| a.key == "protocol_fee_amount" && a.value == expected_protocol_fee.to_string()),
+        "protocol fee should be recorded in swap event"
+    );
+}
+
+#[test]
+fn it_pays_integrator_fee_from_swap_output() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                max_integrator_fee_bps: 50,
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    let integrator = Integrator {
+        address: Addr::unchecked("wallet"),
+        fee_bps: 20,
+    };
+
+    // 12 * 200000 - 100 = 2399900 usdt, protocol fee is 0.3% and integrator fee 0.2% of it, both rounded down
+    let expected_protocol_fee = 7199u128;
+    let expected_integrator_fee = 4799u128;
+    let expected_output = 2399900u128 - expected_protocol_fee - expected_integrator_fee;
+
+    store_single_step_eth_usdt_swap(
+        deps.as_mut_deps().storage,
+        SwapQuantityMode::MinOutputQuantity(FPDecimal::from(expected_output)),
+        Some(integrator),
+    );
+    let response = reply(
+        deps.as_mut_deps(),
+        env,
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![
+            (SWAPPER.to_string(), vec![coin(expected_output, "usdt")]),
+            (TREASURY.to_string(), vec![coin(expected_protocol_fee, "usdt")]),
+            ("wallet".to_string(), vec![coin(expected_integrator_fee, "usdt")]),
+        ],
+        "wrong funds sent after swap"
+    );
+}
+
+#[test]
+fn it_rejects_integrator_fee_above_configured_maximum() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::SwapContract,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let wallet = deps.api.addr_make("wallet");
+    let response = execute(
+        deps.as_mut_deps(),
+        env,
+        message_info(&Addr::unchecked(SWAPPER), &[coin(12u128, "eth")]),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "inj".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: Some(Integrator { address: wallet, fee_bps: 1 }),
+            tip_dust: false,
+        },
+    );
+
+    assert!(
+        response.unwrap_err().to_string().contains("Integrator fee cannot be higher than 0 bps"),
+        "integrator fee above maximum should be rejected"
+    );
+}
+
+#[test]
+fn it_rejects_invalid_integrator_address() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = &Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::SwapContract,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let response = execute(
+        deps.as_mut_deps(),
+        env,
+        message_info(&Addr::unchecked(SWAPPER), &[coin(12u128, "eth")]),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "inj".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: Some(Integrator {
+                address: Addr::unchecked("not an address"),
+                fee_bps: 0,
+            }),
+            tip_dust: false,
+        },
+    );
+
+    assert!(
+        response.is_err(),
+        "swap with invalid integrator address should be rejected before any order"
+    );
+}
+
+#[test]
+fn it_passes_relayer_fee_rebate_to_swapper_when_contract_is_fee_recipient() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    // 12 * 200000 - 100 = 2399900 usdt, plus 40% of the fee paid back to the contract as relayer
+    let expected_rebate = 40u128;
+    let expected_output = 2399900u128 + expected_rebate;
+
+    for _ in 0..2 {
+        store_single_step_eth_usdt_swap(
+            deps.as_mut_deps().storage,
+            SwapQuantityMode::MinOutputQuantity(FPDecimal::from(expected_output)),
+            None,
+        );
+        let response = reply(
+            deps.as_mut_deps(),
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_bank_sends(&response.messages),
+            vec![(SWAPPER.to_string(), vec![coin(expected_output, "usdt")])],
+            "rebate should be included in swap output"
+        );
+    }
+
+    let accrued_rebates: Vec<FPCoin> = from_json(query(deps.as_ref(), env.to_owned(), QueryMsg::GetAccruedRebates {}).unwrap()).unwrap();
+    assert_eq!(
+        accrued_rebates,
+        vec![FPCoin {
+            amount: FPDecimal::from(2 * expected_rebate),
+            denom: "usdt".to_string(),
+        }],
+        "rebates of both swaps should be accrued"
+    );
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    let usdt_ledger = &ledger.iter().find(|entry| entry.denom == "usdt").expect("usdt ledger expected").ledger;
+    assert_eq!(
+        usdt_ledger.rebates_passed_on,
+        FPDecimal::from(2 * expected_rebate),
+        "rebates of both swaps should be counted in the ledger"
+    );
+    assert_eq!(
+        usdt_ledger.balance(),
+        usdt_ledger.rounding_gains - usdt_ledger.rounding_losses,
+        "rebates passed on should not change the buffer balance"
+    );
+}
+
+#[test]
+fn it_keeps_depositor_share_of_relayer_fee_rebate_in_buffer() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    let config = Config {
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_CONTRACT_ADDR)),
+        ..test_config()
+    };
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &Addr::unchecked(TEST_USER_ADDR),
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+    let quote = |deps: Deps<InjectiveQueryWrapper>| {
+        estimate_swap_result(
+            deps,
+            &env,
+            "eth".to_string(),
+            "usdt".to_string(),
+            SwapQuantity::InputQuantity(FPDecimal::from(12u128)),
+            &OutputFeeRates::default(),
+        )
+        .unwrap()
+        .result_quantity
+    };
+    let quote_passing_on_whole_rebate = quote(deps.as_ref());
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                depositor_rebate_share_bps: 2500,
+                ..config
+            },
+        )
+        .unwrap();
+    assert!(
+        quote(deps.as_ref()) < quote_passing_on_whole_rebate,
+        "quote should only include the rebate passed on to the swapper"
+    );
+
+    // 40% of the fee is paid back to the contract as relayer, a quarter of it stays in the buffer
+    let depositor_rebate = 10u128;
+    let expected_output = 2399900u128 + 30;
+
+    store_single_step_eth_usdt_swap(
+        deps.as_mut_deps().storage,
+        SwapQuantityMode::MinOutputQuantity(FPDecimal::from(expected_output)),
+        None,
+    );
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(SWAPPER.to_string(), vec![coin(expected_output, "usdt")])],
+        "only the rebate less the depositor share should be included in swap output"
+    );
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    let usdt_ledger = &ledger.iter().find(|entry| entry.denom == "usdt").expect("usdt ledger expected").ledger;
+    assert_eq!(
+        usdt_ledger.fees,
+        FPDecimal::from(depositor_rebate),
+        "depositor share of the rebate should be kept in the buffer"
+    );
+    assert_eq!(
+        usdt_ledger.rebates_passed_on,
+        FPDecimal::from(30u128),
+        "only the rebate passed on should be counted next to the buffer"
+    );
+}
+
+#[test]
+fn it_records_swap_volume_of_trader_for_fee_tiers() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG.save(deps.as_mut_deps().storage, &test_config()).unwrap();
+
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(TEST_USER_ADDR), &[]),
+        ExecuteMsg::SetFeeTiers {
+            reference_denom: "usdt".to_string(),
+            tiers: vec![
+                FeeTier {
+                    min_volume: FPDecimal::from(1_000_000u128),
+                    discount_bps: 1_000,
+                },
+                FeeTier {
+                    min_volume: FPDecimal::from(5_000_000u128),
+                    discount_bps: 2_500,
+                },
+            ],
+        },
+    )
+    .unwrap();
+
+    // volume from before the 30-day window should not count towards the tier
+    record_trader_volume(
+        deps.as_mut_deps().storage,
+        &Addr::unchecked(SWAPPER),
+        env.block.time.minus_days(30),
+        FPDecimal::from(1_000_000u128),
+    )
+    .unwrap();
+
+    for _ in 0..2 {
+        store_single_step_eth_usdt_swap(deps.as_mut_deps().storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+        reply(
+            deps.as_mut_deps(),
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap();
+    }
+
+    let trader_volume: TraderVolumeResponse = from_json(
+        query(
+            deps.as_ref(),
+            env,
+            QueryMsg::GetTraderVolume {
+                trader: Addr::unchecked(SWAPPER),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    // each swap sold 12 eth at 200000 usdt
+    assert_eq!(
+        trader_volume,
+        TraderVolumeResponse {
+            volume: FPDecimal::from(4_800_000u128),
+            discount_bps: 1_000,
+        },
+        "volume of both swaps should be recorded in reference denom"
+    );
+}
+
+#[test]
+fn it_converts_fees_into_treasury_denom_one_coin_after_another() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler =
+        create_oracle_query_handler(FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ZERO, FPDecimal::ZERO, 0, 0);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                admin: admin.to_owned(),
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+    set_conversion_price_sources(deps.as_mut_deps(), &env);
+
+    let keeper = Addr::unchecked("keeper");
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::UpdateBufferKeepers {
+            add: vec![keeper.to_owned()],
+            remove: vec![],
+        },
+    )
+    .unwrap();
+    let convert = ExecuteMsg::ConvertFees {
+        coins: vec![coin(12u128, "eth"), coin(100u128, "inj")],
+        target_denom: "usdt".to_string(),
+        max_slippage_bps: 100,
+    };
+
+    let result = execute(deps.as_mut_deps(), env.to_owned(), message_info(&keeper, &[]), convert.to_owned());
+    assert!(
+        result.as_ref().is_err_and(|err| err.to_string().contains("accrue_protocol_fees")),
+        "conversion without accrued fees should point to accrue_protocol_fees, got {result:?}"
+    );
+
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "eth", FPDecimal::from(12u128)).unwrap();
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "inj", FPDecimal::from(100u128)).unwrap();
+
+    let response = execute(deps.as_mut_deps(), env.to_owned(), message_info(&keeper, &[]), convert).unwrap();
+    assert_eq!(response.messages.len(), 1, "only the first fee coin should be swapped at first");
+
+    // output goes to the treasury without protocol fee, and the next coin starts swapping once it is sent
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(TREASURY.to_string(), vec![coin(2399900u128, "usdt")])],
+        "converted eth fees should be sent to treasury"
+    );
+    let event = response.events.iter().find(|e| e.ty == "withdrawal").expect("withdrawal event expected");
+    let attribute = |key: &str| event.attributes.iter().find(|a| a.key == key).unwrap().value.to_owned();
+    assert_eq!(
+        (
+            attribute("denom"),
+            attribute("amount"),
+            attribute("balance_before"),
+            attribute("balance_after")
+        ),
+        (
+            "usdt".to_string(),
+            "2399900".to_string(),
+            "1000000000000000".to_string(),
+            "999999997600100".to_string()
+        ),
+        "treasury send should be reported as a withdrawal"
+    );
+    assert_eq!(response.messages.len(), 1, "swap of the next fee coin should wait for the send");
+    assert_eq!(
+        (response.messages[0].id, response.messages[0].reply_on.to_owned()),
+        (FEE_CONVERSION_REPLY_ID, ReplyOn::Success),
+        "next fee coin should be swapped in the reply of the send"
+    );
+
+    #[allow(deprecated)]
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        Reply {
+            id: FEE_CONVERSION_REPLY_ID,
+            payload: Binary::default(),
+            gas_used: 0,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+                msg_responses: vec![],
+            }),
+        },
+    )
+    .unwrap();
+    assert_eq!(response.messages.len(), 1, "swap of the next fee coin should be started");
+    assert_eq!(
+        response.messages[0].id, ATOMIC_ORDER_REPLY_ID,
+        "next fee coin should be swapped by an atomic order"
+    );
+
+    let response = reply(
+        deps.as_mut_deps(),
+        env,
+        mock_atomic_order_reply(FPDecimal::from(100u128), FPDecimal::from(10u128), FPDecimal::ONE),
+    )
+    .unwrap();
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(TREASURY.to_string(), vec![coin(999u128, "usdt")])],
+        "converted inj fees should be sent to treasury"
+    );
+    assert_eq!(response.messages.len(), 1, "no swap should follow the last fee coin");
+    assert!(
+        FEE_CONVERSION.may_load(deps.as_ref().storage).unwrap().is_none(),
+        "finished conversion should be cleared"
+    );
+}
+
+// eth is priced by selling it into the eth/usdt market and inj and usdt by the oracle, which quotes 1 for any pair
+fn set_conversion_price_sources(deps: DepsMut<InjectiveQueryWrapper>, env: &Env) {
+    let oracle_pricing = |denom: &str| BufferPricing {
+        denom: denom.to_string(),
+        decimals: 0,
+        source: BufferPriceSource::Oracle {
+            oracle_type: OracleType::PriceFeed,
+            base: denom.to_string(),
+            quote: "usd".to_string(),
+        },
+    };
+
+    execute(
+        deps,
+        env.to_owned(),
+        message_info(&Addr::unchecked(TEST_USER_ADDR), &[]),
+        ExecuteMsg::SetBufferPriceSources {
+            sources: vec![
+                BufferPricing {
+                    denom: "eth".to_string(),
+                    decimals: 0,
+                    source: BufferPriceSource::Route {
+                        target_denom: "usdt".to_string(),
+                        quantity: FPDecimal::from(12u128),
+                        target_decimals: 0,
+                    },
+                },
+                oracle_pricing("inj"),
+                oracle_pricing("usdt"),
+            ],
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn fee_conversion_is_limited_to_accrued_fees_and_max_slippage_and_counts_no_volume() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler =
+        create_oracle_query_handler(FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ZERO, FPDecimal::ZERO, 0, 0);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(true)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::SetFeeTiers {
+            reference_denom: "usdt".to_string(),
+            tiers: vec![FeeTier {
+                min_volume: FPDecimal::from(1_000_000u128),
+                discount_bps: 1_000,
+            }],
+        },
+    )
+    .unwrap();
+    set_conversion_price_sources(deps.as_mut_deps(), &env);
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "eth", FPDecimal::from(12u128)).unwrap();
+
+    let convert = |amount: u128| ExecuteMsg::ConvertFees {
+        coins: vec![coin(amount, "eth")],
+        target_denom: "usdt".to_string(),
+        max_slippage_bps: 0,
+    };
+
+    let result = execute(deps.as_mut_deps(), env.to_owned(), message_info(&admin, &[]), convert(13));
+    assert!(result.is_err(), "converting more than the accrued fees should be rejected");
+
+    // contract holds 10^15 eth in mocks
+    WITHDRAWAL_RESERVES
+        .save(deps.as_mut_deps().storage, "eth".to_string(), &FPDecimal::from(999_999_999_999_989u128))
+        .unwrap();
+    let result = execute(deps.as_mut_deps(), env.to_owned(), message_info(&admin, &[]), convert(12));
+    assert!(
+        matches!(result, Err(ContractError::WithdrawalBelowReserve { ref denom, .. }) if denom == "eth"),
+        "conversion below reserve should be rejected, got {result:?}"
+    );
+    WITHDRAWAL_RESERVES.remove(deps.as_mut_deps().storage, "eth".to_string());
+
+    execute(deps.as_mut_deps(), env.to_owned(), message_info(&admin, &[]), convert(12)).unwrap();
+    let result = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(100000u128), FPDecimal::from(100u128)),
+    );
+    assert!(
+        matches!(result, Err(ContractError::MinOutputAmountNotReached(..))),
+        "conversion below price source value should fail, got {result:?}"
+    );
+
+    reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    let treasury_volume: TraderVolumeResponse = from_json(
+        query(
+            deps.as_ref(),
+            env,
+            QueryMsg::GetTraderVolume {
+                trader: Addr::unchecked(TREASURY),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        treasury_volume.volume,
+        FPDecimal::ZERO,
+        "fee conversion should not count as trader volume"
+    );
+}
+
+#[test]
+fn it_returns_swap_response_in_data() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                max_integrator_fee_bps: 50,
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    store_single_step_eth_usdt_swap(
+        deps.as_mut_deps().storage,
+        SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE),
+        Some(Integrator {
+            address: Addr::unchecked("wallet"),
+            fee_bps: 20,
+        }),
+    );
+    let response = reply(
+        deps.as_mut_deps(),
+        env,
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    let swap_response: SwapResponse = from_json(response.data.expect("swap response should be set as data")).unwrap();
+    assert_eq!(
+        swap_response,
+        SwapResponse {
+            output: coin(2399900u128 - 7199u128 - 4799u128, "usdt"),
+            refund: coin(0u128, "eth"),
+            protocol_fee: coin(7199u128, "usdt"),
+            integrator_fee: coin(4799u128, "usdt"),
+            swap_results: vec![SwapResults {
+                market_id: MarketId::unchecked(TEST_MARKET_ID_1),
+                quantity: FPDecimal::from(2399900u128),
+                price: FPDecimal::from(200000u128),
+                fee: FPDecimal::from(100u128),
+                rebate: FPDecimal::ZERO,
+            }],
+        },
+        "wrong swap response data"
+    );
+}
+
+#[test]
+fn it_applies_fee_overrides_of_the_route() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                admin: admin.to_owned(),
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::SetRouteFeeOverrides {
+            source_denom: "usdt".to_string(),
+            target_denom: "eth".to_string(),
+            fee_recipient: Some(FeeRecipient::Address(Addr::unchecked("partner"))),
+            protocol_fee_bps: Some(50),
+        },
+    )
+    .unwrap();
+
+    // changing steps of the route keeps its overrides
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+
+    let response = execute(
+        deps.as_mut_deps(),
+        env,
+        message_info(&Addr::unchecked(SWAPPER), &[coin(12u128, "eth")]),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "usdt".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: None,
+            tip_dust: false,
+        },
+    )
+    .unwrap();
+
+    let order_fee_recipient = match &response.messages[0].msg {
+        CosmosMsg::Custom(InjectiveMsgWrapper {
+            msg_data: InjectiveMsg::CreateSpotMarketOrder { order, .. },
+            ..
+        }) => order.order_info.fee_recipient.to_owned(),
+        msg => panic!("expected spot market order, got {msg:?}"),
+    };
+    assert_eq!(
+        order_fee_recipient,
+        Some(Addr::unchecked("partner")),
+        "route fee recipient should be used"
+    );
+
+    let swap_operation = SWAP_OPERATION_STATE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(swap_operation.output_fee_rates.protocol_fee_bps, 50, "route protocol fee should be used");
+}
+
+#[test]
+fn it_sets_swapper_as_fee_recipient_in_sender_mode() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Sender,
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+
+    let response = execute(
+        deps.as_mut_deps(),
+        env,
+        message_info(&Addr::unchecked(SWAPPER), &[coin(12u128, "eth")]),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "usdt".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: None,
+            tip_dust: false,
+        },
+    )
+    .unwrap();
+
+    let order_fee_recipient = match &response.messages[0].msg {
+        CosmosMsg::Custom(InjectiveMsgWrapper {
+            msg_data: InjectiveMsg::CreateSpotMarketOrder { order, .. },
+            ..
+        }) => order.order_info.fee_recipient.to_owned(),
+        msg => panic!("expected spot market order, got {msg:?}"),
+    };
+    assert_eq!(order_fee_recipient, Some(Addr::unchecked(SWAPPER)), "swapper should be the fee recipient");
+}
+
+#[test]
+fn it_deposits_burn_share_of_protocol_fee_to_burn_auction() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                burn_share_bps: 2500,
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    // protocol fee of 7199 usdt is split 25% to the burn auction, rounded down, and the rest to the treasury
+    let expected_burn_share = 1799u128;
+    let expected_treasury_share = 7199u128 - expected_burn_share;
+
+    for _ in 0..2 {
+        store_single_step_eth_usdt_swap(deps.as_mut_deps().storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+        let response = reply(
+            deps.as_mut_deps(),
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_bank_sends(&response.messages),
+            vec![
+                (SWAPPER.to_string(), vec![coin(2399900u128 - 7199u128, "usdt")]),
+                (TREASURY.to_string(), vec![coin(expected_treasury_share, "usdt")]),
+            ],
+            "treasury should receive protocol fee net of burn share"
+        );
+
+        let burn_deposit = response
+            .messages
+            .iter()
+            .find_map(|message| match &message.msg {
+                CosmosMsg::Custom(InjectiveMsgWrapper {
+                    msg_data: InjectiveMsg::Deposit { subaccount_id, amount, .. },
+                    ..
+                }) => Some((subaccount_id.to_owned(), amount.to_owned())),
+                _ => None,
+            })
+            .expect("burn auction deposit expected");
+        assert_eq!(burn_deposit.0.as_str(), BURN_AUCTION_SUBACCOUNT_ID, "wrong burn auction subaccount");
+        assert_eq!(burn_deposit.1, coin(expected_burn_share, "usdt"), "wrong burn share deposited");
+    }
+
+    let contributions: Vec<FPCoin> = from_json(query(deps.as_ref(), env, QueryMsg::GetBurnAuctionContributions {}).unwrap()).unwrap();
+    assert_eq!(
+        contributions,
+        vec![FPCoin {
+            amount: FPDecimal::from(2 * expected_burn_share),
+            denom: "usdt".to_string(),
+        }],
+        "burn auction contributions should add up over swaps"
+    );
+}
+
+#[test]
+fn it_records_rounding_of_swap_steps_in_buffer_ledger() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG.save(deps.as_mut_deps().storage, &test_config()).unwrap();
+
+    // buys of 12 eth cost 12 * 200000 + 100 = 2400100 usdt, the first swap leaves 100 usdt in the contract as a tip and
+    // the second one takes 50 usdt from the buffer
+    for (input_quantity, tip_dust) in [(2400200u128, true), (2400050u128, false)] {
+        let storage = deps.as_mut_deps().storage;
+        store_single_step_eth_usdt_swap(storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::from(12u128)), None);
+        let mut swap_operation = SWAP_OPERATION_STATE.load(storage).unwrap();
+        swap_operation.input_funds = coin(input_quantity, "usdt");
+        swap_operation.refund = coin(0u128, "usdt");
+        swap_operation.tip_dust = tip_dust;
+        SWAP_OPERATION_STATE.save(storage, &swap_operation).unwrap();
+        STEP_STATE
+            .save(
+                storage,
+                &CurrentSwapStep {
+                    step_idx: 0,
+                    current_balance: FPCoin {
+                        amount: FPDecimal::from(input_quantity),
+                        denom: "usdt".to_string(),
+                    },
+                    step_target_denom: "eth".to_string(),
+                    is_buy: true,
+                },
+            )
+            .unwrap();
+
+        reply(
+            deps.as_mut_deps(),
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap();
+    }
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    assert_eq!(
+        ledger,
+        vec![BufferLedgerEntry {
+            denom: "usdt".to_string(),
+            ledger: BufferLedger {
+                rounding_gains: FPDecimal::from(100u128),
+                rounding_losses: FPDecimal::from(50u128),
+                ..BufferLedger::default()
+            },
+        }],
+        "wrong rounding recorded in buffer ledger"
+    );
+}
+
+#[test]
+fn it_refunds_whole_units_of_rounding_dust_at_end_of_swap() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    CONFIG.save(deps.as_mut_deps().storage, &test_config()).unwrap();
+
+    // buy of 12 eth costs 12 * 200000 + 100.5 = 2400100.5 usdt and leaves 99.5 usdt of the swapper in the contract
+    let storage = deps.as_mut_deps().storage;
+    store_single_step_eth_usdt_swap(storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::from(12u128)), None);
+    let mut swap_operation = SWAP_OPERATION_STATE.load(storage).unwrap();
+    swap_operation.input_funds = coin(2400200u128, "usdt");
+    swap_operation.refund = coin(0u128, "usdt");
+    SWAP_OPERATION_STATE.save(storage, &swap_operation).unwrap();
+    STEP_STATE
+        .save(
+            storage,
+            &CurrentSwapStep {
+                step_idx: 0,
+                current_balance: FPCoin {
+                    amount: FPDecimal::from(2400200u128),
+                    denom: "usdt".to_string(),
+                },
+                step_target_denom: "eth".to_string(),
+                is_buy: true,
+            },
+        )
+        .unwrap();
+
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::must_from_str("100.5")),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![
+            (SWAPPER.to_string(), vec![coin(12u128, "eth")]),
+            (SWAPPER.to_string(), vec![coin(99u128, "usdt")]),
+        ],
+        "whole units of the dust should be refunded"
+    );
+
+    let swap_event = response
+        .events
+        .iter()
+        .find(|event| event.ty == "atomic_swap_execution")
+        .expect("swap event expected");
+    let get_attribute = |key: &str| swap_event.attributes.iter().find(|a| a.key == key).unwrap().value.to_owned();
+    assert_eq!(get_attribute("dust"), "99.5usdt", "wrong dust reported");
+    assert_eq!(get_attribute("dust_refund"), "99usdt", "wrong dust refund reported");
+    assert_eq!(get_attribute("dust_tipped"), "false", "dust should not be tipped");
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    assert_eq!(
+        ledger,
+        vec![BufferLedgerEntry {
+            denom: "usdt".to_string(),
+            ledger: BufferLedger {
+                rounding_gains: FPDecimal::must_from_str("0.5"),
+                ..BufferLedger::default()
+            },
+        }],
+        "fraction of the dust should stay in the buffer"
+    );
+}
+
+#[test]
+fn buffer_ledger_follows_contract_balances_through_multi_hop_swap_with_dust() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.protocol_fee_bps = 10;
+    config.treasury = Some(Addr::unchecked(TREASURY));
+    CONFIG.save(deps.as_mut_deps().storage, &config).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "inj".to_string(),
+        vec![TEST_MARKET_ID_1.into(), TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+    // protocol fee tops up the inj buffer, which holds only the fraction of the output when the fee is charged
+    for (denom, target_balance) in [("inj", Some(FPDecimal::ONE)), ("usdt", None)] {
+        BUFFER_DENOMS
+            .save(
+                deps.as_mut_deps().storage,
+                denom.to_string(),
+                &BufferDenom {
+                    denom: denom.to_string(),
+                    min_balance: FPDecimal::ZERO,
+                    target_balance,
+                    low_balance: None,
+                    critical_balance: None,
+                    critical_max_use_bps: 0,
+                },
+            )
+            .unwrap();
+    }
+
+    let input = coin(12u128, "eth");
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&Addr::unchecked(SWAPPER), &[input.to_owned()]),
+        ExecuteMsg::SwapMinOutput {
+            target_denom: "inj".to_string(),
+            min_output_quantity: FPDecimal::ONE,
+            integrator: None,
+            tip_dust: false,
+        },
+    )
+    .unwrap();
+
+    // sell of 12 eth brings 12 * 197000 - 5910.75 = 2358089.25 usdt, buy of 2880.5 inj spends 2880.5 * 818 + 1767.1875 =
+    // 2358016.1875 usdt of it
+    let sell = (FPDecimal::from(12u128), FPDecimal::from(197000u128), FPDecimal::must_from_str("5910.75"));
+    let buy = (
+        FPDecimal::must_from_str("2880.5"),
+        FPDecimal::from(818u128),
+        FPDecimal::must_from_str("1767.1875"),
+    );
+    reply(deps.as_mut_deps(), env.to_owned(), mock_atomic_order_reply(sell.0, sell.1, sell.2)).unwrap();
+    let response = reply(deps.as_mut_deps(), env.to_owned(), mock_atomic_order_reply(buy.0, buy.1, buy.2)).unwrap();
+
+    // whole units of the usdt dust, the output less the truncated protocol fee and the fee less the top-up are sent out
+    let sends = get_bank_sends(&response.messages);
+    assert_eq!(
+        sends,
+        vec![
+            (SWAPPER.to_string(), vec![coin(2878u128, "inj")]),
+            (TREASURY.to_string(), vec![coin(1u128, "inj")]),
+            (SWAPPER.to_string(), vec![coin(73u128, "usdt")]),
+        ],
+        "wrong sends of output, dust refund and protocol fee"
+    );
+
+    // what the swap left in the contract of each denom, by the order results and the sends
+    let sent = |denom: &str| {
+        sends
+            .iter()
+            .flat_map(|(_, coins)| coins.iter())
+            .filter(|coin| coin.denom == denom)
+            .fold(FPDecimal::ZERO, |sum, coin| sum + FPDecimal::from(coin.amount))
+    };
+    let contract_balance_changes = [
+        ("eth", FPDecimal::from(input.amount) - sell.0),
+        ("inj", buy.0 - sent("inj")),
+        ("usdt", sell.0 * sell.1 - sell.2 - (buy.0 * buy.1 + buy.2) - sent("usdt")),
+    ];
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    for (denom, balance_change) in contract_balance_changes {
+        let ledger_balance = ledger
+            .iter()
+            .find(|entry| entry.denom == denom)
+            .map_or(FPDecimal::ZERO, |entry| entry.ledger.balance());
+        assert_eq!(
+            ledger_balance, balance_change,
+            "buffer ledger of {denom} should hold what the swap left in the contract"
+        );
+    }
+}
+
+#[test]
+fn it_keeps_depositor_share_of_protocol_fee_in_buffer() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG
+        .save(
+            deps.as_mut_deps().storage,
+            &Config {
+                protocol_fee_bps: 30,
+                treasury: Some(Addr::unchecked(TREASURY)),
+                depositor_fee_share_bps: 5000,
+                ..test_config()
+            },
+        )
+        .unwrap();
+
+    // half of the 7199 usdt protocol fee, rounded down, stays in the buffer
+    let expected_depositor_share = 3599u128;
+
+    store_single_step_eth_usdt_swap(deps.as_mut_deps().storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![
+            (SWAPPER.to_string(), vec![coin(2399900u128 - 7199u128, "usdt")]),
+            (TREASURY.to_string(), vec![coin(7199u128 - expected_depositor_share, "usdt")]),
+        ],
+        "treasury should receive protocol fee net of depositor share"
+    );
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    assert_eq!(
+        ledger,
+        vec![BufferLedgerEntry {
+            denom: "usdt".to_string(),
+            ledger: BufferLedger {
+                fees: FPDecimal::from(expected_depositor_share),
+                ..BufferLedger::default()
+            },
+        }],
+        "depositor share should be recorded as buffer fees"
+    );
+}
+
+fn protocol_fee_config(accrue_protocol_fees: bool) -> Config {
+    Config {
+        protocol_fee_bps: 30,
+        treasury: Some(Addr::unchecked(TREASURY)),
+        accrue_protocol_fees,
+        ..test_config()
+    }
+}
+
+fn save_usdt_buffer_target(storage: &mut dyn Storage, target_balance: u128) {
+    BUFFER_DENOMS
+        .save(
+            storage,
+            "usdt".to_string(),
+            &BufferDenom {
+                denom: "usdt".to_string(),
+                min_balance: FPDecimal::ZERO,
+                target_balance: Some(FPDecimal::from(target_balance)),
+                low_balance: None,
+                critical_balance: None,
+                critical_max_use_bps: 0,
+            },
+        )
+        .unwrap();
+}
+
+fn get_usdt_buffer_fees(deps: Deps<InjectiveQueryWrapper>, env: &Env) -> FPDecimal {
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps, env.to_owned(), QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    ledger.into_iter().find(|entry| entry.denom == "usdt").unwrap().ledger.fees
+}
+
+#[test]
+fn it_tops_up_buffer_to_target_from_protocol_fee_after_swap() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(false)).unwrap();
+    save_usdt_buffer_target(deps.as_mut_deps().storage, 5000);
+    record_buffer_deposit(deps.as_mut_deps().storage, "usdt", FPDecimal::from(1000u128)).unwrap();
+
+    let swap_reply = |deps: DepsMut<InjectiveQueryWrapper>| {
+        store_single_step_eth_usdt_swap(deps.storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+        reply(
+            deps,
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap()
+    };
+
+    // 4000 of the 7199 usdt protocol fee bring the buffer up to its target
+    let response = swap_reply(deps.as_mut_deps());
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![
+            (SWAPPER.to_string(), vec![coin(2399900u128 - 7199u128, "usdt")]),
+            (TREASURY.to_string(), vec![coin(7199u128 - 4000u128, "usdt")]),
+        ],
+        "treasury should receive protocol fee above buffer target"
+    );
+    assert_eq!(
+        get_usdt_buffer_fees(deps.as_ref(), &env),
+        FPDecimal::from(4000u128),
+        "top-up should be recorded as buffer fees"
+    );
+
+    let response = swap_reply(deps.as_mut_deps());
+    assert_eq!(
+        get_bank_sends(&response.messages)[1],
+        (TREASURY.to_string(), vec![coin(7199u128, "usdt")]),
+        "treasury should receive whole protocol fee once buffer is at target"
+    );
+    assert_eq!(
+        get_usdt_buffer_fees(deps.as_ref(), &env),
+        FPDecimal::from(4000u128),
+        "buffer at target should not be topped up"
+    );
+}
+
+#[test]
+fn it_accrues_protocol_fees_until_keeper_tops_up_buffers() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(true)).unwrap();
+    save_usdt_buffer_target(deps.as_mut_deps().storage, 5000);
+
+    store_single_step_eth_usdt_swap(deps.as_mut_deps().storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+    )
+    .unwrap();
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(SWAPPER.to_string(), vec![coin(2399900u128 - 7199u128, "usdt")])],
+        "protocol fee should stay in the contract"
+    );
+
+    let accrued_fees: Vec<FPCoin> = from_json(query(deps.as_ref(), env.to_owned(), QueryMsg::GetAccruedProtocolFees {}).unwrap()).unwrap();
+    assert_eq!(
+        accrued_fees,
+        vec![FPCoin {
+            amount: FPDecimal::from(7199u128),
+            denom: "usdt".to_string(),
+        }],
+        "wrong accrued protocol fees"
+    );
+
+    let keeper = message_info(&Addr::unchecked("keeper"), &[]);
+    let response = execute(deps.as_mut(), env.to_owned(), keeper.to_owned(), ExecuteMsg::TopUpBuffers {}).unwrap();
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(TREASURY.to_string(), vec![coin(7199u128 - 5000u128, "usdt")])],
+        "treasury should receive accrued fees above buffer target"
+    );
+    assert_eq!(
+        get_usdt_buffer_fees(deps.as_ref(), &env),
+        FPDecimal::from(5000u128),
+        "buffer should be topped up to its target"
+    );
+
+    let result = execute(deps.as_mut(), env, keeper, ExecuteMsg::TopUpBuffers {});
+    assert!(result.is_err(), "accrued fees should be swept only once");
+}
+
+#[test]
+fn converted_protocol_fees_are_not_swept_again_by_buffer_top_up() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler =
+        create_oracle_query_handler(FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ZERO, FPDecimal::ZERO, 0, 0);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(true)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "eth".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_1.into()],
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+    set_conversion_price_sources(deps.as_mut_deps(), &env);
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "eth", FPDecimal::from(12u128)).unwrap();
+    record_accrued_protocol_fee(deps.as_mut_deps().storage, "inj", FPDecimal::from(100u128)).unwrap();
+
+    execute(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        ExecuteMsg::ConvertFees {
+            coins: vec![coin(12u128, "eth"), coin(40u128, "inj")],
+            target_denom: "usdt".to_string(),
+            max_slippage_bps: 100,
+        },
+    )
+    .unwrap();
+
+    let accrued_fees: Vec<FPCoin> = from_json(query(deps.as_ref(), env.to_owned(), QueryMsg::GetAccruedProtocolFees {}).unwrap()).unwrap();
+    assert_eq!(
+        accrued_fees,
+        vec![FPCoin {
+            amount: FPDecimal::from(60u128),
+            denom: "inj".to_string(),
+        }],
+        "converted fees should be taken out of accrued fees"
+    );
+
+    let response = execute(
+        deps.as_mut(),
+        env,
+        message_info(&Addr::unchecked("keeper"), &[]),
+        ExecuteMsg::TopUpBuffers {},
+    )
+    .unwrap();
+    assert_eq!(
+        get_bank_sends(&response.messages),
+        vec![(TREASURY.to_string(), vec![coin(60u128, "inj")])],
+        "only fees left after the conversion should be swept"
+    );
+}
+
+#[test]
+fn it_emits_buffer_low_event_when_swap_takes_buffer_below_low_balance() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(false)).unwrap();
+
+    let swap_with_low_balance = |deps: DepsMut<InjectiveQueryWrapper>, low_balance: FPDecimal, balance_before: FPDecimal, dust: u128| {
+        BUFFER_DENOMS
+            .save(
+                deps.storage,
+                "usdt".to_string(),
+                &BufferDenom {
+                    denom: "usdt".to_string(),
+                    min_balance: FPDecimal::ZERO,
+                    target_balance: None,
+                    low_balance: Some(low_balance),
+                    critical_balance: Some(FPDecimal::ZERO),
+                    critical_max_use_bps: 0,
+                },
+            )
+            .unwrap();
+        store_single_step_eth_usdt_swap(deps.storage, SwapQuantityMode::MinOutputQuantity(FPDecimal::ONE), None);
+        SWAP_OPERATION_STATE
+            .update(deps.storage, |mut swap| -> StdResult<_> {
+                swap.buffer_balances_before = vec![FPCoin {
+                    amount: balance_before,
+                    denom: "usdt".to_string(),
+                }];
+                Ok(swap)
+            })
+            .unwrap();
+        record_swap_dust(deps.storage, "usdt", FPDecimal::from(dust)).unwrap();
+        reply(
+            deps,
+            env.to_owned(),
+            mock_atomic_order_reply(FPDecimal::from(12u128), FPDecimal::from(200000u128), FPDecimal::from(100u128)),
+        )
+        .unwrap()
+    };
+    let buffer_low_events = |response: &Response<InjectiveMsgWrapper>| response.events.iter().filter(|event| event.ty == "buffer_low").count();
+
+    // contract holds 10^15 usdt in mocks, 2399900 of it are output and fees of the swap
+    let balance_before = FPDecimal::from(1_000_000_000_000_000u128);
+    let buffer_balance = FPDecimal::from(1_000_000_000_000_000u128 - 2399900u128);
+
+    let response = swap_with_low_balance(deps.as_mut_deps(), buffer_balance, balance_before, 0);
+    assert_eq!(buffer_low_events(&response), 0, "buffer at its low balance should not be reported");
+
+    let response = swap_with_low_balance(deps.as_mut_deps(), buffer_balance, balance_before, 5);
+    assert_eq!(buffer_low_events(&response), 1, "dust refunded to the swapper should not count as buffer");
+
+    let response = swap_with_low_balance(deps.as_mut_deps(), buffer_balance + FPDecimal::ONE, buffer_balance, 0);
+    assert_eq!(
+        buffer_low_events(&response),
+        0,
+        "buffer already low before the swap should not be reported again"
+    );
+
+    let response = swap_with_low_balance(deps.as_mut_deps(), buffer_balance + FPDecimal::ONE, balance_before, 0);
+    let buffer_low_event = response
+        .events
+        .iter()
+        .find(|event| event.ty == "buffer_low")
+        .expect("buffer_low event expected");
+    assert_eq!(
+        buffer_low_event
+            .attributes
+            .iter()
+            .map(|attribute| (attribute.key.as_str(), attribute.value.to_owned()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("denom", "usdt".to_string()),
+            ("balance_before", balance_before.to_string()),
+            ("balance", buffer_balance.to_string()),
+            ("low_balance", (buffer_balance + FPDecimal::ONE).to_string()),
+            ("is_critical", "false".to_string()),
+        ],
+        "wrong buffer_low event"
+    );
+}
+
+#[test]
+fn it_caps_buffer_used_by_buys_per_swap_and_per_block() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+
+    let input = coin(1000000u128, "usdt");
+    let buffer_needed = estimate_single_swap_execution(
+        &deps.as_ref(),
+        &env,
+        &LiveMarketData::new(deps.as_ref(), &env),
+        &MarketId::unchecked(TEST_MARKET_ID_2),
+        &FeeRecipient::Address(admin.to_owned()),
+        SwapEstimationAmount::InputQuantity(input.to_owned().into()),
+        false,
+    )
+    .unwrap()
+    .buffer_needed;
+    assert!(buffer_needed > FPDecimal::ZERO, "buy over several levels should need buffer");
+
+    let set_caps = |deps: DepsMut<InjectiveQueryWrapper>, per_swap: Option<FPDecimal>, per_block: Option<FPDecimal>| {
+        execute(
+            deps,
+            mock_env(),
+            message_info(&admin, &[]),
+            ExecuteMsg::SetBufferCaps {
+                caps: vec![BufferCap {
+                    denom: "usdt".to_string(),
+                    per_swap,
+                    per_block,
+                }],
+            },
+        )
+        .unwrap();
+    };
+    let swap = |deps: DepsMut<InjectiveQueryWrapper>, env: &Env| {
+        execute(
+            deps,
+            env.to_owned(),
+            message_info(&Addr::unchecked(SWAPPER), &[input.to_owned()]),
+            ExecuteMsg::SwapMinOutput {
+                target_denom: "inj".to_string(),
+                min_output_quantity: FPDecimal::ONE,
+                integrator: None,
+                tip_dust: false,
+            },
+        )
+    };
+
+    let half_needed = buffer_needed / FPDecimal::TWO;
+    set_caps(deps.as_mut_deps(), Some(half_needed), None);
+    let result = swap(deps.as_mut_deps(), &env);
+    assert!(
+        matches!(result, Err(ContractError::BufferCapExceeded { ref denom, available, .. }) if denom == "usdt" && available == half_needed),
+        "swap above the per-swap cap should fail, got {result:?}"
+    );
+
+    let quote = query(
+        deps.as_ref(),
+        env.to_owned(),
+        QueryMsg::GetOutputQuantity {
+            from_quantity: input.amount.into(),
+            source_denom: "usdt".to_string(),
+            target_denom: "inj".to_string(),
+            route: None,
+            trader: None,
+            integrator: None,
+        },
+    );
+    assert!(quote.is_err(), "quote above the per-swap cap should fail");
+
+    // block cap leaves room for one swap
+    set_caps(deps.as_mut_deps(), None, Some(buffer_needed + half_needed));
+    swap(deps.as_mut_deps(), &env).unwrap();
+    SWAP_OPERATION_STATE.remove(deps.as_mut_deps().storage);
+    let result = swap(deps.as_mut_deps(), &env);
+    assert!(
+        matches!(result, Err(ContractError::BufferCapExceeded { available, .. }) if available == half_needed),
+        "second swap in the block should only have what is left of the block cap, got {result:?}"
+    );
+
+    env.block.height += 1;
+    swap(deps.as_mut_deps(), &env).unwrap();
+}
+
+#[test]
+fn fee_exempt_swaps_draw_down_their_own_budget_instead_of_buffer_caps() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    let admin = Addr::unchecked(TEST_USER_ADDR);
+    let rebalancer = Addr::unchecked(SWAPPER);
+
+    instantiate(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        message_info(&admin, &[]),
+        InstantiateMsg {
+            fee_recipient: FeeRecipient::Address(admin.to_owned()),
+            admin: admin.to_owned(),
+        },
+    )
+    .unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![TEST_MARKET_ID_2.into()],
+    )
+    .unwrap();
+    update_fee_exempt_addresses(deps.as_mut_deps(), &admin, vec![rebalancer.to_owned()], vec![]).unwrap();
+
+    let input = coin(1000000u128, "usdt");
+    let buffer_needed = estimate_single_swap_execution(
+        &deps.as_ref(),
+        &env,
+        &LiveMarketData::new(deps.as_ref(), &env),
+        &MarketId::unchecked(TEST_MARKET_ID_2),
+        &FeeRecipient::Address(admin.to_owned()),
+        SwapEstimationAmount::InputQuantity(input.to_owned().into()),
+        false,
+    )
+    .unwrap()
+    .buffer_needed;
+    let half_needed = buffer_needed / FPDecimal::TWO;
+
+    set_buffer_caps(
+        deps.as_mut_deps(),
+        &admin,
+        vec![BufferCap {
+            denom: "usdt".to_string(),
+            per_swap: Some(half_needed),
+            per_block: Some(half_needed),
+        }],
+    )
+    .unwrap();
+    set_exempt_buffer_budget(
+        deps.as_mut_deps(),
+        &admin,
+        vec![FPCoin {
+            amount: buffer_needed + half_needed,
+            denom: "usdt".to_string(),
+        }],
+    )
+    .unwrap();
+
+    let swap = |deps: DepsMut<InjectiveQueryWrapper>, env: &Env| {
+        execute(
+            deps,
+            env.to_owned(),
+            message_info(&rebalancer, &[input.to_owned()]),
+            ExecuteMsg::SwapMinOutput {
+                target_denom: "inj".to_string(),
+                min_output_quantity: FPDecimal::ONE,
+                integrator: None,
+                tip_dust: false,
+            },
+        )
+    };
+
+    swap(deps.as_mut_deps(), &env).unwrap();
+    assert_eq!(
+        get_buffer_cap_left(deps.as_ref().storage, "usdt", env.block.height).unwrap(),
+        Some(half_needed),
+        "exempt swap should neither be held to nor use up the shared block cap"
+    );
+    assert_eq!(
+        get_exempt_buffer_budget(deps.as_ref().storage).unwrap(),
+        vec![FPCoin {
+            amount: half_needed,
+            denom: "usdt".to_string(),
+        }],
+        "exempt swap should draw down its budget"
+    );
+
+    // the budget does not come back in the next block
+    SWAP_OPERATION_STATE.remove(deps.as_mut_deps().storage);
+    env.block.height += 1;
+    let result = swap(deps.as_mut_deps(), &env);
+    assert!(
+        matches!(result, Err(ContractError::ExemptBufferBudgetExceeded { budget, .. }) if budget == half_needed),
+        "exempt swap above what is left of the budget should fail, got {result:?}"
+    );
+}
+
+#[test]
+fn keeper_rebalances_buffer_between_denoms_within_max_slippage() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler =
+        create_oracle_query_handler(FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ZERO, FPDecimal::ZERO, 0, 0);
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+    let keeper = Addr::unchecked("keeper");
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(false)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin.sender,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![MarketId::unchecked(TEST_MARKET_ID_2)],
+    )
+    .unwrap();
+
+    let buffer = |denom: &str| BufferDenom {
+        denom: denom.to_string(),
+        min_balance: FPDecimal::ZERO,
+        target_balance: None,
+        low_balance: None,
+        critical_balance: None,
+        critical_max_use_bps: 0,
+    };
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetBufferDenoms {
+            buffers: vec![buffer("inj"), buffer("usdt")],
+        },
+    )
+    .unwrap();
+    // 10 inj sell into the best inj/usdt bid of 800 for 7980 usdt after fees, usdt is priced at 1 by the oracle
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetBufferPriceSources {
+            sources: vec![
+                BufferPricing {
+                    denom: "inj".to_string(),
+                    decimals: 0,
+                    source: BufferPriceSource::Route {
+                        target_denom: "usdt".to_string(),
+                        quantity: FPDecimal::from(10u128),
+                        target_decimals: 0,
+                    },
+                },
+                BufferPricing {
+                    denom: "usdt".to_string(),
+                    decimals: 0,
+                    source: BufferPriceSource::Oracle {
+                        oracle_type: OracleType::PriceFeed,
+                        base: "usdt".to_string(),
+                        quote: "usd".to_string(),
+                    },
+                },
+            ],
+        },
+    )
+    .unwrap();
+    record_buffer_deposit(deps.as_mut_deps().storage, "inj", FPDecimal::from(1000u128)).unwrap();
+
+    let rebalance = ExecuteMsg::RebalanceBuffer {
+        coin: coin(10u128, "inj"),
+        target_denom: "usdt".to_string(),
+        max_slippage_bps: 10,
+    };
+    let result = execute(deps.as_mut(), env.to_owned(), message_info(&keeper, &[]), rebalance.to_owned());
+    assert!(result.is_err(), "expected error on rebalance by address that is not a keeper");
+
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin,
+        ExecuteMsg::UpdateBufferKeepers {
+            add: vec![keeper.to_owned()],
+            remove: vec![],
+        },
+    )
+    .unwrap();
+    let keepers: Vec<Addr> = from_json(
+        query(
+            deps.as_ref(),
+            env.to_owned(),
+            QueryMsg::GetBufferKeepers {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(keepers, vec![keeper.to_owned()], "wrong buffer keepers");
+
+    // contract holds 10^15 inj in mocks, the rebalance sells 10 of it
+    BUFFER_DENOMS
+        .save(
+            deps.as_mut_deps().storage,
+            "inj".to_string(),
+            &BufferDenom {
+                min_balance: FPDecimal::from(999_999_999_999_991u128),
+                ..buffer("inj")
+            },
+        )
+        .unwrap();
+    let result = execute(deps.as_mut(), env.to_owned(), message_info(&keeper, &[]), rebalance.to_owned());
+    assert!(
+        matches!(result, Err(ContractError::BufferUnderfunded { ref denom, .. }) if denom == "inj"),
+        "rebalance should not take the buffer it sells below its min balance, got {result:?}"
+    );
+    BUFFER_DENOMS.save(deps.as_mut_deps().storage, "inj".to_string(), &buffer("inj")).unwrap();
+
+    let response = execute(deps.as_mut(), env.to_owned(), message_info(&keeper, &[]), rebalance).unwrap();
+    assert_eq!(response.messages.len(), 1, "rebalance should place an order");
+    let min_output = response.attributes.iter().find(|a| a.key == "min_output").unwrap();
+    assert_eq!(min_output.value, "7972.02", "min output should be 7980 usdt less 10 bps");
+
+    let result = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(10u128), FPDecimal::from(800u128), FPDecimal::from(30u128)),
+    );
+    assert!(
+        matches!(result, Err(ContractError::RebalanceSlippageExceeded { output, .. }) if output == FPDecimal::from(7970u128)),
+        "rebalance beyond max slippage should fail, got {result:?}"
+    );
+
+    let response = reply(
+        deps.as_mut_deps(),
+        env.to_owned(),
+        mock_atomic_order_reply(FPDecimal::from(10u128), FPDecimal::from(800u128), FPDecimal::from(20u128)),
+    )
+    .unwrap();
+    assert!(response.messages.is_empty(), "rebalanced funds should stay in the contract");
+    let rebalance_event = response
+        .events
+        .iter()
+        .find(|event| event.ty == "buffer_rebalance")
+        .expect("buffer rebalance event expected");
+    let output = rebalance_event.attributes.iter().find(|a| a.key == "output").unwrap();
+    assert_eq!(output.value, "7980usdt", "wrong rebalance output reported");
+
+    let ledger: Vec<BufferLedgerEntry> = from_json(query(deps.as_ref(), env.to_owned(), QueryMsg::GetBufferLedger {}).unwrap()).unwrap();
+    assert_eq!(
+        ledger,
+        vec![
+            BufferLedgerEntry {
+                denom: "inj".to_string(),
+                ledger: BufferLedger {
+                    principal: FPDecimal::from(1000u128),
+                    rebalanced_out: FPDecimal::from(10u128),
+                    ..BufferLedger::default()
+                },
+            },
+            BufferLedgerEntry {
+                denom: "usdt".to_string(),
+                ledger: BufferLedger {
+                    rebalanced_in: FPDecimal::from(7980u128),
+                    ..BufferLedger::default()
+                },
+            },
+        ],
+        "rebalance should move buffer from inj to usdt without touching principal"
+    );
+
+    let pnl: BufferPnlResponse = from_json(query(deps.as_ref(), env, QueryMsg::GetBufferPnl {}).unwrap()).unwrap();
+    assert_eq!(
+        pnl.denoms.iter().map(|denom| denom.rebalanced).collect::<Vec<_>>(),
+        vec![-FPDecimal::from(10u128), FPDecimal::from(7980u128)],
+        "rebalanced value should be reported by denom"
+    );
+    assert_eq!(
+        pnl.total_net_value,
+        FPDecimal::ZERO,
+        "rebalance at the price source value should lose nothing"
+    );
+}
+
+#[test]
+fn rebalance_min_output_accounts_for_decimals_of_both_denoms() {
+    let mut env = mock_env();
+    env.contract.address = Addr::unchecked(TEST_CONTRACT_ADDR);
+    let mut deps = mock_deps_eth_inj(MultiplierQueryBehavior::Success);
+    deps.querier.oracle_price_response_handler =
+        create_oracle_query_handler(FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ONE, FPDecimal::ZERO, FPDecimal::ZERO, 0, 0);
+    let admin = message_info(&Addr::unchecked(TEST_USER_ADDR), &[]);
+    CONFIG.save(deps.as_mut_deps().storage, &protocol_fee_config(false)).unwrap();
+    set_route(
+        deps.as_mut_deps(),
+        &admin.sender,
+        "inj".to_string(),
+        "usdt".to_string(),
+        vec![MarketId::unchecked(TEST_MARKET_ID_2)],
+    )
+    .unwrap();
+
+    let buffer = |denom: &str| BufferDenom {
+        denom: denom.to_string(),
+        min_balance: FPDecimal::ZERO,
+        target_balance: None,
+        low_balance: None,
+        critical_balance: None,
+        critical_max_use_bps: 0,
+    };
+    let oracle_pricing = |denom: &str, decimals: u8| BufferPricing {
+        denom: denom.to_string(),
+        decimals,
+        source: BufferPriceSource::Oracle {
+            oracle_type: OracleType::PriceFeed,
+            base: denom.to_string(),
+            quote: "usd".to_string(),
+        },
+    };
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetBufferDenoms {
+            buffers: vec![buffer("inj"), buffer("usdt")],
+        },
+    )
+    .unwrap();
+    // both are priced at 1 by the oracle, inj has 6 decimals and usdt 8
+    execute(
+        deps.as_mut(),
+        env.to_owned(),
+        admin.to_owned(),
+        ExecuteMsg::SetBufferPriceSources {
+            sources: vec![oracle_pricing("inj", 6), oracle_pricing("usdt", 8)],
+        },
+    )
+    .unwrap();
+    record_buffer_deposit(deps.as_mut_deps().storage, "inj", FPDecimal::from(1000u128)).unwrap();
+
+    let response = execute(
+        deps.as_mut(),
+        env,
+        admin,
+        ExecuteMsg::RebalanceBuffer {
+            coin: coin(10u128, "inj"),
+            target_denom: "usdt".to_string(),
+            max_slippage_bps: 0,
+        },
+    )
+    .unwrap();
+    let min_output = response.attributes.iter().find(|a| a.key == "min_output").unwrap();
+    assert_eq!(min_output.value, "1000", "0.00001 inj should be worth 1000 chain units of usdt");
+}
@@ -1,11 +1,11 @@
 use crate::{
-    helpers::Scaled,
-    msg::{ExecuteMsg, FeeRecipient, InstantiateMsg},
-    types::FPCoin,
+    helpers::{dec_scale_factor, Scaled},
+    msg::{ExecuteMsg, FeeRecipient, InstantiateMsg, QueryMsg},
+    types::{BufferLedgerEntry, Config, FPCoin, SwapResponse},
 };
 
 use cosmwasm_std::{
-    coin,
+    coin, from_json,
     testing::{MockApi, MockStorage},
     to_json_binary, Addr, Coin, ContractResult, OwnedDeps, QuerierResult, SystemError, SystemResult, Uint128,
 };
@@ -23,11 +23,11 @@ use injective_std::{
             authz::v1beta1::{Grant, MsgGrant},
             bank::v1beta1::{QueryAllBalancesRequest, QueryBalanceRequest},
         },
-        cosmwasm::wasm::v1::{AcceptedMessageKeysFilter, ContractExecutionAuthorization, ContractGrant, MaxCallsLimit},
-        injective::exchange::v1beta1::{MsgCreateSpotLimitOrder, OrderInfo, OrderType, SpotOrder},
+        cosmwasm::wasm::v1::{AcceptedMessageKeysFilter, ContractExecutionAuthorization, ContractGrant, MaxCallsLimit, MsgExecuteContractResponse},
+        injective::exchange::v1beta1::{MsgCreateSpotLimitOrder, OrderInfo, OrderType, QuerySubaccountDepositsRequest, SpotOrder},
     },
 };
-use injective_test_tube::{Account, Authz, Bank, Exchange, InjectiveTestApp, Module, SigningAccount, Wasm};
+use injective_test_tube::{Account, Authz, Bank, Exchange, ExecuteResponse, InjectiveTestApp, Module, SigningAccount, Wasm};
 use injective_testing::{
     test_tube::bank::send,
     test_tube::exchange::{add_denom_notional_and_decimal, get_spot_market_id},
@@ -48,10 +48,6 @@ pub const INJ: &str = "inj";
 pub const INJ_2: &str = "inj_2";
 pub const NINJA: &str = "ninja";
 
-pub const DEFAULT_TAKER_FEE: f64 = 0.001;
-pub const DEFAULT_ATOMIC_MULTIPLIER: f64 = 2.5;
-pub const DEFAULT_SELF_RELAYING_FEE_PART: f64 = 0.6;
-
 #[allow(clippy::too_many_arguments)]
 pub fn launch_spot_market_custom(
     exchange: &Exchange<InjectiveTestApp>,
@@ -100,6 +96,22 @@ impl Decimals {
     }
 }
 
+// Config with the test user as admin and fee recipient and no fees, tests override what they need
+pub fn test_config() -> Config {
+    Config {
+        fee_recipient: FeeRecipient::Address(Addr::unchecked(TEST_USER_ADDR)),
+        admin: Addr::unchecked(TEST_USER_ADDR),
+        protocol_fee_bps: 0,
+        treasury: None,
+        max_integrator_fee_bps: 0,
+        burn_share_bps: 0,
+        depositor_fee_share_bps: 0,
+        depositor_rebate_share_bps: 0,
+        max_buffer_withdrawal_bps: 0,
+        accrue_protocol_fees: false,
+    }
+}
+
 // Helper function to create a PriceLevel
 pub fn create_price_level(p: u128, q: u128) -> PriceLevel {
     PriceLevel {
@@ -297,7 +309,7 @@ pub fn mock_realistic_deps_eth_atom(
     })
 }
 
-fn create_mock_spot_market(base: &str, min_price_tick_size: FPDecimal, min_quantity_tick_size: FPDecimal, idx: u32) -> SpotMarket {
+pub fn create_mock_spot_market(base: &str, min_price_tick_size: FPDecimal, min_quantity_tick_size: FPDecimal, idx: u32) -> SpotMarket {
     SpotMarket {
         ticker: format!("{base}usdt"),
         base_denom: base.to_string(),
@@ -727,6 +739,118 @@ pub fn query_bank_balance(bank: &Bank<InjectiveTestApp>, denom: &str, address: &
     .unwrap()
 }
 
+pub fn query_subaccount_deposit(exchange: &Exchange<InjectiveTestApp>, subaccount_id: &str, denom: &str) -> FPDecimal {
+    let deposits = exchange
+        .query_subaccount_deposits(&QuerySubaccountDepositsRequest {
+            subaccount_id: subaccount_id.to_string(),
+            subaccount: None,
+        })
+        .unwrap()
+        .deposits;
+
+    // protobuf serializes Dec values with extra 10^18 factor
+    deposits
+        .get(denom)
+        .map(|deposit| FPDecimal::must_from_str(&deposit.total_balance) / dec_scale_factor())
+        .unwrap_or_default()
+}
+
+pub fn query_buffer_ledger_balance(wasm: &Wasm<InjectiveTestApp>, contr_addr: &str, denom: &str) -> FPDecimal {
+    let ledger: Vec<BufferLedgerEntry> = wasm.query(contr_addr, &QueryMsg::GetBufferLedger {}).unwrap();
+    ledger
+        .iter()
+        .find(|entry| entry.denom == denom)
+        .map(|entry| entry.ledger.balance())
+        .unwrap_or_default()
+}
+
+pub fn query_accrued_rebate(wasm: &Wasm<InjectiveTestApp>, contr_addr: &str, denom: &str) -> FPDecimal {
+    let rebates: Vec<FPCoin> = wasm.query(contr_addr, &QueryMsg::GetAccruedRebates {}).unwrap();
+    rebates
+        .iter()
+        .find(|rebate| rebate.denom == denom)
+        .map(|rebate| rebate.amount)
+        .unwrap_or_default()
+}
+
+pub fn get_swap_response(response: &ExecuteResponse<MsgExecuteContractResponse>) -> SwapResponse {
+    from_json(&response.data.data).unwrap()
+}
+
+// dust the swap execution event reports as refunded to the swapper, in whole units of the denom
+pub fn get_swap_dust_refund(response: &ExecuteResponse<MsgExecuteContractResponse>, denom: &str) -> FPDecimal {
+    let dust_refund = response
+        .events
+        .iter()
+        .filter(|event| event.ty == "wasm-atomic_swap_execution")
+        .flat_map(|event| event.attributes.iter())
+        .find(|attribute| attribute.key == "dust_refund")
+        .expect("swap execution event should report the dust refund")
+        .value
+        .to_owned();
+
+    dust_refund
+        .split(',')
+        .filter_map(|coin| coin.strip_suffix(denom))
+        .find(|amount| !amount.is_empty() && amount.chars().all(|c| c.is_ascii_digit()))
+        .map(FPDecimal::must_from_str)
+        .unwrap_or_default()
+}
+
+// checks the swapper got the output and dust refund reported by the swap, that rebates passed on by the swap were paid
+// to the contract and that the buffer ledger of the quote denom follows the change of the contract balance
+#[allow(clippy::too_many_arguments)]
+pub fn assert_swap_payouts(
+    wasm: &Wasm<InjectiveTestApp>,
+    bank: &Bank<InjectiveTestApp>,
+    contr_addr: &str,
+    swapper: &str,
+    response: &ExecuteResponse<MsgExecuteContractResponse>,
+    quote_denom: &str,
+    contract_balance_before: FPDecimal,
+    ledger_balance_before: FPDecimal,
+) {
+    let swap_response = get_swap_response(response);
+    let output_balance = query_bank_balance(bank, &swap_response.output.denom, swapper);
+    assert_eq!(
+        output_balance,
+        FPDecimal::from(swap_response.output.amount),
+        "swapper did not receive output reported by the swap"
+    );
+
+    let dust_refund = get_swap_dust_refund(response, quote_denom);
+    assert_eq!(
+        query_bank_balance(bank, quote_denom, swapper),
+        dust_refund,
+        "swapper did not receive dust refund reported by the swap"
+    );
+
+    let rebates = swap_response.swap_results.iter().fold(FPDecimal::ZERO, |total, result| {
+        assert!(
+            result.rebate > FPDecimal::ZERO,
+            "self-relaying swap got no rebate in market {}",
+            result.market_id.as_str()
+        );
+        total + result.rebate
+    });
+    assert_eq!(
+        query_accrued_rebate(wasm, contr_addr, quote_denom),
+        rebates,
+        "rebates passed on by the swap differ from the ones received by the contract"
+    );
+
+    let contract_balance_change = query_bank_balance(bank, quote_denom, contr_addr) - contract_balance_before;
+    let ledger_balance_change = query_buffer_ledger_balance(wasm, contr_addr, quote_denom) - ledger_balance_before;
+    let max_diff = human_to_dec("0.0001", Decimals::Six);
+    assert!(
+        are_fpdecimals_approximately_equal(contract_balance_change, ledger_balance_change, max_diff),
+        "buffer ledger does not follow contract balance. Balance change: {}, ledger change: {}, max diff: {}",
+        contract_balance_change,
+        ledger_balance_change,
+        max_diff
+    );
+}
+
 pub fn create_contract_authorization(
     app: &InjectiveTestApp,
     contract: String,
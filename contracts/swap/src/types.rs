@@ -1,7 +1,10 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Coin};
-use injective_cosmwasm::MarketId;
+use cosmwasm_std::{Addr, Coin, Timestamp};
+use injective_cosmwasm::{MarketId, OracleType, PriceLevel, SpotMarket};
 use injective_math::FPDecimal;
+use serde::{Deserialize, Deserializer};
+
+use crate::msg::FeeRecipient;
 
 #[cw_serde]
 pub enum SwapEstimationAmount {
@@ -49,6 +52,8 @@ pub struct StepExecutionEstimate {
     pub result_quantity: FPDecimal,
     pub is_buy_order: bool,
     pub fee_estimate: Option<FPCoin>,
+    // part of the funds locked for a buy that comes from the buffer
+    pub buffer_needed: FPDecimal,
 }
 
 #[cw_serde]
@@ -59,6 +64,37 @@ pub struct CurrentSwapOperation {
     pub swap_quantity_mode: SwapQuantityMode,
     pub input_funds: Coin,
     pub refund: Coin,
+    pub integrator: Option<Integrator>,
+    pub output_fee_rates: OutputFeeRates,
+    // exchange fee recipient of the orders, config one unless the route overrides it
+    pub fee_recipient: Addr,
+    // rounding leftovers stay in the buffer instead of being refunded
+    #[serde(default)]
+    pub tip_dust: bool,
+    // swap of the contract's own funds, like a fee conversion, which counts towards no trader volume
+    #[serde(default)]
+    pub internal: bool,
+    // balances of the buffers of quote denoms of the route when the swap started, buffer_low events are emitted for
+    // those the swap takes below their low balance
+    #[serde(default)]
+    pub buffer_balances_before: Vec<FPCoin>,
+}
+
+// Fee coins still waiting to be swapped into target denom by a fee conversion in progress
+#[cw_serde]
+pub struct FeeConversion {
+    pub target_denom: String,
+    pub pending: Vec<Coin>,
+    // each output must be worth its coin by the buffer price sources, less this slippage
+    pub max_slippage_bps: u16,
+}
+
+// Buffer funds being swapped into another buffer denom by a rebalance in progress
+#[cw_serde]
+pub struct BufferRebalance {
+    pub keeper: Addr,
+    pub input: Coin,
+    pub min_output: FPDecimal,
 }
 
 #[cw_serde]
@@ -76,14 +112,276 @@ pub struct SwapResults {
     pub quantity: FPDecimal,
     pub price: FPDecimal,
     pub fee: FPDecimal,
+    // relayer fee share paid back to the contract, in quote denom of the market
+    pub rebate: FPDecimal,
+}
+
+// Set as data of the response finishing a swap, so that calling contracts can decode what they received
+#[cw_serde]
+pub struct SwapResponse {
+    pub output: Coin,
+    pub refund: Coin,
+    // fees skimmed from the output, in output denom
+    pub protocol_fee: Coin,
+    pub integrator_fee: Coin,
+    pub swap_results: Vec<SwapResults>,
+}
+
+// Sources of the funds of a buffer denom held by the contract, the buffer owns principal + fees + rounding gains -
+// rounding losses + rebalanced in - rebalanced out of it. Relayer fee rebates passed on to swappers are only counted
+// next to it.
+#[cw_serde]
+#[serde(default)]
+pub struct BufferLedger {
+    // deposited minus withdrawn by the operators
+    pub principal: FPDecimal,
+    // depositor shares of protocol fees and relayer fee rebates kept in the buffer instead of sent out or passed on
+    pub fees: FPDecimal,
+    // leftovers of swap steps that stayed in the contract
+    pub rounding_gains: FPDecimal,
+    // shortfalls of swap steps covered by the buffer
+    pub rounding_losses: FPDecimal,
+    // received from and sent to buffers of other denoms by rebalances, value moved between denoms rather than principal
+    pub rebalanced_in: FPDecimal,
+    pub rebalanced_out: FPDecimal,
+    // relayer fee rebates the contract received and passed on to swappers, not part of the balance
+    pub rebates_passed_on: FPDecimal,
+}
+
+// derived default of FPDecimal is a zero with negative sign, which does not equal FPDecimal::ZERO
+impl Default for BufferLedger {
+    fn default() -> Self {
+        BufferLedger {
+            principal: FPDecimal::ZERO,
+            fees: FPDecimal::ZERO,
+            rounding_gains: FPDecimal::ZERO,
+            rounding_losses: FPDecimal::ZERO,
+            rebalanced_in: FPDecimal::ZERO,
+            rebalanced_out: FPDecimal::ZERO,
+            rebates_passed_on: FPDecimal::ZERO,
+        }
+    }
+}
+
+impl BufferLedger {
+    pub fn balance(&self) -> FPDecimal {
+        self.principal + self.fees + self.rounding_gains - self.rounding_losses + self.rebalanced_in - self.rebalanced_out
+    }
+}
+
+#[cw_serde]
+pub struct BufferLedgerEntry {
+    pub denom: String,
+    pub ledger: BufferLedger,
+}
+
+// Ledgers the profit and loss of buffers is counted from, the start of the contract until the admin resets it
+#[cw_serde]
+pub struct BufferPnlBaseline {
+    pub time: Timestamp,
+    pub ledgers: Vec<BufferLedgerEntry>,
+}
+
+// Price of one whole unit of a buffer denom in whole units of the reporting currency
+#[cw_serde]
+pub enum BufferPriceSource {
+    // pair price of base/quote in the oracle module, which is already in whole units
+    Oracle {
+        oracle_type: OracleType,
+        base: String,
+        quote: String,
+    },
+    // output per chain unit of selling quantity of the denom through its stored route to target_denom, scaled to whole
+    // units with target_decimals of target_denom
+    Route {
+        target_denom: String,
+        quantity: FPDecimal,
+        target_decimals: u8,
+    },
+}
+
+#[cw_serde]
+pub struct BufferPricing {
+    pub denom: String,
+    // a whole unit of the denom is 10^decimals of its chain units
+    pub decimals: u8,
+    pub source: BufferPriceSource,
+}
+
+#[cw_serde]
+pub struct BufferPnl {
+    pub denom: String,
+    pub fees: FPDecimal,
+    pub rounding_gains: FPDecimal,
+    pub rounding_losses: FPDecimal,
+    // rebalanced in - rebalanced out, summed by value over the denoms it is what rebalances lost to slippage
+    pub rebalanced: FPDecimal,
+    // fees + rounding gains - rounding losses + rebalanced, deposits and withdrawals of principal are not profit
+    pub net: FPDecimal,
+    // rebates passed on to swappers, neither profit nor loss of the buffer so not part of net
+    pub rebates_passed_on: FPDecimal,
+    // price of a whole unit, None if the denom has no price source or it failed
+    pub price: Option<FPDecimal>,
+    pub net_value: Option<FPDecimal>,
+}
+
+#[cw_serde]
+pub struct BufferPnlResponse {
+    // None while the baseline was never reset
+    pub baseline_time: Option<Timestamp>,
+    pub denoms: Vec<BufferPnl>,
+    // sum of net values of the denoms that have a price
+    pub total_net_value: FPDecimal,
+}
+
+// Quote denom the contract keeps a buffer of, swaps needing it fail early while its balance is below min_balance
+#[cw_serde]
+pub struct BufferDenom {
+    pub denom: String,
+    pub min_balance: FPDecimal,
+    // protocol fees of the denom top up the buffer up to this balance before the rest goes to the treasury
+    #[serde(default)]
+    pub target_balance: Option<FPDecimal>,
+    // swaps leaving the buffer below this balance emit a buffer_low event
+    #[serde(default)]
+    pub low_balance: Option<FPDecimal>,
+    // below this balance, buys needing more than critical_max_use_bps of the buffer are rejected
+    #[serde(default)]
+    pub critical_balance: Option<FPDecimal>,
+    #[serde(default)]
+    pub critical_max_use_bps: u16,
+}
+
+impl BufferDenom {
+    pub fn is_low(&self, balance: FPDecimal) -> bool {
+        self.low_balance.is_some_and(|low_balance| balance < low_balance)
+    }
+
+    pub fn is_critical(&self, balance: FPDecimal) -> bool {
+        self.critical_balance.is_some_and(|critical_balance| balance < critical_balance)
+    }
+}
+
+#[cw_serde]
+pub struct WithdrawalReservesResponse {
+    pub reserves: Vec<FPCoin>,
+    pub emergency_mode: bool,
+}
+
+#[cw_serde]
+pub struct BufferStatus {
+    pub denom: String,
+    pub min_balance: FPDecimal,
+    pub balance: FPDecimal,
+    // part of the balance owned by the buffer according to its ledger
+    pub ledger_balance: FPDecimal,
+    pub is_underfunded: bool,
+    pub is_low: bool,
+    pub is_critical: bool,
+}
+
+// Limits of the buffer of a denom that swaps can use, None for no limit
+#[cw_serde]
+pub struct BufferCap {
+    pub denom: String,
+    pub per_swap: Option<FPDecimal>,
+    // shared by all swaps of a block
+    pub per_block: Option<FPDecimal>,
+}
+
+#[cw_serde]
+pub struct BufferPosition {
+    pub denom: String,
+    pub shares: FPDecimal,
+    pub total_shares: FPDecimal,
+    // buffer the shares can currently be redeemed for
+    pub amount: FPDecimal,
+}
+
+// configs stored before 1.1.0 hold the fee recipient as a plain address
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredFeeRecipient {
+    Mode(FeeRecipient),
+    Address(Addr),
+}
+
+fn deserialize_fee_recipient<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FeeRecipient, D::Error> {
+    Ok(match StoredFeeRecipient::deserialize(deserializer)? {
+        StoredFeeRecipient::Mode(fee_recipient) => fee_recipient,
+        StoredFeeRecipient::Address(addr) => FeeRecipient::Address(addr),
+    })
 }
 
 #[cw_serde]
 pub struct Config {
-    // if fee_recipient is contract, fee discount is replayed to a sender (will not stay in the contract)
-    pub fee_recipient: Addr,
+    // if fee_recipient is contract, fee discount is replayed to a sender (will not stay in the contract): buys are sized
+    // with the discounted fee and proceeds of sells include the rebate. The contract is stored as its address
+    #[serde(deserialize_with = "deserialize_fee_recipient")]
+    pub fee_recipient: FeeRecipient,
     // who can change routes
     pub admin: Addr,
+    // share of swap output kept by the protocol, in basis points
+    #[serde(default)]
+    pub protocol_fee_bps: u16,
+    // receives protocol fees, must be set if protocol fee is charged
+    #[serde(default)]
+    pub treasury: Option<Addr>,
+    // highest fee integrators can charge on top of swaps, in basis points
+    #[serde(default)]
+    pub max_integrator_fee_bps: u16,
+    // share of each protocol fee deposited to the burn auction subaccount of the exchange, in basis points
+    #[serde(default)]
+    pub burn_share_bps: u16,
+    // share of each protocol fee kept in the buffer for its depositors, in basis points
+    #[serde(default)]
+    pub depositor_fee_share_bps: u16,
+    // share of each relayer fee rebate kept in the buffer for its depositors instead of passed on to the swapper, in basis points
+    #[serde(default)]
+    pub depositor_rebate_share_bps: u16,
+    // most of the buffer of a denom that depositors can withdraw in a single block, in basis points, 0 for no limit
+    #[serde(default)]
+    pub max_buffer_withdrawal_bps: u16,
+    // if set, the treasury share of protocol fees is kept in the contract until TopUpBuffers sweeps it
+    #[serde(default)]
+    pub accrue_protocol_fees: bool,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct OutputFeeRates {
+    pub protocol_fee_bps: u16,
+    pub integrator_fee_bps: u16,
+}
+
+#[cw_serde]
+pub struct FeeTier {
+    // rolling 30-day volume in reference denom needed for this tier
+    pub min_volume: FPDecimal,
+    // share of protocol fee waived, in basis points
+    pub discount_bps: u16,
+}
+
+#[cw_serde]
+pub struct FeeTierSchedule {
+    // denom in which swap volume is valued, swaps not touching it do not count
+    pub reference_denom: String,
+    // ordered by min_volume ascending
+    pub tiers: Vec<FeeTier>,
+}
+
+#[cw_serde]
+pub struct TraderVolumeResponse {
+    pub volume: FPDecimal,
+    pub discount_bps: u16,
+}
+
+#[cw_serde]
+pub struct Integrator {
+    // receives integrator fee in target denom
+    pub address: Addr,
+    // share of swap output paid to the integrator, in basis points
+    pub fee_bps: u16,
 }
 
 #[cw_serde]
@@ -91,9 +389,22 @@ pub struct SwapRoute {
     pub steps: Vec<MarketId>,
     pub source_denom: String,
     pub target_denom: String,
+    // overrides of the config for swaps over this route, e.g. to share relayer rebates of partner markets
+    #[serde(default)]
+    pub fee_recipient: Option<FeeRecipient>,
+    #[serde(default)]
+    pub protocol_fee_bps: Option<u16>,
 }
 
 impl SwapRoute {
+    pub fn effective_fee_recipient(&self, config: &Config) -> FeeRecipient {
+        self.fee_recipient.to_owned().unwrap_or_else(|| config.fee_recipient.to_owned())
+    }
+
+    pub fn effective_protocol_fee_bps(&self, config: &Config) -> u16 {
+        self.protocol_fee_bps.unwrap_or(config.protocol_fee_bps)
+    }
+
     pub fn steps_from(&self, denom: &str) -> Vec<MarketId> {
         if self.source_denom == denom {
             self.steps.clone()
@@ -113,6 +424,35 @@ pub struct SwapStep {
 
 #[cw_serde]
 pub struct SwapEstimationResult {
+    // for exact output quotes this is the input required by execution, padding included
     pub result_quantity: FPDecimal,
     pub expected_fees: Vec<FPCoin>,
+    // part of the required input added on top of the estimate to cover rounding, only set for exact output quotes
+    pub input_padding: Option<FPDecimal>,
+}
+
+#[cw_serde]
+pub struct MaxSwapSizeResponse {
+    // largest input quantity that can be swapped with min output mode
+    pub max_input_quantity: FPDecimal,
+    // largest output quantity that can be requested with exact output mode
+    pub max_output_quantity: FPDecimal,
+}
+
+#[cw_serde]
+pub struct PriceLadderPoint {
+    pub size: FPDecimal,
+    // None if orderbooks or the buffer cannot support swapping this size
+    pub result_quantity: Option<FPDecimal>,
+    // result quantity received per unit of source denom
+    pub effective_rate: Option<FPDecimal>,
+}
+
+#[cw_serde]
+pub struct HypotheticalMarket {
+    // fee rates, tick sizes and relayer fee share are taken from here
+    pub market: SpotMarket,
+    pub atomic_execution_fee_multiplier: FPDecimal,
+    pub buys: Vec<PriceLevel>,
+    pub sells: Vec<PriceLevel>,
 }